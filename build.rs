@@ -0,0 +1,17 @@
+use std::process::Command;
+
+/// Makes the commit being built available to the crate as `IPTOOLED_COMMIT`, or `unknown` outside a git checkout, like a source tarball.
+fn main() {
+	let commit =
+		Command::new("git")
+			.args(&["rev-parse", "--short=12", "HEAD"])
+			.output()
+			.ok()
+			.filter(|output| output.status.success())
+			.and_then(|output| String::from_utf8(output.stdout).ok())
+			.map_or_else(|| "unknown".to_owned(), |commit| commit.trim().to_owned());
+
+	println!("cargo:rustc-env=IPTOOLED_COMMIT={}", commit);
+	println!("cargo:rerun-if-changed=.git/HEAD");
+	println!("cargo:rerun-if-changed=.git/refs/heads");
+}
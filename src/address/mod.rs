@@ -0,0 +1,272 @@
+#[cfg(test)]
+mod tests;
+
+use std::convert::TryFrom;
+use std::error::Error;
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::str::FromStr;
+
+pub const ADDRESS_BYTES: usize = 16;
+pub const ADDRESS_BITS: u8 = 8 * (ADDRESS_BYTES as u8);
+
+pub const IPV4_BYTES: usize = 4;
+
+/// The prefix of IPv4 addresses within the address space: ::ffff:0:0/96.
+const IPV4_MAPPED_PREFIX: [u8; ADDRESS_BYTES - IPV4_BYTES] = [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0xff, 0xff];
+
+/// The number of bits before the IPv4 part of an address.
+pub const IPV4_OFFSET_BITS: u8 = 8 * (ADDRESS_BYTES - IPV4_BYTES) as u8;
+
+#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct Address(pub [u8; ADDRESS_BYTES]);
+
+/// A kind of address range reserved for a special purpose, which reports are almost never really about.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum SpecialRange {
+	/// ::1 and 127.0.0.0/8.
+	Loopback,
+	/// fe80::/10 and 169.254.0.0/16.
+	LinkLocal,
+	/// fc00::/7.
+	UniqueLocal,
+	/// ff00::/8 and 224.0.0.0/4.
+	Multicast,
+	/// 2001:db8::/32, 3fff::/20, 192.0.2.0/24, 198.51.100.0/24, and 203.0.113.0/24.
+	Documentation,
+}
+
+/// Prefixes are ordered by their first address, then by size, so each prefix sorts right before the prefixes it contains, which sort together.
+#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct AddressPrefix {
+	/// The first address with this prefix, i.e. the one ending with `ADDRESS_BITS - bits` zero bits.
+	first: Address,
+
+	/// The number of bits in the prefix.
+	bits: u8,
+}
+
+impl Address {
+	pub fn from_ipv4(ipv4: [u8; IPV4_BYTES]) -> Self {
+		let mut result = [0; ADDRESS_BYTES];
+		result[..ADDRESS_BYTES - IPV4_BYTES].copy_from_slice(&IPV4_MAPPED_PREFIX);
+		result[ADDRESS_BYTES - IPV4_BYTES..].copy_from_slice(&ipv4);
+		Self(result)
+	}
+
+	pub fn is_ipv4(&self) -> bool {
+		self.0[..ADDRESS_BYTES - IPV4_BYTES] == IPV4_MAPPED_PREFIX
+	}
+
+	/// Finds the special-purpose range the address is in, if any.
+	pub fn special_range(&self) -> Option<SpecialRange> {
+		let bytes = &self.0;
+
+		if self.is_ipv4() {
+			return match bytes[ADDRESS_BYTES - IPV4_BYTES..] {
+				[127, ..] => Some(SpecialRange::Loopback),
+				[169, 254, ..] => Some(SpecialRange::LinkLocal),
+				[224..=239, ..] => Some(SpecialRange::Multicast),
+				[192, 0, 2, _] | [198, 51, 100, _] | [203, 0, 113, _] => Some(SpecialRange::Documentation),
+				_ => None,
+			};
+		}
+
+		match bytes[..] {
+			[0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1] => Some(SpecialRange::Loopback),
+			[0xfe, 0x80..=0xbf, ..] => Some(SpecialRange::LinkLocal),
+			[0xfc..=0xfd, ..] => Some(SpecialRange::UniqueLocal),
+			[0xff, ..] => Some(SpecialRange::Multicast),
+			[0x20, 0x01, 0x0d, 0xb8, ..] | [0x3f, 0xff, 0x00..=0x0f, ..] => Some(SpecialRange::Documentation),
+			_ => None,
+		}
+	}
+
+	/// Gets the IPv4 address embedded in a 6to4 (2002::/16) or Teredo (2001::/32) address, if it is one. For Teredo, that’s the client’s public address, which is stored inverted.
+	pub fn tunnelled_ipv4(&self) -> Option<[u8; IPV4_BYTES]> {
+		match self.0 {
+			[0x20, 0x02, a, b, c, d, ..] => Some([a, b, c, d]),
+			[0x20, 0x01, 0x00, 0x00, .., a, b, c, d] => Some([!a, !b, !c, !d]),
+			_ => None,
+		}
+	}
+
+	/// Replaces a 6to4 or Teredo address with the IPv4 address it tunnels, so it shares that address’s reputation.
+	pub fn unwrap_tunnel(self) -> Self {
+		match self.tunnelled_ipv4() {
+			Some(ipv4) => Self::from_ipv4(ipv4),
+			None => self,
+		}
+	}
+
+	/// Counts the leading bits two addresses have in common.
+	pub fn common_prefix_len(&self, other: &Self) -> u8 {
+		(u128::from_be_bytes(self.0) ^ u128::from_be_bytes(other.0)).leading_zeros() as u8
+	}
+
+	pub fn prefix(&self, bits: u8) -> AddressPrefix {
+		self.prefix_checked(bits)
+			.expect("tried to take a prefix longer than an Address")
+	}
+
+	/// Gets the prefix of the address with a number of bits, or `None` if that’s longer than an address.
+	pub fn prefix_checked(&self, bits: u8) -> Option<AddressPrefix> {
+		if bits > ADDRESS_BITS {
+			return None;
+		}
+
+		let mask = u128::max_value().checked_shl(u32::from(ADDRESS_BITS - bits)).unwrap_or(0);
+
+		Some(AddressPrefix {
+			first: Address((u128::from_be_bytes(self.0) & mask).to_be_bytes()),
+			bits,
+		})
+	}
+}
+
+/// An error from parsing text that isn’t an address or prefix.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct ParseError;
+
+impl fmt::Display for ParseError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		f.write_str("invalid address or prefix")
+	}
+}
+
+impl Error for ParseError {}
+
+impl From<IpAddr> for Address {
+	/// Converts IPv4 addresses to IPv4-mapped addresses.
+	fn from(address: IpAddr) -> Self {
+		match address {
+			IpAddr::V4(ipv4) => Self::from_ipv4(ipv4.octets()),
+			IpAddr::V6(ipv6) => Self(ipv6.octets()),
+		}
+	}
+}
+
+impl FromStr for Address {
+	type Err = ParseError;
+
+	/// Parses an IPv6 or IPv4 address in the standard notation.
+	fn from_str(text: &str) -> Result<Self, ParseError> {
+		text.parse::<IpAddr>().map(Self::from).map_err(|_| ParseError)
+	}
+}
+
+impl fmt::Display for Address {
+	/// Formats IPv4 addresses as such, other 16-byte addresses as IPv6 addresses, and other lengths as hex.
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		if self.is_ipv4() {
+			return write!(f, "{}", Ipv4Addr::from(<[u8; IPV4_BYTES]>::try_from(&self.0[ADDRESS_BYTES - IPV4_BYTES..]).unwrap()));
+		}
+
+		match <[u8; 16]>::try_from(&self.0[..]) {
+			Ok(bytes) => write!(f, "{}", Ipv6Addr::from(bytes)),
+			Err(_) => self.0.iter().try_for_each(|byte| write!(f, "{:02x}", byte)),
+		}
+	}
+}
+
+impl AddressPrefix {
+	/// Parses a prefix in CIDR notation, like `2001:db8::/32` or `192.0.2.0/24`, with bits past the prefix ignored. An address on its own is a prefix of all of its bits.
+	pub fn parse(text: &str) -> Option<Self> {
+		let (address, bits) =
+			match text.find('/') {
+				Some(index) => (&text[..index], Some(text[index + 1..].parse::<u8>().ok()?)),
+				None => (text, None),
+			};
+
+		match address.parse().ok()? {
+			IpAddr::V4(ipv4) => {
+				let bits = bits.unwrap_or(8 * IPV4_BYTES as u8);
+
+				if bits > 8 * IPV4_BYTES as u8 {
+					return None;
+				}
+
+				Some(Address::from_ipv4(ipv4.octets()).prefix(IPV4_OFFSET_BITS + bits))
+			}
+			IpAddr::V6(ipv6) => Address(ipv6.octets()).prefix_checked(bits.unwrap_or(ADDRESS_BITS)),
+		}
+	}
+
+	pub fn first(&self) -> &Address {
+		&self.first
+	}
+
+	pub fn bits(&self) -> u8 {
+		self.bits
+	}
+
+	/// Shortens the prefix in place by one bit. Panics if it’s empty.
+	pub fn shorten(&mut self) {
+		self.bits = self.bits.checked_sub(1)
+			.expect("tried to shorten an empty AddressPrefix");
+
+		let new_byte = self.bits / 8;
+		let new_bit = self.bits % 8;
+		self.first.0[usize::from(new_byte)] &= mask(new_bit);
+	}
+
+	/// Shortens the prefix in place to a number of bits at once. Panics if that’s longer than the prefix.
+	pub fn shorten_to(&mut self, bits: u8) {
+		assert!(bits <= self.bits, "tried to lengthen an AddressPrefix");
+		*self = self.first.prefix(bits);
+	}
+
+	/// Gets the prefix one bit shorter, which contains this one, or `None` if it’s empty.
+	pub fn supernet(&self) -> Option<Self> {
+		if self.bits == 0 {
+			return None;
+		}
+
+		let mut result = self.clone();
+		result.shorten();
+		Some(result)
+	}
+
+	/// Gets the longest prefix containing both prefixes.
+	pub fn common_prefix(a: &Self, b: &Self) -> Self {
+		a.first.prefix(a.first.common_prefix_len(&b.first).min(a.bits).min(b.bits))
+	}
+
+	pub fn contains(&self, other: &Self) -> bool {
+		self.bits <= other.bits && self.is_prefix_of(&other.first)
+	}
+
+	pub fn is_prefix_of(&self, address: &Address) -> bool {
+		let Self { first, bits } = self;
+		let wholes = usize::from(bits / 8);
+		let remainder = bits % 8;
+
+		first.0[..wholes] == address.0[..wholes]
+			&& (remainder == 0 || (first.0[wholes] ^ address.0[wholes]) & mask(remainder) == 0)
+	}
+}
+
+impl fmt::Display for AddressPrefix {
+	/// Formats the prefix in CIDR notation, relative to the IPv4 address for prefixes of IPv4 addresses.
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		if self.first.is_ipv4() {
+			write!(f, "{}/{}", self.first, self.bits - IPV4_OFFSET_BITS)
+		} else {
+			write!(f, "{}/{}", self.first, self.bits)
+		}
+	}
+}
+
+impl FromStr for AddressPrefix {
+	type Err = ParseError;
+
+	/// Parses a prefix like `AddressPrefix::parse`.
+	fn from_str(text: &str) -> Result<Self, ParseError> {
+		Self::parse(text).ok_or(ParseError)
+	}
+}
+
+/// A byte with the first n bits set.
+const fn mask(n: u8) -> u8 {
+	!(0xff_u8 >> n)
+}
@@ -0,0 +1,136 @@
+use quickcheck::{Arbitrary, Gen};
+use rand::Rng;
+use rand::seq::SliceRandom;
+
+use std::net::Ipv4Addr;
+
+use super::super::random;
+use super::{ADDRESS_BITS, ADDRESS_BYTES, Address, AddressPrefix, SpecialRange};
+
+/// Addresses clustered like real ones half the time, and otherwise made of only a few different bytes, so that random ones often share long prefixes in both cases.
+impl Arbitrary for Address {
+	fn arbitrary<G: Gen>(g: &mut G) -> Self {
+		if g.gen() {
+			return random::address(g);
+		}
+
+		let mut result = [0; ADDRESS_BYTES];
+
+		for byte in &mut result {
+			*byte = *[0x00, 0x01, 0x80, 0xff].choose(g).unwrap();
+		}
+
+		Self(result)
+	}
+}
+
+impl Arbitrary for AddressPrefix {
+	fn arbitrary<G: Gen>(g: &mut G) -> Self {
+		let address: Address = Arbitrary::arbitrary(g);
+		address.prefix(g.gen_range(0, ADDRESS_BITS + 1))
+	}
+}
+
+#[quickcheck]
+fn supernet_contains_prefix(prefix: AddressPrefix) -> bool {
+	match prefix.supernet() {
+		Some(supernet) => supernet.bits() + 1 == prefix.bits() && supernet.contains(&prefix) && !prefix.contains(&supernet),
+		None => prefix.bits() == 0,
+	}
+}
+
+#[quickcheck]
+fn common_prefix_is_longest(a: AddressPrefix, b: AddressPrefix) -> bool {
+	let common = AddressPrefix::common_prefix(&a, &b);
+
+	// No prefix a bit longer contains both.
+	let longest =
+		common.bits() == a.bits().min(b.bits())
+			|| !a.first().prefix(common.bits() + 1).contains(&b);
+
+	common.contains(&a)
+		&& common.contains(&b)
+		&& longest
+		&& common == AddressPrefix::common_prefix(&b, &a)
+}
+
+/// Checks that prefixes sort before the prefixes they contain, and that the prefixes a prefix contains sort together, which lookups in ordered maps rely on.
+#[quickcheck]
+fn contained_prefixes_sort_together(a: AddressPrefix, b: AddressPrefix, c: AddressPrefix) -> bool {
+	let mut sorted = [a, b, c];
+	sorted.sort();
+	let [first, middle, last] = sorted;
+
+	(!first.contains(&last) || first.contains(&middle))
+		&& (!last.contains(&first) || last == first)
+}
+
+/// Checks IPv4 special-purpose ranges against the standard library’s.
+#[quickcheck]
+fn ipv4_special_ranges_match_std(ipv4: u32) -> bool {
+	let std_address = Ipv4Addr::from(ipv4);
+
+	let expected =
+		if std_address.is_loopback() {
+			Some(SpecialRange::Loopback)
+		} else if std_address.is_link_local() {
+			Some(SpecialRange::LinkLocal)
+		} else if std_address.is_multicast() {
+			Some(SpecialRange::Multicast)
+		} else if std_address.is_documentation() {
+			Some(SpecialRange::Documentation)
+		} else {
+			None
+		};
+
+	Address::from_ipv4(ipv4.to_be_bytes()).special_range() == expected
+}
+
+/// Checks that the IPv4 address a 6to4 or Teredo address tunnels is the one embedded in it, and that other addresses are left alone.
+#[quickcheck]
+fn tunnels_unwrap_to_ipv4(ipv4: u32, address: Address) -> bool {
+	let ipv4 = ipv4.to_be_bytes();
+
+	let mut six_to_four = address.clone();
+	six_to_four.0[..2].copy_from_slice(&[0x20, 0x02]);
+	six_to_four.0[2..6].copy_from_slice(&ipv4);
+
+	let mut teredo = address.clone();
+	teredo.0[..4].copy_from_slice(&[0x20, 0x01, 0x00, 0x00]);
+	teredo.0[12..].copy_from_slice(&(!u32::from_be_bytes(ipv4)).to_be_bytes());
+
+	six_to_four.unwrap_tunnel() == Address::from_ipv4(ipv4)
+		&& teredo.unwrap_tunnel() == Address::from_ipv4(ipv4)
+		&& (address.tunnelled_ipv4().is_some() || address.clone().unwrap_tunnel() == address)
+}
+
+#[quickcheck]
+fn common_prefix_len_is_longest(a: Address, b: Address) -> bool {
+	let bits = a.common_prefix_len(&b);
+
+	a.prefix(bits) == b.prefix(bits)
+		&& (bits == ADDRESS_BITS || a.prefix(bits + 1) != b.prefix(bits + 1))
+}
+
+#[quickcheck]
+fn shorten_to_matches_shortening_by_bits(prefix: AddressPrefix, bits: u8) -> bool {
+	let bits = bits % (prefix.bits() + 1);
+
+	let mut by_bits = prefix.clone();
+	while by_bits.bits() > bits {
+		by_bits.shorten();
+	}
+
+	let mut at_once = prefix.clone();
+	at_once.shorten_to(bits);
+
+	at_once == by_bits && at_once == prefix.first().prefix(bits)
+}
+
+#[quickcheck]
+fn prefix_checked_rejects_only_long_prefixes(address: Address, bits: u8) -> bool {
+	match address.prefix_checked(bits) {
+		Some(prefix) => bits <= ADDRESS_BITS && prefix.bits() == bits && prefix.is_prefix_of(&address),
+		None => bits > ADDRESS_BITS,
+	}
+}
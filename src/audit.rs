@@ -0,0 +1,36 @@
+use std::fs::{File, OpenOptions};
+use std::io::{self, LineWriter, Write};
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use super::tree::{Operation, Retraction, TreeOperation};
+
+/// An append-only file with a line for each accepted report and retraction and the local user that made it, for abuse investigations. Lines are written as they happen, so none are lost if the process doesn’t exit cleanly.
+pub struct AuditLog {
+	file: LineWriter<File>,
+}
+
+impl AuditLog {
+	pub fn open(path: &Path) -> io::Result<Self> {
+		let file = OpenOptions::new()
+			.append(true)
+			.create(true)
+			.open(path)?;
+
+		Ok(Self {
+			file: LineWriter::new(file),
+		})
+	}
+
+	/// Appends a line of tab-separated fields: the Unix time, the user id of the client’s process or `-` if it isn’t known, what happened, the address, and the user.
+	pub fn record(&mut self, uid: Option<u32>, operation: &TreeOperation) -> io::Result<()> {
+		let now = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |since_epoch| since_epoch.as_secs());
+		let uid = uid.map_or_else(|| "-".to_owned(), |uid| uid.to_string());
+
+		match operation {
+			TreeOperation::Perform(Operation(type_, address, user)) => writeln!(self.file, "{}\t{}\t{}\t{}\t{}", now, uid, type_.name(), address, user),
+			TreeOperation::Retract(Retraction::Report(type_, address, user)) => writeln!(self.file, "{}\t{}\tretract-{}\t{}\t{}", now, uid, type_.name(), address, user),
+			TreeOperation::Retract(Retraction::User(user)) => writeln!(self.file, "{}\t{}\tforget-user\t-\t{}", now, uid, user),
+		}
+	}
+}
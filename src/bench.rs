@@ -0,0 +1,44 @@
+use std::time::{Duration, Instant};
+
+use super::cli::BenchOptions;
+use super::random::{self, RandomSource, XorShift};
+use super::time_list::CoarseSystemTime;
+use super::tree::{Operation, OperationType, SpamTree, TreeSettings, USER_BYTES, User};
+
+fn user(random: &mut XorShift) -> User {
+	let mut bytes = [0; USER_BYTES];
+	random.fill(&mut bytes);
+	User::from_bytes(bytes)
+}
+
+fn report(name: &str, count: u32, elapsed: Duration) {
+	let per_second = f64::from(count) / elapsed.as_secs_f64();
+	println!("{}: {} in {:.3} s ({:.0}/s)", name, count, elapsed.as_secs_f64(), per_second);
+}
+
+/// Times random operations and then random queries against an in-memory tree.
+pub fn bench(options: &BenchOptions) {
+	let mut random = XorShift(0x2545_f491_4f6c_dd1d);
+	let mut tree = SpamTree::new(TreeSettings::DEFAULT);
+	let now = CoarseSystemTime::now();
+
+	let start = Instant::now();
+
+	for _ in 0..options.operations {
+		let type_ = if random.below(4) == 0 { OperationType::Spam } else { OperationType::Trust };
+		tree.perform(Operation(type_, random::address(&mut random), user(&mut random)), now);
+	}
+
+	report("operations", options.operations, start.elapsed());
+
+	let size = tree.size();
+	println!("tree: {} prefixes, about {:.1} MiB", size.prefixes, size.estimated_bytes as f64 / (1024.0 * 1024.0));
+
+	let start = Instant::now();
+
+	for _ in 0..options.queries {
+		tree.query(&random::address(&mut random), now);
+	}
+
+	report("queries", options.queries, start.elapsed());
+}
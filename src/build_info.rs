@@ -0,0 +1,39 @@
+use super::protocol::PROTOCOL_VERSION;
+
+/// The crate’s version.
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// The commit the daemon was built from, or `unknown`.
+pub const COMMIT: &str = env!("IPTOOLED_COMMIT");
+
+/// Lists the optional features the daemon was built with.
+pub fn features() -> Vec<&'static str> {
+	let mut features = Vec::new();
+
+	if cfg!(feature = "bench") {
+		features.push("bench");
+	}
+
+	if cfg!(feature = "io-uring") {
+		features.push("io-uring");
+	}
+
+	if cfg!(feature = "sandbox") {
+		features.push("sandbox");
+	}
+
+	features
+}
+
+/// Describes the build for `--version`, after the name.
+pub fn long_version() -> String {
+	let features = features();
+
+	format!(
+		"{}\ncommit: {}\nprotocol: {}\nfeatures: {}",
+		VERSION,
+		COMMIT,
+		PROTOCOL_VERSION,
+		if features.is_empty() { "none".to_owned() } else { features.join(", ") },
+	)
+}
@@ -0,0 +1,937 @@
+use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
+use clap::{Error as ClapError, ErrorKind as ClapErrorKind};
+use std::convert::TryFrom;
+use std::env;
+use std::ffi::OsString;
+use std::io;
+use std::net::{SocketAddr, ToSocketAddrs};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::sync::Arc;
+use std::time::Duration;
+
+use super::address::{ADDRESS_BITS, Address, IPV4_BYTES};
+use super::build_info;
+use super::config;
+use super::logging;
+use super::mmdb::Database;
+use super::prefix_list::PrefixList;
+use super::salt::UserSalt;
+use super::otlp::Tracer;
+use super::statsd::Statsd;
+use super::time_list::{CoarseDuration, Hours, Minutes, TimeUnit};
+use super::tree::{Prior, TreeSettings};
+
+const PREFIX_MINIMUM_HELP: &str = "The shortest prefix that results can come from, i.e. how far reputation generalizes across networks [default: 12]";
+
+const IPV4_PREFIX_MINIMUM_HELP: &str = "The shortest prefix that results for IPv4 addresses can come from, within the IPv4 address [default: 24]";
+
+const ENTRIES_PER_USER_HELP: &str = "How many entries a user can have within the user expiry, counting every type without its own limit [default: 5]";
+
+const USER_EXPIRY_HELP: &str = "How long an entry counts toward its user’s entry limit [default: 720]";
+
+const ADDRESS_EXPIRY_HELP: &str = "How long an entry counts at all [default: 17520]";
+
+#[cfg(any(test, feature = "bench"))]
+const DEFAULT_BENCH_OPERATIONS: &str = "100000";
+
+const DEFAULT_REPLAY_INTERVAL: &str = "24";
+
+const DEFAULT_STATSD_INTERVAL: &str = "10";
+
+const DEFAULT_SLOW_REQUEST_SAMPLE: &str = "1";
+
+const DEFAULT_DEGRADED_AFTER: &str = "3";
+
+pub struct ServeOptions {
+	pub persist_path: PathBuf,
+	/// `None` when serving a single client over stdin and stdout.
+	pub socket_path: Option<PathBuf>,
+	pub idle_timeout: Option<Duration>,
+	/// How often to rebuild the snapshot that queries are served from, if they’re served from one.
+	pub snapshot_interval: Option<Duration>,
+	pub prior: Prior,
+	pub tree_settings: TreeSettings,
+	/// A salt to hash users with before storing them, if any.
+	pub user_salt: Option<UserSalt>,
+	/// What to do with reports for addresses in special-purpose ranges, like loopback and documentation addresses.
+	pub special_ranges: SpecialRangePolicy,
+	/// Whether to treat 6to4 and Teredo addresses as the IPv4 addresses they tunnel.
+	pub unwrap_tunnels: bool,
+	/// The StatsD agent to send metrics to, if any, which is connected to while parsing arguments so it’s done before the sandbox is applied.
+	pub statsd: Option<Rc<Statsd>>,
+	pub statsd_interval: Duration,
+	/// Records spans for requests and sends them to an OpenTelemetry collector, if there is one.
+	pub tracer: Option<Rc<Tracer>>,
+	/// How long a request can take before it’s logged as slow, if slow requests are logged.
+	pub slow_request_threshold: Option<Duration>,
+	/// Logs one in this many slow requests, so a burst of them doesn’t flood the log.
+	pub slow_request_sample: u32,
+	/// The file to append a line to for each accepted report and retraction, if any.
+	pub audit_path: Option<PathBuf>,
+	/// How many writes to the operation log have to fail in a row for the daemon to be degraded.
+	pub degraded_after: u32,
+	/// Whether to refuse reports and retractions while degraded, instead of accepting ones that might not be saved.
+	pub refuse_writes_when_degraded: bool,
+	/// How long the daemon can stay degraded before exiting, if it exits.
+	pub degraded_exit_after: Option<Duration>,
+	/// The number of requests per second each user id can make and how many it can make at once, if limited. Doesn’t apply to the admin socket.
+	pub rate_limit: Option<(u32, u32)>,
+	/// The address to listen on for followers to stream operations to, if any, as given.
+	pub replication_address: Option<String>,
+	/// The replication address of the primary to follow, if this is a read-only replica of it.
+	pub primary_address: Option<SocketAddr>,
+	/// Which log messages to write, as a filter like `warn`.
+	pub log_filter: String,
+	pub log_format: LogFormat,
+	pub log_target: LogTarget,
+	/// Persistence directories of other deployments whose operations are merged in when starting.
+	pub import_paths: Vec<PathBuf>,
+	#[cfg(unix)]
+	pub daemonize: bool,
+	#[cfg(unix)]
+	pub pid_path: Option<PathBuf>,
+	#[cfg(unix)]
+	pub log_path: Option<PathBuf>,
+	#[cfg(unix)]
+	pub chroot_path: Option<PathBuf>,
+	#[cfg(unix)]
+	pub handoff_path: Option<PathBuf>,
+	/// The socket that only the daemon’s user can connect to, which stats, overrides, forgetting users, shutting down, and flushing are only accepted on, if there is one.
+	#[cfg(unix)]
+	pub admin_socket_path: Option<PathBuf>,
+	/// The file to append diagnostic dumps to on SIGUSR1, instead of stderr.
+	#[cfg(unix)]
+	pub dump_path: Option<PathBuf>,
+	#[cfg(all(target_os = "linux", feature = "sandbox"))]
+	pub sandbox: bool,
+}
+
+#[cfg(any(test, feature = "bench"))]
+pub struct BenchOptions {
+	pub operations: u32,
+	pub queries: u32,
+}
+
+impl ServeOptions {
+	/// Lists the effective value of each option, after reading the config file and filling in defaults, by the option’s name.
+	pub fn summary(&self) -> Vec<(&'static str, String)> {
+		fn or_off(value: Option<impl ToString>) -> String {
+			value.map_or_else(|| "off".to_owned(), |value| value.to_string())
+		}
+
+		fn path(path: &Option<PathBuf>) -> String {
+			or_off(path.as_ref().map(|path| path.display()))
+		}
+
+		let settings = &self.tree_settings;
+		let hours = |duration: CoarseDuration| format!("{}h", duration.units());
+		let seconds = |duration: Duration| format!("{}s", duration.as_secs());
+		let loaded = |present: bool| if present { "on" } else { "off" }.to_owned();
+
+		let result = vec![
+			("persist-path", self.persist_path.display().to_string()),
+			("socket-path", self.socket_path.as_ref().map_or_else(|| "stdio".to_owned(), |path| path.display().to_string())),
+			("idle-timeout", or_off(self.idle_timeout.map(seconds))),
+			("snapshot-interval", or_off(self.snapshot_interval.map(seconds))),
+			("prefix-minimum", settings.prefix_bits_minimum.to_string()),
+			("ipv4-prefix-minimum", settings.ipv4_prefix_bits_minimum.to_string()),
+			("truncate-to", or_off(settings.truncate_bits)),
+			("ipv4-truncate-to", or_off(settings.ipv4_truncate_bits)),
+			("special-ranges", match self.special_ranges {
+				SpecialRangePolicy::Accept => "accept",
+				SpecialRangePolicy::Ignore => "ignore",
+				SpecialRangePolicy::Reject => "reject",
+			}.to_owned()),
+			("unwrap-tunnels", self.unwrap_tunnels.to_string()),
+			("entries-per-user", settings.entries_per_user.to_string()),
+			("trust-entries-per-user", or_off(settings.trust_entries_per_user)),
+			("spam-entries-per-user", or_off(settings.spam_entries_per_user)),
+			("entries-per-user-prefix", or_off(settings.entries_per_user_prefix)),
+			("user-expiry", hours(settings.user_expiry)),
+			("address-expiry", hours(settings.address_expiry)),
+			("trust-address-expiry", or_off(settings.trust_address_expiry.map(hours))),
+			("decay-half-life", or_off(settings.decay_half_life.map(hours))),
+			("velocity-window-minutes", or_off(settings.velocity_window.map(CoarseDuration::units))),
+			("spam-prior", self.prior.spam.to_string()),
+			("trusted-prior", self.prior.trusted.to_string()),
+			("allocation-boundaries", settings.allocation_boundaries_only.to_string()),
+			("distinct-users", settings.distinct_users.to_string()),
+			("min-distinct-users", or_off(settings.min_distinct_users)),
+			("spam-quarantine", or_off(settings.spam_quarantine)),
+			("split-threshold", or_off(settings.split_threshold)),
+			("cap-per-64", or_off(settings.network_cap)),
+			("empty-cache-ttl", or_off(settings.empty_cache_ttl.map(seconds))),
+			("max-prefixes", or_off(settings.max_prefixes)),
+			("allowlist", format!("{} prefixes", settings.allowlist.prefix_count())),
+			("denylist", format!("{} prefixes", settings.denylist.prefix_count())),
+			("asn-database", loaded(settings.asn_database.is_some())),
+			("country-database", loaded(settings.country_database.is_some())),
+			("user-salt", loaded(self.user_salt.is_some())),
+			("import", or_off(Some(self.import_paths.iter().map(|path| path.display().to_string()).collect::<Vec<_>>().join(", ")).filter(|paths| !paths.is_empty()))),
+			("statsd", loaded(self.statsd.is_some())),
+			("statsd-interval", seconds(self.statsd_interval)),
+			("otlp-endpoint", loaded(self.tracer.is_some())),
+			("slow-request-threshold", or_off(self.slow_request_threshold.map(|threshold| format!("{}ms", threshold.as_millis())))),
+			("slow-request-sample", self.slow_request_sample.to_string()),
+			("audit-log", path(&self.audit_path)),
+			("degraded-after", self.degraded_after.to_string()),
+			("refuse-writes-when-degraded", self.refuse_writes_when_degraded.to_string()),
+			("degraded-exit-after", or_off(self.degraded_exit_after.map(seconds))),
+			("rate-limit", or_off(self.rate_limit.map(|(rate, _)| format!("{}/s", rate)))),
+			("rate-limit-burst", or_off(self.rate_limit.map(|(_, burst)| burst))),
+			("replication-listen", or_off(self.replication_address.as_ref())),
+			("replica", or_off(self.primary_address)),
+			("log-level", self.log_filter.clone()),
+			("log-format", match self.log_format {
+				LogFormat::Text => "text",
+				LogFormat::Json => "json",
+			}.to_owned()),
+			("log-target", match self.log_target {
+				LogTarget::Stderr => "stderr",
+				#[cfg(unix)]
+				LogTarget::Syslog => "syslog",
+				#[cfg(unix)]
+				LogTarget::Journald => "journald",
+			}.to_owned()),
+		];
+
+		#[cfg(unix)]
+		let result = [result, vec![
+			("daemonize", self.daemonize.to_string()),
+			("pid-file", path(&self.pid_path)),
+			("log-file", path(&self.log_path)),
+			("chroot", path(&self.chroot_path)),
+			("handoff", path(&self.handoff_path)),
+			("admin-socket", path(&self.admin_socket_path)),
+			("dump-file", path(&self.dump_path)),
+		]].concat();
+
+		#[cfg(all(target_os = "linux", feature = "sandbox"))]
+		let result = [result, vec![("sandbox", self.sandbox.to_string())]].concat();
+
+		result
+	}
+}
+
+/// What to do with reports for addresses in special-purpose ranges.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum SpecialRangePolicy {
+	Accept,
+	/// Drop them, but respond as if they were accepted.
+	Ignore,
+	/// Drop them and respond with a failure.
+	Reject,
+}
+
+/// How log messages are written.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum LogFormat {
+	Text,
+	/// One JSON object per line, for log collectors.
+	Json,
+}
+
+/// Where log messages are written.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum LogTarget {
+	/// Stderr, which is the log file if there is one.
+	Stderr,
+	/// The syslog daemon, at `/dev/log`.
+	#[cfg(unix)]
+	Syslog,
+	/// systemd’s journal, with each message’s fields kept separate.
+	#[cfg(unix)]
+	Journald,
+}
+
+pub struct ReplayOptions {
+	pub persist_path: PathBuf,
+	/// How much log time passes between printing the addresses’ results.
+	pub interval: CoarseDuration,
+	pub addresses: Vec<Address>,
+}
+
+pub enum Command {
+	Serve(ServeOptions),
+	Dump(PathBuf),
+	Verify(PathBuf),
+	Diff(PathBuf, PathBuf),
+	Replay(ReplayOptions),
+	#[cfg(any(test, feature = "bench"))]
+	Bench(BenchOptions),
+}
+
+/// Checks that a value is a whole number that fits in `T`, the type it’s parsed as.
+pub fn is_number<T: TryFrom<u64>>(value: String) -> Result<(), String> {
+	match value.parse::<u64>() {
+		Ok(number) if T::try_from(number).is_ok() => Ok(()),
+		Ok(_) => Err("is too large".to_owned()),
+		Err(_) => Err("must be a whole number".to_owned()),
+	}
+}
+
+/// Makes a validator for a prefix size of up to `maximum` bits.
+fn is_prefix_bits(maximum: u8) -> impl Fn(String) -> Result<(), String> {
+	move |value| match value.parse::<u8>() {
+		Ok(bits) if bits <= maximum => Ok(()),
+		_ => Err(format!("must be a number of bits from 0 to {}", maximum)),
+	}
+}
+
+fn is_address(value: String) -> Result<(), String> {
+	value.parse::<Address>()
+		.map(|_| ())
+		.map_err(|_| "must be an IPv4 or IPv6 address".to_owned())
+}
+
+fn is_log_filter(value: String) -> Result<(), String> {
+	if logging::is_valid_filter(&value) {
+		Ok(())
+	} else {
+		Err("must be a level, like warn, or a list of levels for modules, like info,iptooled::handoff=debug".to_owned())
+	}
+}
+
+/// The units durations can be given in, by suffix, in seconds. A year is 365 days.
+const DURATION_SUFFIXES: [(char, u64); 5] = [('m', 60), ('h', 3600), ('d', 24 * 3600), ('w', 7 * 24 * 3600), ('y', 365 * 24 * 3600)];
+
+/// Parses a duration with a unit, like `18h`, `30d`, or `2y`, or a number on its own in the units `bare` is the length of in seconds, as a whole number of `U` from 1 to 2^16 − 1.
+fn parse_duration<U: TimeUnit>(text: &str, bare: u64) -> Option<CoarseDuration<U>> {
+	let (number, unit) =
+		match DURATION_SUFFIXES.iter().find(|&&(suffix, _)| text.ends_with(suffix)) {
+			Some(&(_, unit)) => (&text[..text.len() - 1], unit),
+			None => (text, bare),
+		};
+
+	let seconds = number.parse::<u64>().ok()?.checked_mul(unit)?;
+
+	CoarseDuration::from_duration(Duration::from_secs(seconds))
+		.filter(|duration| duration.units() != 0 && duration.to_duration().as_secs() == seconds)
+}
+
+fn is_hours(value: String) -> Result<(), String> {
+	match parse_duration::<Hours>(&value, Hours::SECONDS) {
+		Some(_) => Ok(()),
+		None => Err(format!("must be a whole number of hours from 1 to {}, or a duration like 18h, 30d, 2w, or 2y", u16::max_value())),
+	}
+}
+
+fn is_window_hours(value: String) -> Result<(), String> {
+	// The window is kept in minutes.
+	match parse_duration::<Minutes>(&value, Hours::SECONDS) {
+		Some(_) => Ok(()),
+		None => Err(format!("must be a whole number of hours from 1 to {}, or a duration like 90m or 7d of up to {} minutes", u16::max_value() / 60, u16::max_value())),
+	}
+}
+
+fn is_minutes(value: String) -> Result<(), String> {
+	match parse_duration::<Minutes>(&value, Minutes::SECONDS) {
+		Some(_) => Ok(()),
+		None => Err(format!("must be a whole number of minutes from 1 to {}, or a duration like 6h or 7d", u16::max_value())),
+	}
+}
+
+fn is_seconds(value: String) -> Result<(), String> {
+	match value.parse::<u64>() {
+		Ok(seconds) if seconds != 0 => Ok(()),
+		_ => Err("must be a whole number of seconds, at least 1".to_owned()),
+	}
+}
+
+pub fn is_sample(value: String) -> Result<(), String> {
+	match value.parse::<u32>() {
+		Ok(sample) if sample != 0 => Ok(()),
+		_ => Err(format!("must be a whole number from 1 to {}", u32::max_value())),
+	}
+}
+
+pub fn is_entry_count(value: String) -> Result<(), String> {
+	value.parse::<u16>()
+		.map(|_| ())
+		.map_err(|_| format!("must be a whole number up to {}", u16::max_value()))
+}
+
+pub fn is_weight(value: String) -> Result<(), String> {
+	match value.parse::<f64>() {
+		Ok(weight) if weight > 0.0 && weight.is_finite() => Ok(()),
+		_ => Err("must be a positive number".to_owned()),
+	}
+}
+
+fn persist_path_arg() -> Arg<'static, 'static> {
+	Arg::with_name("persist-path")
+		.required(true)
+		.help("The directory containing the operation log")
+}
+
+fn app() -> App<'static, 'static> {
+	let socket_path =
+		Arg::with_name("socket-path")
+			.help("Where to create the socket, or the pipe’s name (like \\\\.\\pipe\\iptooled) on Windows");
+
+	#[cfg(unix)]
+	let socket_path = socket_path.required_unless("stdio");
+
+	#[cfg(windows)]
+	let socket_path = socket_path.required(true);
+
+	let serve =
+		SubCommand::with_name("serve")
+			// Lets the command line override the configuration file.
+			.setting(AppSettings::AllArgsOverrideSelf)
+			.about("Serves requests on a Unix socket, or a named pipe on Windows")
+			.arg(persist_path_arg())
+			.arg(socket_path)
+			.arg(Arg::with_name("idle-timeout")
+				.long("idle-timeout")
+				.value_name("SECONDS")
+				.validator(is_number::<u64>)
+				.help("Disconnects clients that go this long without sending a request; 0 to keep them, like leaving it out"))
+			.arg(Arg::with_name("snapshot-interval")
+				.long("snapshot-interval")
+				.value_name("SECONDS")
+				.validator(is_seconds)
+				.help("Serves queries from a copy of the counts rebuilt this often, so they don’t wait for reports"))
+			.arg(Arg::with_name("prefix-minimum")
+				.long("prefix-minimum")
+				.value_name("BITS")
+				.validator(is_prefix_bits(ADDRESS_BITS))
+				.help(PREFIX_MINIMUM_HELP))
+			.arg(Arg::with_name("ipv4-prefix-minimum")
+				.long("ipv4-prefix-minimum")
+				.value_name("BITS")
+				.validator(is_prefix_bits(8 * IPV4_BYTES as u8))
+				.help(IPV4_PREFIX_MINIMUM_HELP))
+			.arg(Arg::with_name("truncate-to")
+				.long("truncate-to")
+				.value_name("BITS")
+				.validator(is_prefix_bits(ADDRESS_BITS))
+				.help("Truncates IPv6 addresses to their prefixes of BITS, e.g. 64, before storing them, so full addresses are never kept"))
+			.arg(Arg::with_name("ipv4-truncate-to")
+				.long("ipv4-truncate-to")
+				.value_name("BITS")
+				.validator(is_prefix_bits(8 * IPV4_BYTES as u8))
+				.help("Truncates IPv4 addresses to their prefixes of BITS, e.g. 24, before storing them"))
+			.arg(Arg::with_name("entries-per-user")
+				.long("entries-per-user")
+				.value_name("COUNT")
+				.validator(is_entry_count)
+				.help(ENTRIES_PER_USER_HELP))
+			.arg(Arg::with_name("trust-entries-per-user")
+				.long("trust-entries-per-user")
+				.value_name("COUNT")
+				.validator(is_entry_count)
+				.help("How many trust entries a user can have within the user expiry, counted separately from --entries-per-user"))
+			.arg(Arg::with_name("spam-entries-per-user")
+				.long("spam-entries-per-user")
+				.value_name("COUNT")
+				.validator(is_entry_count)
+				.help("How many spam entries, and entries of the other report categories, a user can have within the user expiry, counted separately from --entries-per-user"))
+			.arg(Arg::with_name("entries-per-user-prefix")
+				.long("entries-per-user-prefix")
+				.value_name("COUNT")
+				.validator(is_entry_count)
+				.help("How many entries of any type a user can have within the user expiry in one IPv6 /32 or IPv4 /16"))
+			.arg(Arg::with_name("user-expiry")
+				.long("user-expiry")
+				.value_name("HOURS")
+				.validator(is_hours)
+				.help(USER_EXPIRY_HELP))
+			.arg(Arg::with_name("address-expiry")
+				.long("address-expiry")
+				.value_name("HOURS")
+				.validator(is_hours)
+				.help(ADDRESS_EXPIRY_HELP))
+			.arg(Arg::with_name("trust-address-expiry")
+				.long("trust-address-expiry")
+				.value_name("HOURS")
+				.validator(is_hours)
+				.help("How long a trust entry counts at all, if different from other entries"))
+			.arg(Arg::with_name("decay-half-life")
+				.long("decay-half-life")
+				.value_name("HOURS")
+				.validator(is_hours)
+				.help("Makes the weights of entries in weighted queries halve every HOURS instead of staying the same until they expire"))
+			.arg(Arg::with_name("velocity-window")
+				.long("velocity-window")
+				.value_name("HOURS")
+				.validator(is_window_hours)
+				.help("Counts the spam entries within the last HOURS for each prefix, so queries can tell a prefix that suddenly started sending spam"))
+			.arg(Arg::with_name("velocity-window-minutes")
+				.long("velocity-window-minutes")
+				.value_name("MINUTES")
+				.validator(is_minutes)
+				.conflicts_with("velocity-window")
+				.help("Like --velocity-window, but in minutes, for windows shorter than an hour"))
+			.arg(Arg::with_name("special-ranges")
+				.long("special-ranges")
+				.value_name("POLICY")
+				.possible_values(&["accept", "ignore", "reject"])
+				.default_value("accept")
+				.help("What to do with reports for loopback, link-local, unique local, multicast, and documentation addresses, which are almost always client bugs"))
+			.arg(Arg::with_name("unwrap-tunnels")
+				.long("unwrap-tunnels")
+				.help("Treats 6to4 and Teredo addresses as the IPv4 addresses they tunnel, so switching transition mechanisms doesn’t give a fresh reputation"))
+			.arg(Arg::with_name("spam-prior")
+				.long("spam-prior")
+				.value_name("WEIGHT")
+				.validator(is_weight)
+				.help("The number of spam entries every prefix starts out with when estimating spam probabilities [default: 1]"))
+			.arg(Arg::with_name("trusted-prior")
+				.long("trusted-prior")
+				.value_name("WEIGHT")
+				.validator(is_weight)
+				.help("The number of trusted entries every prefix starts out with when estimating spam probabilities [default: 1]"))
+			.arg(Arg::with_name("allocation-boundaries")
+				.long("allocation-boundaries")
+				.help("Only counts IPv6 entries for /128, /64, /56, /48, and /32 prefixes, the sizes commonly assigned, instead of for every prefix size"))
+			.arg(Arg::with_name("distinct-users")
+				.long("distinct-users")
+				.help("Tracks the number of distinct users reporting each prefix, exactly up to 64 and estimated beyond that"))
+			.arg(Arg::with_name("min-distinct-users")
+				.long("min-distinct-users")
+				.value_name("COUNT")
+				.validator(is_entry_count)
+				.help("Ignores prefixes with entries from fewer distinct users than this in queries, so counts don’t reveal individual reports; implies --distinct-users"))
+			.arg(Arg::with_name("spam-quarantine")
+				.long("spam-quarantine")
+				.value_name("COUNT")
+				.validator(is_entry_count)
+				.help("Ignores reports other than trust for a prefix in queries until this many distinct users have made them, so one account can’t get a shared network blocked"))
+			.arg(Arg::with_name("split-threshold")
+				.long("split-threshold")
+				.value_name("COUNT")
+				.validator(is_entry_count)
+				.help("Only counts entries for a prefix once the next shorter one has this many, removing it again when that drops below"))
+			.arg(Arg::with_name("cap-per-64")
+				.long("cap-per-64")
+				.value_name("COUNT")
+				.validator(is_entry_count)
+				.help("Counts at most this many entries of each type from one IPv6 /64 for shorter prefixes, so one host can’t inflate its whole network’s counts"))
+			.arg(Arg::with_name("empty-cache-ttl")
+				.long("empty-cache-ttl")
+				.value_name("SECONDS")
+				.validator(is_seconds)
+				.help("Remembers for this long that queries for an IPv6 /64 came up empty, until something near it is reported"))
+			.arg(Arg::with_name("max-prefixes")
+				.long("max-prefixes")
+				.value_name("COUNT")
+				.validator(is_number::<usize>)
+				.help("Limits the number of prefixes tracked, pruning the ones least recently reported when it’s exceeded"))
+			.arg(Arg::with_name("allowlist")
+				.long("allowlist")
+				.value_name("PATH")
+				.help("Reads a file of prefixes, one per line, that are always fully trusted and can’t be reported as spam"))
+			.arg(Arg::with_name("denylist")
+				.long("denylist")
+				.value_name("PATH")
+				.help("Reads a file of prefixes, one per line, that are always reported as spam"))
+			.arg(Arg::with_name("asn-database")
+				.long("asn-database")
+				.value_name("PATH")
+				.help("Also counts entries per autonomous system, using a MaxMind-format ASN database like GeoLite2-ASN, for addresses without data as specific as their network"))
+			.arg(Arg::with_name("country-database")
+				.long("country-database")
+				.value_name("PATH")
+				.help("Also counts entries per country and lets queries get an address’s country, using a MaxMind-format country database like GeoLite2-Country"))
+			.arg(Arg::with_name("user-salt")
+				.long("user-salt")
+				.value_name("PATH")
+				.help("Hashes users with a secret salt read from a file before storing them, so the operation log doesn’t contain the numbers clients send"))
+			.arg(Arg::with_name("import")
+				.long("import")
+				.value_name("PATH")
+				.multiple(true)
+				.number_of_values(1)
+				.help("Merges in the operations from another persistence directory when starting, without writing them to this one’s log"))
+			.arg(Arg::with_name("statsd")
+				.long("statsd")
+				.value_name("HOST:PORT")
+				.help("Sends counts of connections and requests, the size of the tree, and how long snapshots take to a StatsD agent, like Datadog’s, over UDP"))
+			.arg(Arg::with_name("statsd-prefix")
+				.long("statsd-prefix")
+				.value_name("PREFIX")
+				.default_value("iptooled")
+				.help("The prefix of the names of metrics sent to the StatsD agent"))
+			.arg(Arg::with_name("statsd-interval")
+				.long("statsd-interval")
+				.value_name("SECONDS")
+				.validator(is_seconds)
+				.default_value(DEFAULT_STATSD_INTERVAL)
+				.help("How often to send metrics to the StatsD agent"))
+			.arg(Arg::with_name("otlp-endpoint")
+				.long("otlp-endpoint")
+				.value_name("URL")
+				.help("Sends a trace of each request, with spans for parsing it, updating the tree, and writing the operation log, to an OpenTelemetry collector’s OTLP/HTTP endpoint, like http://localhost:4318"))
+			.arg(Arg::with_name("slow-request-threshold")
+				.long("slow-request-threshold")
+				.value_name("MILLISECONDS")
+				.validator(is_number::<u64>)
+				.help("Logs requests that take longer than this to answer, with the address they’re about and how deep into the tree its query goes"))
+			.arg(Arg::with_name("slow-request-sample")
+				.long("slow-request-sample")
+				.value_name("COUNT")
+				.validator(is_sample)
+				.default_value(DEFAULT_SLOW_REQUEST_SAMPLE)
+				.help("Logs only one in this many slow requests"))
+			.arg(Arg::with_name("audit-log")
+				.long("audit-log")
+				.value_name("PATH")
+				.help("Appends a line to a file for each accepted report and retraction, with the user id of the client that made it, for abuse investigations"))
+			.arg(Arg::with_name("degraded-after")
+				.long("degraded-after")
+				.value_name("COUNT")
+				.validator(is_sample)
+				.default_value(DEFAULT_DEGRADED_AFTER)
+				.help("How many writes to the operation log have to fail in a row, like when the disk is full, for the daemon to report itself degraded in health checks"))
+			.arg(Arg::with_name("refuse-writes-when-degraded")
+				.long("refuse-writes-when-degraded")
+				.help("Refuses reports and retractions while degraded, instead of accepting ones that might be lost on restart"))
+			.arg(Arg::with_name("degraded-exit-after")
+				.long("degraded-exit-after")
+				.value_name("SECONDS")
+				.validator(is_seconds)
+				.help("Exits with status 74 if the daemon stays degraded for this long, so a supervisor can step in"))
+			.arg(Arg::with_name("rate-limit")
+				.long("rate-limit")
+				.value_name("REQUESTS")
+				.validator(is_sample)
+				.help("Limits each user id to this many requests per second, on average, by making its clients wait; clients whose user id isn’t known are limited per connection"))
+			.arg(Arg::with_name("rate-limit-burst")
+				.long("rate-limit-burst")
+				.value_name("COUNT")
+				.validator(is_sample)
+				.requires("rate-limit")
+				.help("How many requests a user id can make at once under --rate-limit after being idle, instead of a second’s worth"))
+			.arg(Arg::with_name("replication-listen")
+				.long("replication-listen")
+				.value_name("HOST:PORT")
+				.help("Listens for followers over TCP and streams every accepted report and retraction to them as it’s applied; anyone who can connect can read them"))
+			.arg(Arg::with_name("replica")
+				.long("replica")
+				.value_name("HOST:PORT")
+				.help("Follows a primary listening with --replication-listen at this address, applying its operations and only answering queries; reports and retractions are refused with the primary’s address"))
+			.arg(Arg::with_name("log-level")
+				.long("log-level")
+				.value_name("FILTER")
+				.validator(is_log_filter)
+				.default_value(logging::DEFAULT_FILTER)
+				.help("Which log messages to write: error, warn, info, debug, or trace and everything more severe, optionally per module, like info,iptooled::handoff=debug"))
+			.arg(Arg::with_name("log-format")
+				.long("log-format")
+				.value_name("FORMAT")
+				.possible_values(&["text", "json"])
+				.default_value("text")
+				.help("Writes log messages as text or as one JSON object per line"))
+			.arg(Arg::with_name("config")
+				.long("config")
+				.value_name("PATH")
+				.help("Reads options from a file of `option = value` lines, which options on the command line override"));
+
+	#[cfg(unix)]
+	let serve =
+		serve
+			.arg(Arg::with_name("stdio")
+				.long("stdio")
+				.conflicts_with_all(&["socket-path", "daemonize", "pid-file", "replication-listen", "replica"])
+				.help("Serves one client over stdin and stdout, then exits, for inetd; log messages are discarded without --log-file"))
+			.arg(Arg::with_name("daemonize")
+				.long("daemonize")
+				.help("Detaches from the terminal once the socket is bound and the operation log is replayed"))
+			.arg(Arg::with_name("pid-file")
+				.long("pid-file")
+				.value_name("PATH")
+				.help("Writes the process id to a file, which is removed on exit"))
+			.arg(Arg::with_name("log-file")
+				.long("log-file")
+				.value_name("PATH")
+				.help("Appends log messages to a file instead of stderr"))
+			.arg(Arg::with_name("log-target")
+				.long("log-target")
+				.value_name("TARGET")
+				.possible_values(&["stderr", "syslog", "journald"])
+				.default_value("stderr")
+				.help("Writes log messages to stderr, the syslog daemon, or systemd’s journal"))
+			.arg(Arg::with_name("chroot")
+				.long("chroot")
+				.value_name("PATH")
+				.help("Changes the root directory after binding the socket; the persistence path is relative to it"))
+			.arg(Arg::with_name("handoff")
+				.long("handoff")
+				.value_name("PATH")
+				// The new daemon can’t bind the replication address until the old one exits.
+				.conflicts_with_all(&["stdio", "replication-listen"])
+				.help("Takes over the socket from a daemon listening for a handoff at this path, if there is one, then listens there for the next upgrade"))
+			.arg(Arg::with_name("admin-socket")
+				.long("admin-socket")
+				.value_name("PATH")
+				.conflicts_with("stdio")
+				.help("Listens on a second socket, only accessible to the daemon’s user, for stats, overrides, forgetting users, shutting down, and flushing, which the main socket stops accepting"))
+			.arg(Arg::with_name("dump-file")
+				.long("dump-file")
+				.value_name("PATH")
+				.help("Appends the diagnostic dump written on SIGUSR1 to a file instead of stderr"));
+
+	#[cfg(all(target_os = "linux", feature = "sandbox"))]
+	let serve =
+		serve.arg(Arg::with_name("sandbox")
+			.long("sandbox")
+			// Sending spans and reconnecting to the primary open connections, which the sandbox doesn’t allow.
+			.conflicts_with_all(&["otlp-endpoint", "replica"])
+			.help("Restricts filesystem access and system calls once ready to serve"));
+
+	let app =
+		App::new("iptooled")
+			.version(build_info::VERSION)
+			// Built once, so leaking it is fine.
+			.long_version(&*Box::leak(build_info::long_version().into_boxed_str()))
+			.about("An address-based spam tree")
+			.setting(AppSettings::SubcommandRequiredElseHelp)
+			.setting(AppSettings::VersionlessSubcommands)
+			.subcommand(serve)
+			.subcommand(SubCommand::with_name("dump")
+				.about("Prints the operations in an operation log, one per line")
+				.arg(persist_path_arg()))
+			.subcommand(SubCommand::with_name("verify")
+				.about("Checks that an operation log can be replayed")
+				.arg(persist_path_arg()))
+			.subcommand(SubCommand::with_name("diff")
+				.about("Prints the entries to add to and remove from the first operation log’s tree to get the second’s, with the default settings, as of now")
+				.arg(persist_path_arg())
+				.arg(Arg::with_name("other-persist-path")
+					.required(true)
+					.help("The directory containing the operation log to compare to")))
+			.subcommand(SubCommand::with_name("replay")
+				.about("Replays an operation log with the clock following the times of its operations, printing the results for some addresses as they change, with the default settings")
+				.arg(Arg::with_name("interval")
+					.long("interval")
+					.value_name("HOURS")
+					.default_value(DEFAULT_REPLAY_INTERVAL)
+					.validator(is_hours)
+					.help("How much log time passes between results"))
+				.arg(persist_path_arg())
+				.arg(Arg::with_name("address")
+					.required(true)
+					.multiple(true)
+					.validator(is_address)
+					.help("An address to print the results for")));
+
+	#[cfg(any(test, feature = "bench"))]
+	let app =
+		app.subcommand(SubCommand::with_name("bench")
+			.about("Measures operation and query speed on an in-memory tree of random addresses")
+			.arg(Arg::with_name("operations")
+				.long("operations")
+				.value_name("COUNT")
+				.default_value(DEFAULT_BENCH_OPERATIONS)
+				.validator(is_number::<u32>)
+				.help("How many trust and spam operations to perform"))
+			.arg(Arg::with_name("queries")
+				.long("queries")
+				.value_name("COUNT")
+				.default_value(DEFAULT_BENCH_OPERATIONS)
+				.validator(is_number::<u32>)
+				.help("How many queries to make")));
+
+	app
+}
+
+fn path_of(matches: &ArgMatches, name: &str) -> Option<PathBuf> {
+	matches.value_of_os(name).map(PathBuf::from)
+}
+
+/// Gets a number from an argument that has a default and a validator.
+fn number_of<T: std::str::FromStr>(matches: &ArgMatches, name: &str) -> T {
+	matches.value_of(name).unwrap().parse().ok().unwrap()
+}
+
+/// Gets a number from an argument that has a validator but might not be present.
+fn optional_number_of<T: std::str::FromStr>(matches: &ArgMatches, name: &str) -> Option<T> {
+	matches.value_of(name).map(|value| value.parse().ok().unwrap())
+}
+
+/// Gets a duration from an argument that has a duration validator but might not be present, with `bare` as for `parse_duration`.
+fn optional_duration_of<U: TimeUnit>(matches: &ArgMatches, name: &str, bare: u64) -> Option<CoarseDuration<U>> {
+	matches.value_of(name).map(|value| parse_duration(value, bare).unwrap())
+}
+
+/// Reads a file named by an option, exiting with a usage error if that fails.
+fn read_or_exit<T>(path: &Path, read: fn(&Path) -> io::Result<T>) -> T {
+	read(path).unwrap_or_else(|err| {
+		ClapError::with_description(&format!("couldn’t read {}: {}", path.display(), err), ClapErrorKind::Io).exit()
+	})
+}
+
+/// Connects to a StatsD agent named by an option, exiting with a usage error if that fails.
+fn connect_statsd_or_exit(address: &str, prefix: &str) -> Statsd {
+	Statsd::connect(address, prefix).unwrap_or_else(|err| {
+		ClapError::with_description(&format!("couldn’t connect to the StatsD agent at {}: {}", address, err), ClapErrorKind::Io).exit()
+	})
+}
+
+/// Looks up an OpenTelemetry collector’s endpoint named by an option, exiting with a usage error if that fails.
+fn tracer_or_exit(endpoint: &str) -> Tracer {
+	Tracer::new(endpoint).unwrap_or_else(|err| {
+		ClapError::with_description(&format!("couldn’t use the OpenTelemetry collector at {}: {}", endpoint, err), ClapErrorKind::InvalidValue).exit()
+	})
+}
+
+/// Looks up the address of a primary named by an option once, exiting with a usage error if that fails.
+fn primary_or_exit(address: &str) -> SocketAddr {
+	let found = address.to_socket_addrs().map(|mut addresses| addresses.next());
+
+	match found {
+		Ok(Some(found)) => found,
+		Ok(None) => ClapError::with_description(&format!("couldn’t find an address for the primary at {}", address), ClapErrorKind::InvalidValue).exit(),
+		Err(err) => ClapError::with_description(&format!("couldn’t look up the primary at {}: {}", address, err), ClapErrorKind::InvalidValue).exit(),
+	}
+}
+
+/// Parses the command line, exiting with a usage message if it’s invalid.
+pub fn parse_args() -> Command {
+	parse_args_from(env::args_os().collect())
+}
+
+/// Parses arguments like `parse_args`, starting with the program’s name.
+pub fn parse_args_from(mut args: Vec<OsString>) -> Command {
+	let matches = app().get_matches_from(&args);
+
+	let config_path =
+		match matches.subcommand() {
+			("serve", Some(matches)) => matches.value_of_os("config").map(PathBuf::from),
+			_ => None,
+		};
+
+	// Options from the configuration file go right after the subcommand, before the ones that override them.
+	let matches =
+		match config_path {
+			Some(config_path) => {
+				let config_args = read_or_exit(&config_path, config::read_as_args);
+
+				let position = args.iter().position(|arg| arg == "serve").unwrap() + 1;
+				args.splice(position..position, config_args);
+				app().get_matches_from(&args)
+			}
+			None => matches,
+		};
+
+	match matches.subcommand() {
+		("serve", Some(matches)) => {
+			// Clients are kept however long they’re idle unless there’s a timeout, which zero disables too.
+			let idle_timeout =
+				match optional_number_of(matches, "idle-timeout") {
+					None | Some(0) => None,
+					Some(seconds) => Some(Duration::from_secs(seconds)),
+				};
+
+			let defaults = TreeSettings::DEFAULT;
+			let tree_settings = TreeSettings {
+				prefix_bits_minimum: optional_number_of(matches, "prefix-minimum").unwrap_or(defaults.prefix_bits_minimum),
+				ipv4_prefix_bits_minimum: optional_number_of(matches, "ipv4-prefix-minimum").unwrap_or(defaults.ipv4_prefix_bits_minimum),
+				truncate_bits: optional_number_of(matches, "truncate-to"),
+				ipv4_truncate_bits: optional_number_of(matches, "ipv4-truncate-to"),
+				entries_per_user: optional_number_of(matches, "entries-per-user").unwrap_or(defaults.entries_per_user),
+				trust_entries_per_user: optional_number_of(matches, "trust-entries-per-user"),
+				spam_entries_per_user: optional_number_of(matches, "spam-entries-per-user"),
+				entries_per_user_prefix: optional_number_of(matches, "entries-per-user-prefix"),
+				user_expiry: optional_duration_of(matches, "user-expiry", Hours::SECONDS).unwrap_or(defaults.user_expiry),
+				address_expiry: optional_duration_of(matches, "address-expiry", Hours::SECONDS).unwrap_or(defaults.address_expiry),
+				trust_address_expiry: optional_duration_of(matches, "trust-address-expiry", Hours::SECONDS),
+				decay_half_life: optional_duration_of(matches, "decay-half-life", Hours::SECONDS),
+				velocity_window:
+					optional_duration_of(matches, "velocity-window-minutes", Minutes::SECONDS)
+						.or_else(|| optional_duration_of(matches, "velocity-window", Hours::SECONDS)),
+				allocation_boundaries_only: matches.is_present("allocation-boundaries"),
+				distinct_users: matches.is_present("distinct-users") || matches.is_present("min-distinct-users"),
+				min_distinct_users: optional_number_of(matches, "min-distinct-users"),
+				spam_quarantine: optional_number_of(matches, "spam-quarantine"),
+				split_threshold: optional_number_of(matches, "split-threshold"),
+				network_cap: optional_number_of(matches, "cap-per-64"),
+				empty_cache_ttl: optional_number_of(matches, "empty-cache-ttl").map(Duration::from_secs),
+				max_prefixes: optional_number_of(matches, "max-prefixes"),
+				allowlist: path_of(matches, "allowlist").map_or(defaults.allowlist, |path| read_or_exit(&path, PrefixList::read)),
+				denylist: path_of(matches, "denylist").map_or(defaults.denylist, |path| read_or_exit(&path, PrefixList::read)),
+				asn_database: path_of(matches, "asn-database").map(|path| Arc::new(read_or_exit(&path, Database::read))),
+				country_database: path_of(matches, "country-database").map(|path| Arc::new(read_or_exit(&path, Database::read))),
+			};
+
+			Command::Serve(ServeOptions {
+				persist_path: path_of(matches, "persist-path").unwrap(),
+				socket_path: path_of(matches, "socket-path"),
+				idle_timeout,
+				snapshot_interval: optional_number_of(matches, "snapshot-interval").map(Duration::from_secs),
+				prior: Prior {
+					spam: optional_number_of(matches, "spam-prior").unwrap_or(Prior::DEFAULT.spam),
+					trusted: optional_number_of(matches, "trusted-prior").unwrap_or(Prior::DEFAULT.trusted),
+				},
+				tree_settings,
+				user_salt: path_of(matches, "user-salt").map(|path| read_or_exit(&path, UserSalt::read)),
+				special_ranges:
+					match matches.value_of("special-ranges").unwrap() {
+						"ignore" => SpecialRangePolicy::Ignore,
+						"reject" => SpecialRangePolicy::Reject,
+						_ => SpecialRangePolicy::Accept,
+					},
+				unwrap_tunnels: matches.is_present("unwrap-tunnels"),
+				statsd: matches.value_of("statsd").map(|address| Rc::new(connect_statsd_or_exit(address, matches.value_of("statsd-prefix").unwrap()))),
+				statsd_interval: Duration::from_secs(number_of(matches, "statsd-interval")),
+				tracer: matches.value_of("otlp-endpoint").map(|endpoint| Rc::new(tracer_or_exit(endpoint))),
+				slow_request_threshold: optional_number_of(matches, "slow-request-threshold").map(Duration::from_millis),
+				slow_request_sample: number_of(matches, "slow-request-sample"),
+				audit_path: path_of(matches, "audit-log"),
+				degraded_after: number_of(matches, "degraded-after"),
+				refuse_writes_when_degraded: matches.is_present("refuse-writes-when-degraded"),
+				degraded_exit_after: optional_number_of(matches, "degraded-exit-after").map(Duration::from_secs),
+				rate_limit: optional_number_of(matches, "rate-limit").map(|rate| (rate, optional_number_of(matches, "rate-limit-burst").unwrap_or(rate))),
+				replication_address: matches.value_of("replication-listen").map(str::to_owned),
+				primary_address: matches.value_of("replica").map(primary_or_exit),
+				log_filter: matches.value_of("log-level").unwrap().to_owned(),
+				log_format:
+					match matches.value_of("log-format").unwrap() {
+						"json" => LogFormat::Json,
+						_ => LogFormat::Text,
+					},
+				log_target:
+					match matches.value_of("log-target") {
+						#[cfg(unix)]
+						Some("syslog") => LogTarget::Syslog,
+						#[cfg(unix)]
+						Some("journald") => LogTarget::Journald,
+						_ => LogTarget::Stderr,
+					},
+				import_paths: matches.values_of_os("import").map_or_else(Vec::new, |paths| paths.map(PathBuf::from).collect()),
+				#[cfg(unix)]
+				daemonize: matches.is_present("daemonize"),
+				#[cfg(unix)]
+				pid_path: path_of(matches, "pid-file"),
+				#[cfg(unix)]
+				log_path: path_of(matches, "log-file"),
+				#[cfg(unix)]
+				chroot_path: path_of(matches, "chroot"),
+				#[cfg(unix)]
+				handoff_path: path_of(matches, "handoff"),
+				#[cfg(unix)]
+				admin_socket_path: path_of(matches, "admin-socket"),
+				#[cfg(unix)]
+				dump_path: path_of(matches, "dump-file"),
+				#[cfg(all(target_os = "linux", feature = "sandbox"))]
+				sandbox: matches.is_present("sandbox"),
+			})
+		}
+		("dump", Some(matches)) => Command::Dump(path_of(matches, "persist-path").unwrap()),
+		("verify", Some(matches)) => Command::Verify(path_of(matches, "persist-path").unwrap()),
+		("diff", Some(matches)) => Command::Diff(path_of(matches, "persist-path").unwrap(), path_of(matches, "other-persist-path").unwrap()),
+		("replay", Some(matches)) => Command::Replay(ReplayOptions {
+			persist_path: path_of(matches, "persist-path").unwrap(),
+			interval: optional_duration_of(matches, "interval", Hours::SECONDS).unwrap(),
+			addresses: matches.values_of("address").unwrap().map(|address| address.parse().unwrap()).collect(),
+		}),
+		#[cfg(any(test, feature = "bench"))]
+		("bench", Some(matches)) => Command::Bench(BenchOptions {
+			operations: number_of(matches, "operations"),
+			queries: number_of(matches, "queries"),
+		}),
+		_ => unreachable!(),
+	}
+}
@@ -0,0 +1,35 @@
+use std::ffi::OsString;
+use std::fs;
+use std::io::{self, ErrorKind};
+use std::path::Path;
+
+/// Reads a configuration file of `key = value` lines as the equivalent `--key value` arguments. Blank lines and lines starting with `#` are ignored. A value of `true` turns on a flag, and `false` leaves it off.
+pub fn read_as_args(path: &Path) -> io::Result<Vec<OsString>> {
+	let contents = fs::read_to_string(path)?;
+	let mut args = Vec::new();
+
+	for (i, line) in contents.lines().enumerate() {
+		let line = line.trim();
+
+		if line.is_empty() || line.starts_with('#') {
+			continue;
+		}
+
+		let (key, value) =
+			match line.find('=') {
+				Some(index) => (line[..index].trim_end(), line[index + 1..].trim_start()),
+				None => return Err(io::Error::new(ErrorKind::InvalidData, format!("line {} isn’t a `key = value` pair", i + 1))),
+			};
+
+		match value {
+			"false" => {}
+			"true" => args.push(format!("--{}", key).into()),
+			_ => {
+				args.push(format!("--{}", key).into());
+				args.push(value.into());
+			}
+		}
+	}
+
+	Ok(args)
+}
@@ -0,0 +1,144 @@
+use std::collections::{BTreeMap, HashMap};
+use std::time::{Duration, Instant};
+
+/// Stands for the user id where it’s unknown, as for `--stdio` and named pipes. It’s `(uid_t) -1`, which no process runs as.
+pub const UNKNOWN_UID: u32 = u32::max_value();
+
+/// How long errors from a user id are counted together.
+pub const CLIENT_ERROR_WINDOW: Duration = Duration::from_secs(60);
+
+/// How many errors from a user id are logged in each window before the rest are only counted.
+const LOGGED_CLIENT_ERRORS: u32 = 10;
+
+/// Why a connection ended.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Disconnect {
+	/// The client closed it.
+	End,
+	/// Reading or writing failed, or the client sent a malformed request.
+	Error,
+	/// The client was idle for longer than the idle timeout.
+	Timeout,
+	/// The daemon closed it to shut down.
+	Kicked,
+}
+
+/// Counts of connections and the requests made on them since startup, to find out which local service is making the most requests.
+#[derive(Debug, Default)]
+pub struct ConnectionStats {
+	accepted: u64,
+	/// The number of connections that ended for each reason, in the order of `Disconnect`’s variants.
+	disconnects: [u64; 4],
+	/// Requests by the user id of the process on the other end, or `UNKNOWN_UID`.
+	requests_by_uid: BTreeMap<u32, u64>,
+	/// The number of requests that had to wait because of the rate limit.
+	throttled: u64,
+}
+
+impl ConnectionStats {
+	pub fn connect(&mut self) {
+		self.accepted += 1;
+	}
+
+	pub fn disconnect(&mut self, reason: Disconnect) {
+		self.disconnects[reason as usize] += 1;
+	}
+
+	pub fn count_request(&mut self, uid: Option<u32>) {
+		*self.requests_by_uid.entry(uid.unwrap_or(UNKNOWN_UID)).or_insert(0) += 1;
+	}
+
+	pub fn count_throttled(&mut self) {
+		self.throttled += 1;
+	}
+
+	pub fn accepted(&self) -> u64 {
+		self.accepted
+	}
+
+	pub fn active(&self) -> u64 {
+		self.accepted - self.disconnects.iter().sum::<u64>()
+	}
+
+	/// Gets the number of connections that ended for each reason: the client closing it, errors, timeouts, and shutting down.
+	pub fn disconnects(&self) -> &[u64; 4] {
+		&self.disconnects
+	}
+
+	/// Gets the number of requests made by each user id, ordered by user id.
+	pub fn requests_by_uid(&self) -> &BTreeMap<u32, u64> {
+		&self.requests_by_uid
+	}
+
+	pub fn throttled(&self) -> u64 {
+		self.throttled
+	}
+}
+
+/// The kinds of errors clients cause, which are counted separately.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub enum ClientErrorKind {
+	/// A malformed request.
+	Format,
+	/// Reading or writing failed, like when the client resets the connection.
+	Io,
+	/// A request that’s only accepted on the admin socket.
+	Forbidden,
+}
+
+impl ClientErrorKind {
+	pub fn name(self) -> &'static str {
+		match self {
+			Self::Format => "format",
+			Self::Io => "I/O",
+			Self::Forbidden => "forbidden request",
+		}
+	}
+}
+
+/// The errors from a user id of one kind since its window started.
+#[derive(Debug)]
+struct ErrorWindow {
+	start: Instant,
+	logged: u32,
+	suppressed: u64,
+}
+
+/// Limits how many client errors are logged from each user id, so a malfunctioning client that keeps sending garbage doesn’t fill the disk with logs. The rest are counted and logged as one summary when their window ends.
+#[derive(Debug, Default)]
+pub struct ClientErrors {
+	windows: HashMap<(u32, ClientErrorKind), ErrorWindow>,
+}
+
+impl ClientErrors {
+	/// Counts an error from a client with user id `uid`, if it’s known, returning whether to log it.
+	pub fn record(&mut self, uid: Option<u32>, kind: ClientErrorKind, now: Instant) -> bool {
+		let window = self.windows.entry((uid.unwrap_or(UNKNOWN_UID), kind)).or_insert(ErrorWindow { start: now, logged: 0, suppressed: 0 });
+
+		if window.logged < LOGGED_CLIENT_ERRORS {
+			window.logged += 1;
+			true
+		} else {
+			window.suppressed += 1;
+			false
+		}
+	}
+
+	/// Ends the windows that started at least `CLIENT_ERROR_WINDOW` before `now`, or all of them if `now` is `None`, returning the number of errors that weren’t logged in each one that had any, ordered by user id.
+	pub fn end_windows(&mut self, now: Option<Instant>) -> Vec<(u32, ClientErrorKind, u64)> {
+		let mut suppressed = Vec::new();
+
+		self.windows.retain(|&(uid, kind), window| {
+			let ended = now.map_or(true, |now| now.duration_since(window.start) >= CLIENT_ERROR_WINDOW);
+
+			if ended && window.suppressed != 0 {
+				suppressed.push((uid, kind, window.suppressed));
+			}
+
+			!ended
+		});
+
+		suppressed.sort_unstable();
+		suppressed
+	}
+}
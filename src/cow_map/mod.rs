@@ -0,0 +1,212 @@
+#[cfg(test)]
+mod tests;
+
+use std::collections::{btree_map, BTreeMap};
+use std::iter::FlatMap;
+use std::ops::{Bound, Index};
+use std::sync::Arc;
+
+use super::tree::estimated_btree_bytes;
+
+/// The number of entries a chunk is split in half at.
+const MAX_CHUNK: usize = 128;
+
+/// The number of entries a chunk is merged into the one before it below.
+const MIN_CHUNK: usize = MAX_CHUNK / 4;
+
+/// An iterator over a `CowMap`’s entries in order.
+pub type Iter<'a, K, V> = FlatMap<btree_map::Values<'a, Option<K>, Arc<BTreeMap<K, V>>>, btree_map::Iter<'a, K, V>, fn(&'a Arc<BTreeMap<K, V>>) -> btree_map::Iter<'a, K, V>>;
+
+/// An ordered map split into chunks of consecutive keys that are shared between clones until one of them changes, so cloning it only copies a reference to each chunk, and each change after that only copies the chunk it’s in.
+#[derive(Clone, Debug)]
+pub struct CowMap<K, V> {
+	/// The chunks by their first possible key, with `None` for the first one, so each key belongs in the last chunk starting at or before it.
+	chunks: BTreeMap<Option<K>, Arc<BTreeMap<K, V>>>,
+	len: usize,
+	/// The chunk of the last removal, which is merged before the next change if it’s left with fewer than `MIN_CHUNK` entries.
+	shrunk: Option<Option<K>>,
+}
+
+impl<K: Clone + Ord, V: Clone> CowMap<K, V> {
+	pub fn new() -> Self {
+		let mut chunks = BTreeMap::new();
+		chunks.insert(None, Arc::new(BTreeMap::new()));
+
+		Self {
+			chunks,
+			len: 0,
+			shrunk: None,
+		}
+	}
+
+	pub fn len(&self) -> usize {
+		self.len
+	}
+
+	/// Gets the chunk a key belongs in.
+	fn chunk(&self, key: &K) -> &BTreeMap<K, V> {
+		self.chunks.range(..=Some(key.clone())).next_back().unwrap().1
+	}
+
+	pub fn get(&self, key: &K) -> Option<&V> {
+		self.chunk(key).get(key)
+	}
+
+	pub fn contains_key(&self, key: &K) -> bool {
+		self.chunk(key).contains_key(key)
+	}
+
+	/// Finds the last entry with a key at or before `key`.
+	pub fn last_at_or_before(&self, key: &K) -> Option<(&K, &V)> {
+		self.chunks.range(..=Some(key.clone())).rev()
+			.find_map(|(_, chunk)| chunk.range(..=key).next_back())
+	}
+
+	/// Iterates over the entries with keys after `key`, in order.
+	pub fn iter_after(&self, key: &K) -> impl Iterator<Item = (&K, &V)> {
+		let key = key.clone();
+		let first = self.chunks.range(..=Some(key.clone())).next_back().unwrap().0.clone();
+
+		self.chunks.range(first..)
+			.flat_map(move |(_, chunk)| chunk.range((Bound::Excluded(key.clone()), Bound::Unbounded)))
+	}
+
+	/// Iterates over the entries in order.
+	pub fn iter(&self) -> Iter<'_, K, V> {
+		self.chunks.values().flat_map(|chunk| chunk.iter())
+	}
+
+	/// Merges the chunk of the last removal into the one before it if it’s too small, so removals don’t leave lots of nearly empty chunks behind. The merged chunk is split again when it’s next added to if that makes it too large.
+	fn merge_shrunk(&mut self) {
+		let first =
+			match self.shrunk.take() {
+				Some(first @ Some(_)) => first,
+				_ => return,
+			};
+
+		let mut chunk =
+			match self.chunks.get(&first) {
+				Some(chunk) if chunk.len() < MIN_CHUNK => self.chunks.remove(&first).unwrap(),
+				_ => return,
+			};
+
+		let (_, previous) = self.chunks.range_mut(..&first).next_back().unwrap();
+		let previous = Arc::make_mut(previous);
+
+		match Arc::get_mut(&mut chunk) {
+			Some(chunk) => previous.append(chunk),
+			None => previous.extend(chunk.iter().map(|(key, value)| (key.clone(), value.clone()))),
+		}
+	}
+
+	/// Gets the entry for a key, copying its chunk first if it’s shared.
+	pub fn entry(&mut self, key: K) -> Entry<'_, K, V> {
+		self.merge_shrunk();
+
+		let first = Some(key.clone());
+		let (_, chunk) = self.chunks.range_mut(..=&first).next_back().unwrap();
+
+		if chunk.len() >= MAX_CHUNK {
+			let middle = chunk.keys().nth(chunk.len() / 2).unwrap().clone();
+			let upper = Arc::make_mut(chunk).split_off(&middle);
+			self.chunks.insert(Some(middle), Arc::new(upper));
+		}
+
+		let (first, chunk) = self.chunks.range_mut(..=first).next_back().unwrap();
+		let (len, shrunk) = (&mut self.len, &mut self.shrunk);
+
+		match Arc::make_mut(chunk).entry(key) {
+			btree_map::Entry::Occupied(entry) => Entry::Occupied(OccupiedEntry { entry, first, len, shrunk }),
+			btree_map::Entry::Vacant(entry) => Entry::Vacant(VacantEntry { entry, len }),
+		}
+	}
+
+	pub fn remove(&mut self, key: &K) -> Option<V> {
+		self.merge_shrunk();
+
+		let (first, chunk) = self.chunks.range_mut(..=Some(key.clone())).next_back().unwrap();
+
+		// Only copied if there’s something to remove.
+		if !chunk.contains_key(key) {
+			return None;
+		}
+
+		self.len -= 1;
+		self.shrunk = Some(first.clone());
+		Arc::make_mut(chunk).remove(key)
+	}
+
+	/// Estimates the memory used by the chunks, including chunks still shared with clones.
+	pub fn estimated_bytes(&self) -> usize {
+		estimated_btree_bytes(&self.chunks) + self.chunks.values().map(|chunk| estimated_btree_bytes(chunk)).sum::<usize>()
+	}
+}
+
+impl<K: Clone + Ord, V: Clone> Default for CowMap<K, V> {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+impl<K: Clone + Ord, V: Clone> Index<&K> for CowMap<K, V> {
+	type Output = V;
+
+	fn index(&self, key: &K) -> &V {
+		self.get(key).expect("Key missing from map")
+	}
+}
+
+/// An entry in a `CowMap`, like a `BTreeMap`’s.
+pub enum Entry<'a, K: Clone + Ord, V> {
+	Occupied(OccupiedEntry<'a, K, V>),
+	Vacant(VacantEntry<'a, K, V>),
+}
+
+impl<'a, K: Clone + Ord, V: Clone> Entry<'a, K, V> {
+	pub fn or_insert_with(self, default: impl FnOnce() -> V) -> &'a mut V {
+		match self {
+			Self::Occupied(entry) => entry.into_mut(),
+			Self::Vacant(entry) => entry.insert(default()),
+		}
+	}
+}
+
+pub struct OccupiedEntry<'a, K: Clone + Ord, V> {
+	entry: btree_map::OccupiedEntry<'a, K, V>,
+	/// The first possible key of the entry’s chunk.
+	first: &'a Option<K>,
+	len: &'a mut usize,
+	shrunk: &'a mut Option<Option<K>>,
+}
+
+impl<'a, K: Clone + Ord, V: Clone> OccupiedEntry<'a, K, V> {
+	pub fn get(&self) -> &V {
+		self.entry.get()
+	}
+
+	pub fn get_mut(&mut self) -> &mut V {
+		self.entry.get_mut()
+	}
+
+	pub fn into_mut(self) -> &'a mut V {
+		self.entry.into_mut()
+	}
+
+	pub fn remove(self) -> V {
+		*self.len -= 1;
+		*self.shrunk = Some(self.first.clone());
+		self.entry.remove()
+	}
+}
+
+pub struct VacantEntry<'a, K: Clone + Ord, V> {
+	entry: btree_map::VacantEntry<'a, K, V>,
+	len: &'a mut usize,
+}
+
+impl<'a, K: Clone + Ord, V: Clone> VacantEntry<'a, K, V> {
+	pub fn insert(self, value: V) -> &'a mut V {
+		*self.len += 1;
+		self.entry.insert(value)
+	}
+}
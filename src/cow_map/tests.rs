@@ -0,0 +1,56 @@
+use std::collections::BTreeMap;
+
+use super::{CowMap, Entry};
+
+/// Checks that a map has the same entries as a `BTreeMap`, and finds the same ones around each of some keys.
+fn same_entries(map: &CowMap<u32, u32>, expected: &BTreeMap<u32, u32>, keys: &[u32]) -> bool {
+	map.len() == expected.len()
+		&& map.iter().eq(expected.iter())
+		&& keys.iter().all(|key| {
+			map.get(key) == expected.get(key)
+				&& map.last_at_or_before(key) == expected.range(..=key).next_back()
+				&& map.iter_after(key).eq(expected.range(key + 1..))
+		})
+}
+
+/// Checks a map changed through entries and removals against a `BTreeMap`, along with clones taken along the way, which shouldn’t see the changes made after them.
+#[quickcheck]
+fn cow_map_matches_btree_map(changes: Vec<(u8, u16, u8)>, keys: Vec<u16>) -> bool {
+	let mut map = CowMap::new();
+	let mut expected = BTreeMap::new();
+	let mut clones = Vec::new();
+
+	for (change, start, count) in changes {
+		// Runs of keys, so that chunks fill up and are split, and empty out and are merged.
+		let run = u32::from(start)..u32::from(start) + u32::from(count);
+
+		match change % 4 {
+			0 => for key in run {
+				*map.entry(key).or_insert_with(|| 0) += 1;
+				*expected.entry(key).or_insert(0) += 1;
+			},
+			1 => for key in run {
+				let removed =
+					match map.entry(key) {
+						Entry::Occupied(entry) => Some(entry.remove()),
+						Entry::Vacant(_) => None,
+					};
+
+				if removed != expected.remove(&key) {
+					return false;
+				}
+			},
+			2 => for key in run {
+				if map.remove(&key) != expected.remove(&key) {
+					return false;
+				}
+			},
+			_ => clones.push((map.clone(), expected.clone())),
+		}
+	}
+
+	let keys: Vec<u32> = keys.into_iter().map(u32::from).chain(expected.keys().cloned()).collect();
+
+	same_entries(&map, &expected, &keys)
+		&& clones.iter().all(|(map, expected)| same_entries(map, expected, &keys))
+}
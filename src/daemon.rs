@@ -0,0 +1,142 @@
+use std::ffi::CString;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::io::AsRawFd;
+use std::path::Path;
+use std::process;
+use tracing::error;
+
+/// Forks, exiting in the parent.
+fn fork_and_exit_parent() -> io::Result<()> {
+	match unsafe { libc::fork() } {
+		-1 => Err(io::Error::last_os_error()),
+		0 => Ok(()),
+		_ => process::exit(0),
+	}
+}
+
+fn redirect(file: &File, fd: libc::c_int) -> io::Result<()> {
+	if unsafe { libc::dup2(file.as_raw_fd(), fd) } == -1 {
+		Err(io::Error::last_os_error())
+	} else {
+		Ok(())
+	}
+}
+
+fn open_log(log_path: &Path) -> io::Result<File> {
+	OpenOptions::new().append(true).create(true).open(log_path)
+}
+
+fn path_to_c_string(path: &Path) -> io::Result<CString> {
+	CString::new(path.as_os_str().as_bytes())
+		.map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "path contains a null byte"))
+}
+
+/// Points stderr at a log file, appending to it.
+pub fn redirect_stderr(log_path: &Path) -> io::Result<()> {
+	redirect(&open_log(log_path)?, libc::STDERR_FILENO)
+}
+
+/// Writes the current process’s id to a file.
+pub fn write_pid_file(pid_path: &Path) -> io::Result<()> {
+	writeln!(File::create(pid_path)?, "{}", process::id())
+}
+
+/// Changes the root directory and moves into it, so relative paths are relative to the new root.
+pub fn change_root(root: &Path) -> io::Result<()> {
+	let root = path_to_c_string(root)?;
+
+	if unsafe { libc::chroot(root.as_ptr()) } == -1 || unsafe { libc::chdir(b"/\0".as_ptr() as *const libc::c_char) } == -1 {
+		return Err(io::Error::last_os_error());
+	}
+
+	Ok(())
+}
+
+/// A file that’s removed when this is dropped. It’s found through its directory, which is opened up front, so it can still be removed after changing root.
+pub struct RemovableFile {
+	directory: File,
+	name: CString,
+}
+
+impl RemovableFile {
+	pub fn new(path: &Path) -> io::Result<Self> {
+		let name = path.file_name()
+			.ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "path doesn’t name a file"))?;
+
+		let directory =
+			match path.parent() {
+				Some(parent) if parent != Path::new("") => File::open(parent)?,
+				_ => File::open(".")?,
+			};
+
+		Ok(Self {
+			directory,
+			name: path_to_c_string(Path::new(name))?,
+		})
+	}
+
+	#[cfg(all(target_os = "linux", feature = "sandbox"))]
+	pub fn directory(&self) -> &File {
+		&self.directory
+	}
+}
+
+impl Drop for RemovableFile {
+	fn drop(&mut self) {
+		if unsafe { libc::unlinkat(self.directory.as_raw_fd(), self.name.as_ptr(), 0) } == -1 {
+			error!(file = ?self.name, error = %io::Error::last_os_error(), "failed to remove file");
+		}
+	}
+}
+
+/// The files needed to daemonize, opened before anything (like changing root) can make them inaccessible.
+pub struct Daemonizer {
+	null: File,
+	log: File,
+	pid_file: Option<File>,
+}
+
+impl Daemonizer {
+	/// Opens `/dev/null`, the log file if there is one, and the PID file if there is one, so that errors are reported to whoever started the daemon.
+	pub fn prepare(pid_path: Option<&Path>, log_path: Option<&Path>) -> io::Result<Self> {
+		let null = OpenOptions::new().read(true).write(true).open("/dev/null")?;
+		let log =
+			match log_path {
+				Some(path) => open_log(path)?,
+				None => null.try_clone()?,
+			};
+		let pid_file =
+			match pid_path {
+				Some(path) => Some(File::create(path)?),
+				None => None,
+			};
+
+		Ok(Self { null, log, pid_file })
+	}
+
+	/// Detaches from the controlling terminal with the traditional double fork and `setsid`, writes the final process’s id to the PID file, and sends stderr to the log file (or discards it if there isn’t one). Must be called before any threads are started. Doesn’t change the working directory, so relative paths keep working.
+	pub fn detach(self) -> io::Result<()> {
+		let Self { null, log, mut pid_file } = self;
+
+		fork_and_exit_parent()?;
+
+		if unsafe { libc::setsid() } == -1 {
+			return Err(io::Error::last_os_error());
+		}
+
+		// The session leader exits, so the daemon can never reacquire a controlling terminal.
+		fork_and_exit_parent()?;
+
+		if let Some(pid_file) = &mut pid_file {
+			writeln!(pid_file, "{}", process::id())?;
+		}
+
+		redirect(&null, libc::STDIN_FILENO)?;
+		redirect(&null, libc::STDOUT_FILENO)?;
+		redirect(&log, libc::STDERR_FILENO)?;
+
+		Ok(())
+	}
+}
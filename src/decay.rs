@@ -0,0 +1,165 @@
+use std::collections::BTreeMap;
+
+use super::address::{ADDRESS_BITS, Address, AddressPrefix};
+use super::time_list::{CoarseDuration, CoarseSystemTime};
+use super::tree::{OPERATION_TYPES, OperationType, PrefixLevels, SpamStats, estimated_btree_bytes};
+
+/// How many half-lives can pass after the reference time before weights are rescaled, keeping them well within the range of an `f64`.
+const REBASE_HALF_LIVES: f64 = 256.0;
+
+/// The position of a type’s weight in `Weights::sums` and `WeightStats`, which is its position in `OperationType::ALL`.
+fn index(type_: OperationType) -> usize {
+	match type_ {
+		OperationType::Trust => 0,
+		OperationType::Spam => 1,
+		OperationType::Abuse => 2,
+		OperationType::Phishing => 3,
+		OperationType::Bruteforce => 4,
+		OperationType::AutomatedTrust => 5,
+		OperationType::AutomatedSpam => 6,
+	}
+}
+
+/// The decayed weight of each type of entry for a prefix, as of the reference time.
+#[derive(Clone, Debug)]
+struct Weights {
+	/// The number of entries contributing, so that the weights can be removed along with the last one despite rounding.
+	entries: u32,
+	sums: [f64; OPERATION_TYPES],
+}
+
+/// The weights of entries for each type, decayed to the time of a query.
+#[derive(Clone, Debug, PartialEq)]
+pub struct WeightStats(pub [f64; OPERATION_TYPES]);
+
+impl WeightStats {
+	/// Treats counts as weights that haven’t decayed, for results that don’t come from entries.
+	pub fn from_counts(stats: &SpamStats) -> Self {
+		let mut result = [0.0; OPERATION_TYPES];
+
+		for &type_ in &OperationType::ALL {
+			result[index(type_)] = f64::from(stats.users(type_));
+		}
+
+		Self(result)
+	}
+
+	/// Keeps only the weights of trust entries.
+	pub fn trusted_only(&self) -> Self {
+		let mut result = [0.0; OPERATION_TYPES];
+
+		for &type_ in &[OperationType::Trust, OperationType::AutomatedTrust] {
+			result[index(type_)] = self.0[index(type_)];
+		}
+
+		Self(result)
+	}
+}
+
+/// Weights of entries that halve every half-life instead of counting fully until they expire. An entry’s weight is stored as 2^(hours since the reference time ÷ half-life), which only needs scaling by the time since the reference time to get its current weight, so nothing has to be updated as time passes.
+#[derive(Clone, Debug)]
+pub struct DecayedWeights {
+	half_life_hours: f64,
+	reference: CoarseSystemTime,
+	weights: BTreeMap<AddressPrefix, Weights>,
+}
+
+impl DecayedWeights {
+	pub fn new(half_life: CoarseDuration) -> Self {
+		Self {
+			half_life_hours: f64::from(half_life.units()),
+			reference: CoarseSystemTime::from_epoch_hours(0),
+			weights: BTreeMap::new(),
+		}
+	}
+
+	fn half_lives_since_reference(&self, time: CoarseSystemTime) -> f64 {
+		(f64::from(time.epoch_hours()) - f64::from(self.reference.epoch_hours())) / self.half_life_hours
+	}
+
+	/// Moves the reference time to `now`, rescaling every weight to match.
+	fn rebase(&mut self, now: CoarseSystemTime) {
+		let scale = (-self.half_lives_since_reference(now)).exp2();
+
+		for weights in self.weights.values_mut() {
+			for sum in &mut weights.sums {
+				*sum *= scale;
+			}
+		}
+
+		self.reference = now;
+	}
+
+	/// Adds an entry’s weight to each prefix of an address with a size in `levels`.
+	pub fn add(&mut self, address: &Address, levels: PrefixLevels, type_: OperationType, time: CoarseSystemTime) {
+		if self.weights.is_empty() {
+			self.reference = time;
+		} else if self.half_lives_since_reference(time) > REBASE_HALF_LIVES {
+			self.rebase(time);
+		}
+
+		let weight = self.half_lives_since_reference(time).exp2();
+		let mut prefix = address.prefix(ADDRESS_BITS);
+
+		for bits in levels.sizes().rev() {
+			prefix.shorten_to(bits);
+
+			let weights = self.weights.entry(prefix.clone()).or_insert(Weights {
+				entries: 0,
+				sums: [0.0; OPERATION_TYPES],
+			});
+
+			weights.entries += 1;
+			weights.sums[index(type_)] += weight;
+		}
+	}
+
+	/// Removes the weight added for an entry by `add` with the same arguments from the prefixes it was `counted` for, which leaves out ones pruned since.
+	pub fn remove(&mut self, address: &Address, levels: PrefixLevels, type_: OperationType, time: CoarseSystemTime, counted: impl Fn(&AddressPrefix) -> bool) {
+		let weight = self.half_lives_since_reference(time).exp2();
+		let mut prefix = address.prefix(ADDRESS_BITS);
+
+		for bits in levels.sizes().rev() {
+			prefix.shorten_to(bits);
+
+			if !counted(&prefix) {
+				continue;
+			}
+
+			match self.weights.get_mut(&prefix) {
+				Some(weights) if weights.entries > 1 => {
+					weights.entries -= 1;
+					weights.sums[index(type_)] -= weight;
+				}
+				Some(_) => {
+					self.weights.remove(&prefix);
+				}
+				None => panic!("Address unexpectedly missing from weights"),
+			}
+		}
+	}
+
+	pub fn estimated_bytes(&self) -> usize {
+		estimated_btree_bytes(&self.weights)
+	}
+
+	/// Removes a prefix pruned from the tree.
+	pub fn prune(&mut self, prefix: &AddressPrefix) {
+		self.weights.remove(prefix);
+	}
+
+	/// Gets the weights for a prefix as of a time.
+	pub fn get(&self, prefix: &AddressPrefix, now: CoarseSystemTime) -> WeightStats {
+		let scale = (-self.half_lives_since_reference(now)).exp2();
+		let mut result = [0.0; OPERATION_TYPES];
+
+		if let Some(weights) = self.weights.get(prefix) {
+			for (weight, sum) in result.iter_mut().zip(&weights.sums) {
+				// Subtracting expired weights can leave slightly negative rounding errors.
+				*weight = (sum * scale).max(0.0);
+			}
+		}
+
+		WeightStats(result)
+	}
+}
@@ -0,0 +1,109 @@
+use std::mem;
+
+use super::tree::User;
+
+/// How many users are counted exactly before switching to an estimate.
+const EXACT_LIMIT: usize = 64;
+
+/// The number of bits of a hash that pick a register. 2^8 registers give a standard error of about 6.5%.
+const REGISTER_BITS: u32 = 8;
+
+const REGISTERS: usize = 1 << REGISTER_BITS;
+
+/// Mixes a user’s number into a well-distributed 64-bit hash (SplitMix64’s finalizer).
+fn hash(user: User) -> u64 {
+	let mut x = u64::from(u32::from_be_bytes(user.to_bytes())).wrapping_add(0x9e37_79b9_7f4a_7c15);
+	x = (x ^ (x >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+	x = (x ^ (x >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+	x ^ (x >> 31)
+}
+
+/// The distinct users with entries for a prefix.
+#[derive(Clone, Debug)]
+pub enum DistinctUsers {
+	/// Each user and their number of entries, so that users can be removed along with their last entry.
+	Exact(Vec<(User, u32)>),
+	/// A HyperLogLog sketch, for when there are too many users to count exactly. Users can’t be removed from it, so the estimate doesn’t go down until the prefix’s entries are all gone.
+	Estimated(Box<[u8]>),
+}
+
+impl DistinctUsers {
+	pub fn new() -> Self {
+		Self::Exact(Vec::new())
+	}
+
+	fn add_to_sketch(registers: &mut [u8], user: User) {
+		let hash = hash(user);
+		let register = (hash >> (64 - REGISTER_BITS)) as usize;
+		let rank = ((hash << REGISTER_BITS).leading_zeros() + 1).min(64 - REGISTER_BITS + 1) as u8;
+
+		registers[register] = registers[register].max(rank);
+	}
+
+	pub fn add(&mut self, user: User) {
+		match self {
+			Self::Exact(users) => {
+				match users.iter().position(|(u, _)| *u == user) {
+					Some(index) => users[index].1 += 1,
+					None if users.len() < EXACT_LIMIT => users.push((user, 1)),
+					None => {
+						let mut registers = vec![0; REGISTERS].into_boxed_slice();
+
+						for &(u, _) in users.iter() {
+							Self::add_to_sketch(&mut registers, u);
+						}
+
+						Self::add_to_sketch(&mut registers, user);
+						*self = Self::Estimated(registers);
+					}
+				}
+			}
+			Self::Estimated(registers) => Self::add_to_sketch(registers, user),
+		}
+	}
+
+	/// Removes one of a user’s entries, if the users are still counted exactly and the user has any.
+	pub fn remove(&mut self, user: User) {
+		if let Self::Exact(users) = self {
+			if let Some(index) = users.iter().position(|(u, _)| *u == user) {
+				if users[index].1 > 1 {
+					users[index].1 -= 1;
+				} else {
+					users.swap_remove(index);
+				}
+			}
+		}
+	}
+
+	/// Gets the number of distinct users, or an estimate of it.
+	pub fn count(&self) -> u32 {
+		match self {
+			Self::Exact(users) => users.len() as u32,
+			Self::Estimated(registers) => {
+				let m = REGISTERS as f64;
+				let alpha = 0.7213 / (1.0 + 1.079 / m);
+				let sum: f64 = registers.iter().map(|&rank| (-f64::from(rank)).exp2()).sum();
+				let estimate = alpha * m * m / sum;
+				let zeros = registers.iter().filter(|&&rank| rank == 0).count();
+
+				// Linear counting is more accurate for small estimates.
+				let estimate =
+					if estimate <= 2.5 * m && zeros != 0 {
+						m * (m / zeros as f64).ln()
+					} else {
+						estimate
+					};
+
+				estimate.round() as u32
+			}
+		}
+	}
+
+	/// Gets the memory allocated outside the value itself, in bytes.
+	pub fn allocated_bytes(&self) -> usize {
+		match self {
+			Self::Exact(users) => users.capacity() * mem::size_of::<(User, u32)>(),
+			Self::Estimated(registers) => registers.len(),
+		}
+	}
+}
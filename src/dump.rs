@@ -0,0 +1,102 @@
+use std::io::{self, Write};
+use std::process;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use super::address::AddressPrefix;
+use super::connections::{ConnectionStats, UNKNOWN_UID};
+use super::latency::{Latencies, PERCENTILES};
+use super::tree::{MemoryUsage, OperationType, SpamStats, TreeSize, WindowStats};
+
+/// The number of networks with the most entries to list.
+pub const TOP_NETWORKS: usize = 20;
+
+/// The reasons connections end, in the order of `Disconnect`’s variants.
+const DISCONNECT_NAMES: [&str; 4] = ["the client", "an error", "an idle timeout", "shutting down"];
+
+/// The state of a running daemon, written as text on SIGUSR1 to debug it without attaching a debugger.
+pub struct Diagnostics<'a> {
+	pub config: &'a [(&'static str, String)],
+	/// The current values of settings that can change while running, which `config` has the startup values of.
+	pub tunables: &'a [(&'static str, String)],
+	pub size: TreeSize,
+	pub memory: MemoryUsage,
+	pub connection_buffer_bytes: usize,
+	pub windows: WindowStats,
+	pub top_networks: Vec<(AddressPrefix, SpamStats)>,
+	pub connections: &'a ConnectionStats,
+	pub latencies: &'a Latencies,
+}
+
+impl Diagnostics<'_> {
+	pub fn write(&self, out: &mut impl Write) -> io::Result<()> {
+		let now = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |since_epoch| since_epoch.as_secs());
+		writeln!(out, "iptooled {} diagnostic dump at {} (Unix time)", process::id(), now)?;
+
+		writeln!(out, "configuration:")?;
+
+		for (name, value) in self.config {
+			writeln!(out, "  {} = {}", name, value)?;
+		}
+
+		writeln!(out, "tunables:")?;
+
+		for (name, value) in self.tunables {
+			writeln!(out, "  {} = {}", name, value)?;
+		}
+
+		writeln!(out, "tree:")?;
+		writeln!(out, "  prefixes: {}", self.size.prefixes)?;
+		writeln!(out, "  users: {}", self.size.users)?;
+		writeln!(out, "  estimated bytes: {}", self.size.estimated_bytes)?;
+
+		writeln!(out, "memory (estimated bytes):")?;
+
+		for (name, bytes) in self.memory.parts().iter() {
+			writeln!(out, "  {}: {}", name, bytes)?;
+		}
+
+		writeln!(out, "  connection_buffers: {}", self.connection_buffer_bytes)?;
+
+		writeln!(out, "windows:")?;
+
+		for (name, window) in &[("user", &self.windows.user_window), ("address", &self.windows.address_window), ("velocity", &self.windows.velocity_window)] {
+			writeln!(out, "  {}: {} entries, {} bytes, at most {} entries", name, window.entries, window.bytes, window.max_entries)?;
+		}
+
+		writeln!(out, "  clamped times: {}", self.windows.clamped_times)?;
+
+		writeln!(out, "connections:")?;
+		writeln!(out, "  accepted: {}", self.connections.accepted())?;
+		writeln!(out, "  active: {}", self.connections.active())?;
+
+		for (name, count) in DISCONNECT_NAMES.iter().zip(self.connections.disconnects()) {
+			writeln!(out, "  ended by {}: {}", name, count)?;
+		}
+
+		writeln!(out, "  throttled requests: {}", self.connections.throttled())?;
+
+		for (&uid, requests) in self.connections.requests_by_uid() {
+			match uid {
+				UNKNOWN_UID => writeln!(out, "  requests from an unknown user: {}", requests)?,
+				uid => writeln!(out, "  requests from uid {}: {}", uid, requests)?,
+			}
+		}
+
+		writeln!(out, "latency in microseconds (count, p50, p90, p99, p99.9, max):")?;
+
+		for (kind, histogram) in self.latencies.iter() {
+			let percentiles: Vec<String> = PERCENTILES.iter().map(|&per_mille| histogram.percentile(per_mille).to_string()).collect();
+			writeln!(out, "  {}: {}, {}, {}", kind.name(), histogram.count(), percentiles.join(", "), histogram.max())?;
+		}
+
+		let type_names: Vec<&str> = OperationType::ALL.iter().map(|type_| type_.name()).collect();
+		writeln!(out, "networks with the most entries ({}):", type_names.join(", "))?;
+
+		for (prefix, stats) in &self.top_networks {
+			let counts: Vec<String> = OperationType::ALL.iter().map(|&type_| stats.users(type_).to_string()).collect();
+			writeln!(out, "  {}: {}", prefix, counts.join(", "))?;
+		}
+
+		out.flush()
+	}
+}
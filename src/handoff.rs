@@ -0,0 +1,132 @@
+use std::fs;
+use std::io::{self, ErrorKind, Read};
+use std::mem;
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+use std::os::unix::net::{UnixListener as StdUnixListener, UnixStream as StdUnixStream};
+use std::path::Path;
+use std::ptr;
+use tokio::net::{UnixListener, UnixStream};
+use tracing::{error, info};
+
+/// The space needed for a control message carrying one file descriptor.
+const FD_CONTROL_BYTES: usize = 64;
+
+/// Sends a file descriptor as an `SCM_RIGHTS` control message, along with a single byte, since some systems don’t deliver control messages without data.
+fn send_fd(socket: RawFd, fd: RawFd) -> io::Result<()> {
+	let mut data = [0_u8];
+	let mut control = [0_u8; FD_CONTROL_BYTES];
+
+	unsafe {
+		let mut iov = libc::iovec {
+			iov_base: data.as_mut_ptr() as *mut libc::c_void,
+			iov_len: data.len(),
+		};
+
+		let mut message: libc::msghdr = mem::zeroed();
+		message.msg_iov = &mut iov;
+		message.msg_iovlen = 1;
+		message.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+		message.msg_controllen = libc::CMSG_SPACE(mem::size_of::<RawFd>() as u32) as _;
+
+		let header = libc::CMSG_FIRSTHDR(&message);
+		(*header).cmsg_level = libc::SOL_SOCKET;
+		(*header).cmsg_type = libc::SCM_RIGHTS;
+		(*header).cmsg_len = libc::CMSG_LEN(mem::size_of::<RawFd>() as u32) as _;
+		ptr::write_unaligned(libc::CMSG_DATA(header) as *mut RawFd, fd);
+
+		if libc::sendmsg(socket, &message, 0) == -1 {
+			return Err(io::Error::last_os_error());
+		}
+	}
+
+	Ok(())
+}
+
+/// Receives a file descriptor sent by `send_fd`, blocking until it arrives.
+fn receive_fd(socket: RawFd) -> io::Result<RawFd> {
+	let mut data = [0_u8];
+	let mut control = [0_u8; FD_CONTROL_BYTES];
+
+	unsafe {
+		let mut iov = libc::iovec {
+			iov_base: data.as_mut_ptr() as *mut libc::c_void,
+			iov_len: data.len(),
+		};
+
+		let mut message: libc::msghdr = mem::zeroed();
+		message.msg_iov = &mut iov;
+		message.msg_iovlen = 1;
+		message.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+		message.msg_controllen = control.len() as _;
+
+		if libc::recvmsg(socket, &mut message, libc::MSG_CMSG_CLOEXEC) == -1 {
+			return Err(io::Error::last_os_error());
+		}
+
+		let header = libc::CMSG_FIRSTHDR(&message);
+
+		if header.is_null() || (*header).cmsg_level != libc::SOL_SOCKET || (*header).cmsg_type != libc::SCM_RIGHTS {
+			return Err(io::Error::new(ErrorKind::InvalidData, "the running daemon didn’t send a socket"));
+		}
+
+		Ok(ptr::read_unaligned(libc::CMSG_DATA(header) as *const RawFd))
+	}
+}
+
+/// The connection to the daemon being taken over from.
+pub struct Takeover(StdUnixStream);
+
+impl Takeover {
+	/// Waits for the old daemon to exit, which closes the connection. It only exits after flushing its operation log.
+	pub fn wait(mut self) -> io::Result<()> {
+		let mut buffer = [0; 1];
+
+		while self.0.read(&mut buffer)? != 0 {}
+
+		Ok(())
+	}
+}
+
+/// Takes over the listening socket of the daemon at a handoff path, if there is one. That daemon stops accepting connections and starts shutting down.
+pub fn take_over(handoff_path: &Path) -> io::Result<Option<(StdUnixListener, Takeover)>> {
+	let stream =
+		match StdUnixStream::connect(handoff_path) {
+			Ok(stream) => stream,
+			Err(err) if err.kind() == ErrorKind::NotFound || err.kind() == ErrorKind::ConnectionRefused => return Ok(None),
+			Err(err) => return Err(err),
+		};
+
+	let fd = receive_fd(stream.as_raw_fd())?;
+	info!("took over from the running daemon");
+
+	Ok(Some((unsafe { StdUnixListener::from_raw_fd(fd) }, Takeover(stream))))
+}
+
+/// Binds the handoff socket, replacing the one left behind by a daemon that was taken over from or that didn’t exit cleanly.
+pub fn bind(handoff_path: &Path) -> io::Result<StdUnixListener> {
+	match fs::remove_file(handoff_path) {
+		Err(err) if err.kind() != ErrorKind::NotFound => return Err(err),
+		_ => {}
+	}
+
+	StdUnixListener::bind(handoff_path)
+}
+
+/// Waits for a new daemon to connect to the handoff socket and sends it the listening socket. The connection has to be kept open until the operation log has been flushed.
+pub async fn hand_off(mut handoff: UnixListener, listener_fd: RawFd) -> UnixStream {
+	loop {
+		let result =
+			match handoff.accept().await {
+				Ok((stream, _)) => send_fd(stream.as_raw_fd(), listener_fd).map(|()| stream),
+				Err(err) => Err(err),
+			};
+
+		match result {
+			Ok(stream) => {
+				info!("handing off to a new daemon");
+				return stream;
+			}
+			Err(err) => error!(error = %err, "handoff failed"),
+		}
+	}
+}
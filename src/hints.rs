@@ -0,0 +1,83 @@
+use std::collections::{BTreeMap, HashMap};
+
+use super::address::{Address, AddressPrefix, IPV4_OFFSET_BITS};
+
+/// The longest hint, in bytes, which is the longest a domain name can be.
+pub const MAX_HINT_BYTES: usize = 253;
+
+/// The most hints to keep for each network. A new hint for a full network replaces its least common one and takes over its count, so hints that keep coming up work their way in.
+const HINTS_PER_NETWORK: usize = 4;
+
+/// The most networks to keep hints for before starting over.
+const NETWORK_LIMIT: usize = 1 << 16;
+
+/// The size of the networks hints are kept for, which is usually one host or site’s.
+const NETWORK_BITS: u8 = 64;
+
+/// The same for IPv4 addresses, relative to the IPv4 address.
+const IPV4_NETWORK_BITS: u8 = 24;
+
+/// Checks whether a hint looks like a domain name: letters, digits, hyphens, underscores, and dots.
+pub fn is_valid_hint(hint: &str) -> bool {
+	hint.len() <= MAX_HINT_BYTES && !hint.is_empty() && hint.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_' || b == b'.')
+}
+
+/// Gets the network of an address that its hints are kept for.
+fn network(address: &Address) -> AddressPrefix {
+	address.prefix(if address.is_ipv4() { IPV4_OFFSET_BITS + IPV4_NETWORK_BITS } else { NETWORK_BITS })
+}
+
+/// The domain names, like HELO or reverse DNS names, sent with spam reports, and how many times each was sent for each network. They’re only kept in memory.
+#[derive(Debug, Default)]
+pub struct Hints(BTreeMap<AddressPrefix, Vec<(String, u32)>>);
+
+impl Hints {
+	/// Counts a hint for an address’s network.
+	pub fn add(&mut self, address: &Address, hint: &str) {
+		let network = network(address);
+
+		if self.0.len() >= NETWORK_LIMIT && !self.0.contains_key(&network) {
+			self.0.clear();
+		}
+
+		let hints = self.0.entry(network).or_insert_with(Vec::new);
+
+		match hints.iter().position(|(h, _)| h == hint) {
+			Some(index) => {
+				hints[index].1 += 1;
+			}
+			None if hints.len() < HINTS_PER_NETWORK => {
+				hints.push((hint.to_owned(), 1));
+			}
+			None => {
+				let least = hints.iter_mut().min_by_key(|(_, count)| *count).unwrap();
+				*least = (hint.to_owned(), least.1 + 1);
+			}
+		}
+	}
+
+	/// Gets the most common hints for the networks within a prefix of an address, or for the address’s network if the prefix is longer or there isn’t one, most common first.
+	pub fn most_common(&self, address: &Address, prefix_bits: u8) -> Vec<(&str, u32)> {
+		let network = network(address);
+		let prefix =
+			if prefix_bits == 0 || prefix_bits >= network.bits() {
+				network
+			} else {
+				address.prefix(prefix_bits)
+			};
+
+		let mut totals: HashMap<&str, u32> = HashMap::new();
+
+		// Prefixes sort right before the prefixes they contain.
+		for (_, hints) in self.0.range(&prefix..).take_while(|(key, _)| prefix.contains(key)) {
+			for (hint, count) in hints {
+				*totals.entry(hint).or_insert(0) += count;
+			}
+		}
+
+		let mut result: Vec<(&str, u32)> = totals.into_iter().collect();
+		result.sort_unstable_by(|(a, a_count), (b, b_count)| b_count.cmp(a_count).then(a.cmp(b)));
+		result.truncate(HINTS_PER_NETWORK);
+		result
+	}
+}
@@ -0,0 +1,156 @@
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fs;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+use super::address::Address;
+use super::cli::ReplayOptions;
+use super::persist::{LOG_FILE_NAME, OPERATION_BYTES, OperationLog, SerializedTreeOperation, read_records};
+use super::time_list::CoarseSystemTime;
+use super::tree::{Change, Operation, OperationType, QueryResult, Retraction, SpamTree, TreeOperation, TreeSettings};
+
+fn read_log(persist_path: &Path) -> io::Result<Vec<u8>> {
+	fs::read(persist_path.join(LOG_FILE_NAME))
+}
+
+/// Prints each operation in a log as [*time*, *type*, *address*, *user*], tab-separated, where *time* is in seconds since the Unix epoch. Retractions have types prefixed with `retract-`, and forgotten users have the type `forget` and the address `-`.
+pub fn dump(persist_path: &Path) -> Result<(), Box<dyn Error>> {
+	let contents = read_log(persist_path)?;
+	let stdout = io::stdout();
+	let mut output = BufWriter::new(stdout.lock());
+
+	for (i, record) in read_records(&contents)?.enumerate() {
+		let (operation, time) =
+			SerializedTreeOperation::from_slice(record).parse()
+				.ok_or_else(|| format!("invalid operation at index {}", i))?;
+
+		let seconds = time.duration_since_epoch().as_secs();
+
+		match operation {
+			TreeOperation::Perform(Operation(type_, address, user)) => writeln!(output, "{}\t{}\t{}\t{}", seconds, type_.name(), address, user)?,
+			TreeOperation::Retract(Retraction::Report(type_, address, user)) => writeln!(output, "{}\tretract-{}\t{}\t{}", seconds, type_.name(), address, user)?,
+			TreeOperation::Retract(Retraction::User(user)) => writeln!(output, "{}\tforget\t-\t{}", seconds, user)?,
+		}
+	}
+
+	output.flush()?;
+	Ok(())
+}
+
+/// Checks that every operation in a log is valid and that replaying it won’t fail on out-of-order times, printing a summary.
+pub fn verify(persist_path: &Path) -> Result<(), Box<dyn Error>> {
+	let contents = read_log(persist_path)?;
+	let records = read_records(&contents)?;
+	let incomplete = records.remainder().len();
+	let mut counts = BTreeMap::new();
+	let mut retractions = 0_u64;
+	let mut latest = None;
+
+	for (i, record) in records.enumerate() {
+		let (operation, time) =
+			SerializedTreeOperation::from_slice(record).parse()
+				.ok_or_else(|| format!("invalid operation at index {}", i))?;
+
+		match operation {
+			TreeOperation::Perform(Operation(type_, _, _)) => *counts.entry(type_).or_insert(0_u64) += 1,
+			TreeOperation::Retract(_) => retractions += 1,
+		}
+
+		// Replaying tolerates times up to an hour earlier than the latest one, for clock adjustments.
+		match latest {
+			Some(latest) if time.epoch_hours() + 1 < latest => {
+				return Err(format!("operation at index {} is more than an hour earlier than one before it", i).into());
+			}
+			Some(latest) if time.epoch_hours() <= latest => {}
+			_ => latest = Some(time.epoch_hours()),
+		}
+	}
+
+	let summary: Vec<String> =
+		OperationType::ALL.iter()
+			.map(|type_| format!("{} {}", counts.get(type_).unwrap_or(&0), type_.name()))
+			.collect();
+
+	println!("{} operations, {} retractions", summary.join(", "), retractions);
+
+	if incomplete != 0 {
+		println!("incomplete operation at end ({} of {} bytes), which will be discarded on startup", incomplete, OPERATION_BYTES);
+	}
+
+	Ok(())
+}
+
+/// Prints the changes that turn the tree from one log into the tree from another as [*+ or -*, *time*, *type*, *address*, *user*] lines, where *user* is `-` for entries that no longer count toward a user.
+pub fn diff(persist_path: &Path, other_persist_path: &Path) -> Result<(), Box<dyn Error>> {
+	let mut tree = SpamTree::new(TreeSettings::DEFAULT);
+	let mut other = SpamTree::new(TreeSettings::DEFAULT);
+	OperationLog::replay_file(&persist_path.join(LOG_FILE_NAME), &mut tree)?;
+	OperationLog::replay_file(&other_persist_path.join(LOG_FILE_NAME), &mut other)?;
+
+	let stdout = io::stdout();
+	let mut output = BufWriter::new(stdout.lock());
+
+	for change in tree.diff(&mut other, CoarseSystemTime::now()) {
+		let (sign, entry) =
+			match change {
+				Change::Add(entry) => ('+', entry),
+				Change::Remove(entry) => ('-', entry),
+			};
+
+		let user = entry.user.map_or_else(|| "-".to_string(), |user| user.to_string());
+		writeln!(output, "{}\t{}\t{}\t{}\t{}", sign, entry.time.duration_since_epoch().as_secs(), entry.type_.name(), entry.address, user)?;
+	}
+
+	output.flush()?;
+	Ok(())
+}
+
+/// Prints a line of `replay` results for each address, as of a time.
+fn print_results(output: &mut impl Write, tree: &mut SpamTree, addresses: &[Address], time: CoarseSystemTime) -> io::Result<()> {
+	for address in addresses {
+		let QueryResult { stats, prefix_bits, .. } = tree.query(address, time);
+		writeln!(output, "{}\t{}\t{}\t{}\t{}", time.duration_since_epoch().as_secs(), address, stats.trusted_users, stats.spam_users, prefix_bits)?;
+	}
+
+	Ok(())
+}
+
+/// Replays a log with the clock following the times of its operations instead of the system’s, printing [*time*, *address*, *trusted*, *spam*, *prefix bits*] lines for each address every interval of log time from the first operation, and at the time of the last one, to see how their results changed.
+pub fn replay(options: &ReplayOptions) -> Result<(), Box<dyn Error>> {
+	let contents = read_log(&options.persist_path)?;
+	let mut tree = SpamTree::new(TreeSettings::DEFAULT);
+	let stdout = io::stdout();
+	let mut output = BufWriter::new(stdout.lock());
+	let mut next_print = None;
+	let mut latest = None;
+
+	for (i, record) in read_records(&contents)?.enumerate() {
+		let (operation, time) =
+			SerializedTreeOperation::from_slice(record).parse()
+				.ok_or_else(|| format!("invalid operation at index {}", i))?;
+
+		// Print the results as of each interval that ends before this operation.
+		let mut next = next_print.unwrap_or(time);
+
+		while next < time {
+			print_results(&mut output, &mut tree, &options.addresses, next)?;
+			next = next.saturating_add(options.interval);
+		}
+
+		next_print = Some(next);
+		latest = Some(latest.map_or(time, |latest: CoarseSystemTime| latest.max(time)));
+
+		match operation {
+			TreeOperation::Perform(operation) => tree.perform(operation, time),
+			TreeOperation::Retract(retraction) => tree.retract(&retraction, time),
+		};
+	}
+
+	if let Some(latest) = latest {
+		print_results(&mut output, &mut tree, &options.addresses, latest)?;
+	}
+
+	output.flush()?;
+	Ok(())
+}
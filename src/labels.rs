@@ -0,0 +1,78 @@
+use std::fs;
+use std::io::{self, ErrorKind};
+use std::path::Path;
+
+use super::address::{Address, AddressPrefix};
+use super::prefix_map::PrefixMap;
+
+/// The name of the file of labels within the persistence directory.
+pub const LABELS_FILE_NAME: &str = "labels";
+
+/// The longest label, in bytes, so its length fits in a byte.
+pub const MAX_LABEL_BYTES: usize = 255;
+
+/// Checks whether a label can be stored: it has to fit on a line of the labels file.
+pub fn is_valid_label(label: &str) -> bool {
+	label.len() <= MAX_LABEL_BYTES && !label.is_empty() && label.trim() == label && !label.contains(char::is_control)
+}
+
+/// Notes attached to prefixes by an administrator, like “corp VPN”. Like overrides, they can nest, and the longest matching prefix applies.
+#[derive(Clone, Debug, Default)]
+pub struct Labels(PrefixMap<String>);
+
+impl Labels {
+	/// Reads labels saved by `write`, or none if the file doesn’t exist yet.
+	pub fn read(path: &Path) -> io::Result<Self> {
+		let contents =
+			match fs::read_to_string(path) {
+				Ok(contents) => contents,
+				Err(err) if err.kind() == ErrorKind::NotFound => return Ok(Self::default()),
+				Err(err) => return Err(err),
+			};
+
+		let mut result = PrefixMap::new();
+
+		for (i, line) in contents.lines().enumerate() {
+			let mut fields = line.splitn(2, ' ');
+
+			match (fields.next().and_then(AddressPrefix::parse), fields.next()) {
+				(Some(prefix), Some(label)) if is_valid_label(label) => {
+					result.insert(prefix, label.to_owned());
+				}
+				_ => return Err(io::Error::new(ErrorKind::InvalidData, format!("line {} of the labels isn’t a prefix and a label", i + 1))),
+			}
+		}
+
+		Ok(Self(result))
+	}
+
+	/// Saves the labels as lines of [*prefix*, *label*], replacing the file atomically.
+	pub fn write(&self, path: &Path) -> io::Result<()> {
+		let mut contents = String::new();
+
+		for (prefix, label) in self.0.iter() {
+			contents.push_str(&format!("{} {}\n", prefix, label));
+		}
+
+		let temporary_path = path.with_extension("new");
+		fs::write(&temporary_path, contents)?;
+		fs::rename(&temporary_path, path)
+	}
+
+	/// Attaches a label to a prefix, or removes its label if the label is `None`.
+	pub fn set(&mut self, prefix: AddressPrefix, label: Option<String>) {
+		match label {
+			Some(label) => {
+				self.0.insert(prefix, label);
+			}
+			None => {
+				self.0.remove(&prefix);
+			}
+		}
+	}
+
+	/// Finds the longest labelled prefix containing an address.
+	pub fn find(&self, address: &Address) -> Option<(&AddressPrefix, &str)> {
+		self.0.longest_match(address).map(|(prefix, label)| (prefix, label.as_str()))
+	}
+}
@@ -0,0 +1,182 @@
+#[cfg(test)]
+mod tests;
+
+use std::time::Duration;
+
+use super::protocol::Request;
+
+/// Each power of two is split into 2^`SUB_BUCKET_BITS` buckets, so recorded values are within 1/16 (6%) of the actual ones.
+const SUB_BUCKET_BITS: u32 = 4;
+
+const SUB_BUCKETS: usize = 1 << SUB_BUCKET_BITS;
+
+/// Values are recorded up to 2^`VALUE_BITS` − 1 microseconds, over an hour, and longer ones are recorded as that.
+const VALUE_BITS: u32 = 32;
+
+const MAX_VALUE: u64 = (1 << VALUE_BITS) - 1;
+
+const BUCKETS: usize = (VALUE_BITS - SUB_BUCKET_BITS + 1) as usize * SUB_BUCKETS;
+
+/// The percentiles reported for each kind of request, in parts per thousand.
+pub const PERCENTILES: [u32; 4] = [500, 900, 990, 999];
+
+/// Gets the bucket a value is counted in: values below `SUB_BUCKETS` get their own, and larger ones share one with the values that have the same `SUB_BUCKET_BITS` + 1 most significant bits.
+fn bucket(value: u64) -> usize {
+	if value < SUB_BUCKETS as u64 {
+		return value as usize;
+	}
+
+	let shift = 63 - value.leading_zeros() - SUB_BUCKET_BITS;
+	(shift as usize + 1) * SUB_BUCKETS + (value >> shift) as usize - SUB_BUCKETS
+}
+
+/// Gets the smallest value counted in a bucket.
+fn lowest_value(bucket: usize) -> u64 {
+	if bucket < SUB_BUCKETS {
+		return bucket as u64;
+	}
+
+	let shift = bucket / SUB_BUCKETS - 1;
+	((SUB_BUCKETS + bucket % SUB_BUCKETS) as u64) << shift
+}
+
+/// Counts of durations in buckets whose width grows with the durations in them, like an HDR histogram, so tail latencies take little memory to track and none to record.
+#[derive(Clone, Debug)]
+pub struct Histogram {
+	counts: Box<[u64; BUCKETS]>,
+	total: u64,
+	/// The longest duration recorded, in microseconds, which is kept exactly up to `MAX_VALUE`.
+	max: u64,
+}
+
+impl Histogram {
+	pub fn new() -> Self {
+		Self {
+			counts: Box::new([0; BUCKETS]),
+			total: 0,
+			max: 0,
+		}
+	}
+
+	pub fn record(&mut self, duration: Duration) {
+		self.record_micros(duration.as_micros() as u64);
+	}
+
+	fn record_micros(&mut self, value: u64) {
+		let value = value.min(MAX_VALUE);
+		self.counts[bucket(value)] += 1;
+		self.total += 1;
+		self.max = self.max.max(value);
+	}
+
+	pub fn count(&self) -> u64 {
+		self.total
+	}
+
+	pub fn max(&self) -> u32 {
+		self.max as u32
+	}
+
+	/// Gets the duration, in microseconds, that a fraction of the recorded durations, in parts per thousand, were at most. It’s rounded up to the end of its bucket, so it’s never less than the actual percentile, and 0 if nothing was recorded.
+	pub fn percentile(&self, per_mille: u32) -> u32 {
+		// The rank of the duration, counting from 1, rounded up.
+		let rank = ((self.total * u64::from(per_mille) + 999) / 1000).max(1);
+		let mut seen = 0;
+
+		for (bucket, count) in self.counts.iter().enumerate() {
+			seen += count;
+
+			if seen >= rank {
+				return (lowest_value(bucket + 1) - 1).min(self.max) as u32;
+			}
+		}
+
+		0
+	}
+}
+
+/// The kinds of requests latencies are tracked for, grouping requests that do similar work.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum RequestKind {
+	Query,
+	Report,
+	/// Retractions, including forgetting a user.
+	Retract,
+	/// Setting overrides and labels.
+	Set,
+	/// Listing overrides, prefixes, and a user’s entries, which take time proportional to the list.
+	List,
+	Stats,
+	/// Keepalives, health checks, shutdowns, flushes, and version requests.
+	Other,
+}
+
+impl RequestKind {
+	pub const ALL: [Self; 7] = [Self::Query, Self::Report, Self::Retract, Self::Set, Self::List, Self::Stats, Self::Other];
+
+	pub fn of(request: &Request) -> Self {
+		match request {
+			Request::Query(..)
+			| Request::CategoryQuery(..)
+			| Request::WeightedQuery(..)
+			| Request::ScoredQuery(..)
+			| Request::ConfidenceQuery(..)
+			| Request::SeenQuery(..)
+			| Request::DistinctQuery(..)
+			| Request::CountryQuery(..)
+			| Request::VerdictQuery(..)
+			| Request::VelocityQuery(..)
+			| Request::LabelledQuery(..)
+			| Request::SourceQuery(..)
+			| Request::HintedQuery(..) => Self::Query,
+			Request::Report(..) | Request::HintedReport(..) => Self::Report,
+			Request::Retract(_) => Self::Retract,
+			Request::SetOverride(..) | Request::SetLabel(..) | Request::SetTunable(..) => Self::Set,
+			Request::ListOverrides | Request::ListPrefixes | Request::ListTunables | Request::UserEntries(_) => Self::List,
+			Request::Stats | Request::StructureStats | Request::WindowStats | Request::LatencyStats | Request::ConnectionStats | Request::MemoryStats => Self::Stats,
+			Request::Keepalive | Request::Shutdown | Request::Health | Request::Flush | Request::Version => Self::Other,
+		}
+	}
+
+	/// Gets the kind’s name, as used in metric names.
+	pub fn name(self) -> &'static str {
+		match self {
+			Self::Query => "query",
+			Self::Report => "report",
+			Self::Retract => "retract",
+			Self::Set => "set",
+			Self::List => "list",
+			Self::Stats => "stats",
+			Self::Other => "other",
+		}
+	}
+}
+
+/// A histogram of the time taken to answer requests for each kind of request.
+#[derive(Clone, Debug)]
+pub struct Latencies {
+	histograms: [Histogram; RequestKind::ALL.len()],
+}
+
+impl Latencies {
+	pub fn new() -> Self {
+		Self {
+			histograms: [Histogram::new(), Histogram::new(), Histogram::new(), Histogram::new(), Histogram::new(), Histogram::new(), Histogram::new()],
+		}
+	}
+
+	pub fn record(&mut self, kind: RequestKind, duration: Duration) {
+		self.histograms[kind as usize].record(duration);
+	}
+
+	/// Iterates over each kind of request and its histogram, in the order of `RequestKind::ALL`.
+	pub fn iter(&self) -> impl Iterator<Item=(RequestKind, &Histogram)> {
+		RequestKind::ALL.iter().copied().zip(&self.histograms)
+	}
+}
+
+impl Default for Latencies {
+	fn default() -> Self {
+		Self::new()
+	}
+}
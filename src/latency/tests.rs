@@ -0,0 +1,55 @@
+use quickcheck::{Arbitrary, Gen};
+use rand::Rng;
+
+use super::{Histogram, SUB_BUCKETS, bucket, lowest_value};
+
+/// A duration in microseconds of any magnitude a histogram records, which `u32`’s arbitrary values, bounded by the generator’s size, aren’t.
+#[derive(Clone, Copy, Debug)]
+struct Micros(u64);
+
+impl Arbitrary for Micros {
+	fn arbitrary<G: Gen>(g: &mut G) -> Self {
+		Self(u64::from(g.gen::<u32>() >> g.gen_range(0, 32)))
+	}
+}
+
+/// A percentile in parts per thousand.
+#[derive(Clone, Copy, Debug)]
+struct PerMille(u32);
+
+impl Arbitrary for PerMille {
+	fn arbitrary<G: Gen>(g: &mut G) -> Self {
+		Self(g.gen_range(0, 1001))
+	}
+}
+
+/// Checks that a value’s bucket covers it, and so that buckets don’t overlap.
+#[quickcheck]
+fn bucket_contains_value(value: Micros) -> bool {
+	let index = bucket(value.0);
+	lowest_value(index) <= value.0 && value.0 < lowest_value(index + 1)
+}
+
+/// Checks percentiles against sorting the values: never below the actual percentile, and above it by at most the precision of the buckets.
+#[quickcheck]
+fn percentiles_are_close(values: Vec<Micros>, per_mille: PerMille) -> bool {
+	let PerMille(per_mille) = per_mille;
+	let mut histogram = Histogram::new();
+	let mut sorted: Vec<u64> = values.iter().map(|value| value.0).collect();
+	sorted.sort();
+
+	for &value in &sorted {
+		histogram.record_micros(value);
+	}
+
+	let result = u64::from(histogram.percentile(per_mille));
+
+	match sorted.len() {
+		0 => result == 0,
+		len => {
+			let rank = ((len as u64 * u64::from(per_mille) + 999) / 1000).max(1);
+			let actual = sorted[rank as usize - 1];
+			result >= actual && result - actual <= actual / SUB_BUCKETS as u64
+		}
+	}
+}
@@ -0,0 +1,94 @@
+#[cfg(unix)]
+pub use self::unix::{Listener, peer_uid};
+
+#[cfg(windows)]
+pub use self::windows::{Listener, peer_uid};
+
+#[cfg(unix)]
+mod unix {
+	use std::io;
+	use std::os::unix::io::{AsRawFd, RawFd};
+	use std::os::unix::net::UnixListener as StdUnixListener;
+	use tokio::net::{UnixListener, UnixStream};
+
+	pub type Client = UnixStream;
+
+	pub struct Listener(UnixListener);
+
+	impl Listener {
+		/// Registers a socket that’s already bound with the runtime.
+		pub fn from_std(listener: StdUnixListener) -> io::Result<Self> {
+			Ok(Self(UnixListener::from_std(listener)?))
+		}
+
+		pub async fn accept(&mut self) -> io::Result<Client> {
+			let (client, _) = self.0.accept().await?;
+			Ok(client)
+		}
+	}
+
+	/// Gets the user id of the process on the other end of a connection, if the system says.
+	pub fn peer_uid(client: &Client) -> Option<u32> {
+		client.peer_cred().ok().map(|cred| cred.uid)
+	}
+
+	impl AsRawFd for Listener {
+		fn as_raw_fd(&self) -> RawFd {
+			self.0.as_raw_fd()
+		}
+	}
+}
+
+#[cfg(windows)]
+mod windows {
+	use mio_named_pipes::NamedPipe;
+	use std::ffi::OsString;
+	use std::io;
+	use std::mem;
+	use std::path::Path;
+	use tokio::future::poll_fn;
+	use tokio::io::PollEvented;
+
+	pub type Client = PollEvented<NamedPipe>;
+
+	/// A named pipe server. There’s always one instance of the pipe waiting for the next client, so clients don’t see the pipe disappear between connections.
+	pub struct Listener {
+		name: OsString,
+		next: Client,
+	}
+
+	impl Listener {
+		/// Creates the first instance of a pipe, like `\\.\pipe\iptooled`.
+		pub fn bind(name: &Path) -> io::Result<Self> {
+			let name = name.as_os_str().to_owned();
+			let next = PollEvented::new(NamedPipe::new(&name)?)?;
+
+			Ok(Self { name, next })
+		}
+
+		/// Waits for a client to connect to the waiting instance, then creates a new one for the next client.
+		pub async fn accept(&mut self) -> io::Result<Client> {
+			match self.next.get_ref().connect() {
+				Ok(()) => {}
+				Err(err) if err.kind() == io::ErrorKind::WouldBlock => {
+					// A pending connection finishing makes the pipe writable.
+					let next = &self.next;
+					poll_fn(|cx| next.poll_write_ready(cx)).await?;
+
+					if let Some(err) = next.get_ref().take_error()? {
+						return Err(err);
+					}
+				}
+				Err(err) => return Err(err),
+			}
+
+			let next = PollEvented::new(NamedPipe::new(&self.name)?)?;
+			Ok(mem::replace(&mut self.next, next))
+		}
+	}
+
+	/// Named pipes don’t say who’s on the other end.
+	pub fn peer_uid(_client: &Client) -> Option<u32> {
+		None
+	}
+}
@@ -0,0 +1,52 @@
+use std::io;
+use tracing_subscriber::EnvFilter;
+use tracing_subscriber::fmt::time::ChronoUtc;
+#[cfg(unix)]
+use tracing_subscriber::layer::SubscriberExt;
+#[cfg(unix)]
+use tracing_subscriber::registry::Registry;
+
+use super::cli::{LogFormat, LogTarget};
+#[cfg(unix)]
+use super::syslog::SystemLog;
+
+/// Which messages are logged when nothing says otherwise.
+pub const DEFAULT_FILTER: &str = "info";
+
+/// Checks that a filter, like `warn` or `info,iptooled::handoff=debug`, can be passed to `init`.
+pub fn is_valid_filter(filter: &str) -> bool {
+	EnvFilter::try_new(filter).is_ok()
+}
+
+/// Sends log messages that pass a filter to their target. Fails if the target is the system log and it isn’t running.
+pub fn init(filter: &str, format: LogFormat, target: LogTarget) -> io::Result<()> {
+	match target {
+		LogTarget::Stderr => init_stderr(filter, format),
+		#[cfg(unix)]
+		LogTarget::Syslog => init_system_log(filter, SystemLog::syslog()?),
+		#[cfg(unix)]
+		LogTarget::Journald => init_system_log(filter, SystemLog::journald()?),
+	}
+
+	Ok(())
+}
+
+/// Writes log messages to stderr, which is the log file if there is one, with UTC timestamps so nothing has to read the time zone once sandboxed.
+fn init_stderr(filter: &str, format: LogFormat) {
+	let builder =
+		tracing_subscriber::fmt()
+			.with_writer(io::stderr)
+			.with_timer(ChronoUtc::rfc3339())
+			.with_env_filter(EnvFilter::new(filter));
+
+	match format {
+		LogFormat::Text => builder.init(),
+		LogFormat::Json => builder.json().init(),
+	}
+}
+
+#[cfg(unix)]
+fn init_system_log(filter: &str, system_log: SystemLog) {
+	let subscriber = Registry::default().with(EnvFilter::new(filter)).with(system_log);
+	tracing::subscriber::set_global_default(subscriber).expect("logging should only be initialized once");
+}
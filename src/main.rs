@@ -1,4 +1,5 @@
 #![feature(async_closure)]
+#![feature(backtrace)]
 #![feature(const_int_conversion)]
 #![feature(process_exitcode_placeholder)]
 #![feature(try_blocks)]
@@ -9,139 +10,1950 @@
 extern crate quickcheck_macros;
 
 mod address;
+mod audit;
+#[cfg(any(test, feature = "bench"))]
+mod bench;
+mod build_info;
+mod cli;
+mod config;
+mod connections;
+mod cow_map;
+#[cfg(unix)]
+mod daemon;
+mod decay;
+mod distinct;
+#[cfg(unix)]
+mod dump;
+#[cfg(unix)]
+mod handoff;
+mod hints;
+mod inspect;
+mod labels;
+mod latency;
+mod listener;
+mod logging;
+mod mmdb;
+mod otlp;
+mod overrides;
+mod persist;
+mod prefix_list;
+mod prefix_map;
 mod protocol;
+#[cfg(any(test, feature = "bench"))]
+mod random;
+mod rate_limit;
+mod replication;
+mod salt;
+#[cfg(all(target_os = "linux", feature = "sandbox"))]
+mod sandbox;
+mod statsd;
+#[cfg(unix)]
+mod stdio;
+#[cfg(unix)]
+mod syslog;
+#[cfg(test)]
+mod tests;
 mod time_list;
 mod tree;
+mod tunables;
+#[cfg(feature = "io-uring")]
+mod uring;
+
+use std::backtrace::Backtrace;
+use std::cell::{Cell, RefCell};
+use std::env;
+use std::error::Error;
+use std::future::Future;
+use std::io;
+use std::iter;
+#[cfg(unix)]
+use std::mem;
+#[cfg(unix)]
+use std::os::unix::io::AsRawFd;
+use std::net::{SocketAddr, TcpListener as StdTcpListener};
+#[cfg(unix)]
+use std::os::unix::net::UnixListener as StdUnixListener;
+use std::panic;
+use std::path::{Path, PathBuf};
+use std::process::{self, ExitCode};
+use std::rc::{Rc, Weak};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::net::TcpListener;
+#[cfg(unix)]
+use tokio::net::UnixListener;
+use tokio::runtime;
+use tokio::sync::{mpsc, watch};
+use tokio::task;
+use tokio::time;
+use tracing::{Instrument, error, info, info_span, warn};
+
+use self::address::{ADDRESS_BYTES, Address, AddressPrefix, IPV4_OFFSET_BITS};
+use self::audit::AuditLog;
+use self::cli::{Command, LogFormat, LogTarget, ServeOptions, SpecialRangePolicy};
+use self::connections::{CLIENT_ERROR_WINDOW, ClientErrorKind, ClientErrors, ConnectionStats, Disconnect, UNKNOWN_UID};
+#[cfg(unix)]
+use self::daemon::{Daemonizer, RemovableFile};
+#[cfg(unix)]
+use self::dump::{Diagnostics, TOP_NETWORKS};
+use self::hints::Hints;
+use self::labels::{LABELS_FILE_NAME, Labels};
+use self::latency::{Latencies, PERCENTILES, RequestKind};
+use self::listener::{Listener, peer_uid};
+use self::otlp::{RequestTrace, Tracer};
+use self::overrides::{OVERRIDES_FILE_NAME, Overrides, Verdict};
+use self::persist::{LOG_FILE_NAME, OperationLog, PersistenceFailed, SerializedTreeOperation, operation_count};
+use self::protocol::{AddressForm, PROTOCOL_VERSION, ReadError, Request, read_request};
+use self::rate_limit::RateLimits;
+use self::replication::{ReplicationLog, Subscription};
+use self::salt::UserSalt;
+use self::statsd::{Counters, Metric, Statsd};
+use self::time_list::CoarseSystemTime;
+use self::tree::{DistinctResult, Operation, OperationType, Prior, QueryResult, Retraction, SeenResult, Snapshot, SpamTree, TreeOperation, User, VelocityResult, WeightedResult};
+use self::tunables::Tunable;
+
+/// How long to wait for connected clients to finish their requests when shutting down.
+const SHUTDOWN_DEADLINE: Duration = Duration::from_secs(10);
+
+/// The z-score of the confidence bounds in responses, for 95% confidence.
+const CONFIDENCE_Z: f64 = 1.96;
+
+/// How often to expire old entries when nothing else does, so a quiet daemon doesn’t hold on to them and the next request doesn’t pay for all of it.
+const EXPIRY_INTERVAL: Duration = Duration::from_secs(60);
+
+/// The size of the buffer requests from each client are read into, which is what each connection costs in memory besides its task.
+const CLIENT_READ_BUFFER_BYTES: usize = 8 * 1024;
+
+/// How many prefixes to send at a time when listing them, so a large list isn’t built in memory all at once.
+const PREFIX_LIST_CHUNK: usize = 1024;
+
+/// How often to measure how late the event loop is to run tasks, for health checks.
+const LAG_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How late the event loop can be to run a task before health checks report it.
+const MAX_HEALTHY_LAG: Duration = Duration::from_secs(1);
+
+/// How often to retry writing the operation log after writes to it failed.
+const LOG_RETRY_INTERVAL: Duration = Duration::from_secs(5);
+
+/// The exit status when writes to the operation log kept failing for longer than `--degraded-exit-after`, `EX_IOERR` from sysexits.h.
+const EXIT_PERSISTENCE_FAILED: u8 = 74;
+
+/// How often to send spans to the OpenTelemetry collector.
+const OTLP_EXPORT_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Set in a health check’s status when the last write to the operation log failed.
+const HEALTH_LOG_FAILING: u8 = 1;
+
+/// Set in a health check’s status when the event loop was late by more than `MAX_HEALTHY_LAG` since the last health check.
+const HEALTH_LAGGING: u8 = 2;
+
+/// Set in a health check’s status when enough writes to the operation log failed in a row that the daemon is degraded.
+const HEALTH_DEGRADED: u8 = 4;
+
+/// Set in a health check’s status when the daemon is a replica that isn’t connected to its primary.
+const HEALTH_NOT_FOLLOWING: u8 = 8;
+
+/// The response to a write sent to a replica: a status that isn’t used for anything else, then where to send it instead.
+const REDIRECT_TO_PRIMARY: u8 = 3;
+
+/// How long a replica waits to reconnect to its primary after losing the connection.
+const REPLICA_RETRY_INTERVAL: Duration = Duration::from_secs(5);
+
+thread_local! {
+	/// The state of the daemon serving on this thread, if any, whose operation log is flushed on a panic.
+	static SERVING: RefCell<Weak<Shared>> = RefCell::new(Weak::new());
+}
+
+/// State shared by all connections.
+struct Shared {
+	tree: RefCell<SpamTree>,
+	/// The snapshot that queries are served from, if there’s a snapshot interval.
+	snapshot: RefCell<Option<Arc<Snapshot>>>,
+	log: RefCell<OperationLog>,
+	audit_log: Option<RefCell<AuditLog>>,
+	overrides_path: PathBuf,
+	labels: RefCell<Labels>,
+	labels_path: PathBuf,
+	/// The domain hints sent with spam reports, which aren’t saved.
+	hints: RefCell<Hints>,
+	idle_timeout: Cell<Option<Duration>>,
+	prior: Cell<Prior>,
+	user_salt: Option<UserSalt>,
+	special_ranges: SpecialRangePolicy,
+	unwrap_tunnels: bool,
+	statsd: Option<Rc<Statsd>>,
+	tracer: Option<Rc<Tracer>>,
+	/// Counts of connections and requests since they were last sent to the StatsD agent.
+	counters: Counters,
+	/// The latencies of requests since startup.
+	latencies: RefCell<Latencies>,
+	connections: RefCell<ConnectionStats>,
+	client_errors: RefCell<ClientErrors>,
+	slow_request_threshold: Cell<Option<Duration>>,
+	slow_request_sample: Cell<u32>,
+	/// The number of slow requests since startup, to sample the ones that are logged.
+	slow_requests: Cell<u64>,
+	/// The number of writes to the operation log that failed in a row.
+	log_failures: Cell<u32>,
+	degraded_after: Cell<u32>,
+	refuse_writes_when_degraded: bool,
+	/// When the daemon became degraded, if it is.
+	degraded_since: Cell<Option<Instant>>,
+	/// How long the daemon was allowed to stay degraded, if it’s shutting down because it stayed degraded for longer.
+	persistence_failed: Cell<Option<Duration>>,
+	/// The longest the event loop was late to run a task since the last health check.
+	loop_lag: Cell<Duration>,
+	/// The token buckets limiting how fast each user id can make requests, if they’re limited.
+	rate_limits: Option<RefCell<RateLimits>>,
+	/// Whether there’s an admin socket, which some requests are only accepted on.
+	admin_socket: bool,
+	/// The recent operations to stream to followers, if there’s a replication listener.
+	replication: Option<Rc<RefCell<ReplicationLog>>>,
+	/// The primary to follow, if this is a replica, which clients are told to send writes to.
+	primary: Option<SocketAddr>,
+	/// Whether this is a replica that’s connected to its primary.
+	following: Cell<bool>,
+	/// The sequence number of the next operation to ask the primary for, if this is a replica.
+	next_replicated: Cell<u64>,
+	shutdown: watch::Sender<bool>,
+}
+
+impl Shared {
+	/// Makes the state for serving a tree, along with a receiver that’s notified when a shutdown is requested.
+	fn new(tree: SpamTree, log: OperationLog, options: &ServeOptions) -> io::Result<(Rc<Self>, watch::Receiver<bool>)> {
+		let (shutdown_sender, shutdown_receiver) = watch::channel(false);
+
+		#[cfg(unix)]
+		let admin_socket = options.admin_socket_path.is_some();
+		#[cfg(windows)]
+		let admin_socket = false;
+
+		let log_operations = log.operations();
+		let replication = options.replication_address.as_ref().map(|_| Rc::new(RefCell::new(ReplicationLog::new(options.persist_path.join(LOG_FILE_NAME), log_operations))));
+
+		let shared = Rc::new(Self {
+			tree: RefCell::new(tree),
+			snapshot: RefCell::new(None),
+			log: RefCell::new(log),
+			audit_log: options.audit_path.as_deref().map(AuditLog::open).transpose()?.map(RefCell::new),
+			overrides_path: options.persist_path.join(OVERRIDES_FILE_NAME),
+			labels: RefCell::new(Labels::read(&options.persist_path.join(LABELS_FILE_NAME))?),
+			hints: RefCell::new(Hints::default()),
+			labels_path: options.persist_path.join(LABELS_FILE_NAME),
+			idle_timeout: Cell::new(options.idle_timeout),
+			prior: Cell::new(options.prior),
+			user_salt: options.user_salt.clone(),
+			special_ranges: options.special_ranges,
+			unwrap_tunnels: options.unwrap_tunnels,
+			statsd: options.statsd.clone(),
+			tracer: options.tracer.clone(),
+			counters: Counters::default(),
+			latencies: RefCell::new(Latencies::new()),
+			connections: RefCell::new(ConnectionStats::default()),
+			client_errors: RefCell::new(ClientErrors::default()),
+			slow_request_threshold: Cell::new(options.slow_request_threshold),
+			slow_request_sample: Cell::new(options.slow_request_sample),
+			slow_requests: Cell::new(0),
+			log_failures: Cell::new(0),
+			degraded_after: Cell::new(options.degraded_after),
+			refuse_writes_when_degraded: options.refuse_writes_when_degraded,
+			degraded_since: Cell::new(None),
+			persistence_failed: Cell::new(None),
+			loop_lag: Cell::new(Duration::from_secs(0)),
+			rate_limits: options.rate_limit.map(|(rate, burst)| RefCell::new(RateLimits::new(rate, burst))),
+			admin_socket,
+			replication,
+			primary: options.primary_address,
+			following: Cell::new(false),
+			next_replicated: Cell::new(log_operations),
+			shutdown: shutdown_sender,
+		});
+
+		SERVING.with(|serving| *serving.borrow_mut() = Rc::downgrade(&shared));
+
+		Ok((shared, shutdown_receiver))
+	}
+
+	/// Hashes a user from a request with the salt, if there is one.
+	fn salted(&self, user: User) -> User {
+		match &self.user_salt {
+			Some(user_salt) => user_salt.hash(user),
+			None => user,
+		}
+	}
+
+	/// Records a step of handling a request from `start` until now, if the request is traced.
+	fn trace_step(&self, trace: Option<&RequestTrace>, name: &'static str, start: Instant) {
+		if let (Some(tracer), Some(trace)) = (&self.tracer, trace) {
+			tracer.record_step(trace, name, start);
+		}
+	}
+
+	/// Appends an operation or tombstone to the operation log, remembering whether that succeeded for health checks.
+	fn append_log(&self, serialized: &SerializedTreeOperation, trace: Option<&RequestTrace>) {
+		let start = Instant::now();
+		let result = self.log.borrow_mut().append(serialized);
+		self.trace_step(trace, "persist", start);
+		let appended = result.is_ok();
+		self.record_log_result(result);
+
+		// Operations are numbered by their position in the log, which followers catch up from, so one that isn’t in it can’t be sent either.
+		match &self.replication {
+			Some(replication) if appended => replication.borrow_mut().push(serialized),
+			_ => {}
+		}
+	}
+
+	/// Flushes the operation log, remembering whether that succeeded like a write.
+	fn flush_log(&self) {
+		let result = self.log.borrow_mut().flush();
+		self.record_log_result(result);
+	}
+
+	/// Counts writes to the operation log that failed in a row, becoming degraded after `degraded_after` of them and recovering on the next one that succeeds.
+	fn record_log_result(&self, result: io::Result<()>) {
+		match result {
+			Ok(()) => {
+				self.log_failures.set(0);
+
+				if let Some(since) = self.degraded_since.take() {
+					info!(seconds = since.elapsed().as_secs(), "writes to the operation log are succeeding again, no longer degraded");
+				}
+			}
+			Err(err) => {
+				error!(error = %err, "failed to write to operation log");
+
+				let failures = self.log_failures.get().saturating_add(1);
+				self.log_failures.set(failures);
+
+				if failures >= self.degraded_after.get() && self.degraded_since.get().is_none() {
+					error!(failures, "writes to the operation log keep failing, degraded");
+					self.degraded_since.set(Some(Instant::now()));
+				}
+			}
+		}
+	}
+
+	/// Gets a tunable’s current value, in the form its command-line option takes.
+	fn tunable(&self, tunable: Tunable) -> String {
+		fn or_off(value: Option<impl ToString>) -> String {
+			value.map_or_else(|| "off".to_owned(), |value| value.to_string())
+		}
+
+		let tree = self.tree.borrow();
+
+		match tunable {
+			Tunable::IdleTimeout => self.idle_timeout.get().map_or(0, |timeout| timeout.as_secs()).to_string(),
+			Tunable::SpamPrior => self.prior.get().spam.to_string(),
+			Tunable::TrustedPrior => self.prior.get().trusted.to_string(),
+			Tunable::MinDistinctUsers => or_off(tree.settings().min_distinct_users),
+			Tunable::SpamQuarantine => or_off(tree.settings().spam_quarantine),
+			Tunable::SlowRequestThreshold => or_off(self.slow_request_threshold.get().map(|threshold| threshold.as_millis())),
+			Tunable::SlowRequestSample => self.slow_request_sample.get().to_string(),
+			Tunable::DegradedAfter => self.degraded_after.get().to_string(),
+		}
+	}
+
+	/// Changes a tunable, checking the value the same way as its command-line option. Turning off the ones that can be turned off takes `off`.
+	fn set_tunable(&self, tunable: Tunable, value: &str) -> Result<(), String> {
+		match tunable {
+			Tunable::IdleTimeout => {
+				cli::is_number::<u64>(value.to_owned())?;
+
+				self.idle_timeout.set(
+					match value.parse().unwrap() {
+						0 => None,
+						seconds => Some(Duration::from_secs(seconds)),
+					});
+			}
+			Tunable::SpamPrior | Tunable::TrustedPrior => {
+				cli::is_weight(value.to_owned())?;
+
+				let mut prior = self.prior.get();
+				let weight = value.parse().unwrap();
+
+				match tunable {
+					Tunable::SpamPrior => prior.spam = weight,
+					_ => prior.trusted = weight,
+				}
+
+				self.prior.set(prior);
+			}
+			Tunable::MinDistinctUsers => {
+				let minimum =
+					match value {
+						"off" => None,
+						_ => {
+							cli::is_entry_count(value.to_owned())?;
+							Some(value.parse().unwrap())
+						}
+					};
+
+				if minimum.is_some() && !self.tree.borrow().settings().distinct_users {
+					return Err("requires starting with --distinct-users".to_owned());
+				}
+
+				self.tree.borrow_mut().set_min_distinct_users(minimum);
+			}
+			Tunable::SpamQuarantine => {
+				cli::is_entry_count(value.to_owned())?;
+
+				if self.tree.borrow().settings().spam_quarantine.is_none() {
+					return Err("can only be changed if it was set at startup".to_owned());
+				}
+
+				self.tree.borrow_mut().set_spam_quarantine(value.parse().unwrap());
+			}
+			Tunable::SlowRequestThreshold => {
+				let threshold =
+					match value {
+						"off" => None,
+						_ => {
+							cli::is_number::<u64>(value.to_owned())?;
+							Some(Duration::from_millis(value.parse().unwrap()))
+						}
+					};
+
+				self.slow_request_threshold.set(threshold);
+			}
+			Tunable::SlowRequestSample => {
+				cli::is_sample(value.to_owned())?;
+				self.slow_request_sample.set(value.parse().unwrap());
+			}
+			Tunable::DegradedAfter => {
+				cli::is_sample(value.to_owned())?;
+				self.degraded_after.set(value.parse().unwrap());
+			}
+		}
+
+		// The snapshot was made with the old settings, so queries use the tree until the next one.
+		if let Tunable::MinDistinctUsers | Tunable::SpamQuarantine = tunable {
+			*self.snapshot.borrow_mut() = None;
+		}
+
+		info!(name = tunable.name(), value, "changed tunable");
+		Ok(())
+	}
+
+	/// Logs how many client errors from each user id weren’t logged individually in the windows that ended by `now`, or in every window if `now` is `None`.
+	fn summarize_client_errors(&self, now: Option<Instant>) {
+		for (uid, kind, count) in self.client_errors.borrow_mut().end_windows(now) {
+			match uid {
+				UNKNOWN_UID => warn!("{} more {} errors from clients of an unknown user weren’t logged", count, kind.name()),
+				uid => warn!("{} more {} errors from uid {} weren’t logged", count, kind.name(), uid),
+			}
+		}
+	}
+
+	/// Whether reports and retractions are refused, because the daemon is degraded and `--refuse-writes-when-degraded` is set.
+	fn refusing_writes(&self) -> bool {
+		self.refuse_writes_when_degraded && self.degraded_since.get().is_some()
+	}
+
+	/// Records an accepted operation or retraction, and the user id of the client that made it if it’s known, in the audit log, if there is one.
+	fn audit(&self, uid: Option<u32>, operation: &TreeOperation) {
+		if let Some(audit_log) = &self.audit_log {
+			if let Err(err) = audit_log.borrow_mut().record(uid, operation) {
+				error!(error = %err, "failed to write to audit log");
+			}
+		}
+	}
+
+	/// Estimates the memory used by the read buffers of open connections.
+	fn connection_buffer_bytes(&self) -> usize {
+		self.connections.borrow().active() as usize * CLIENT_READ_BUFFER_BYTES
+	}
+
+	fn record_latency(&self, kind: RequestKind, duration: Duration) {
+		self.latencies.borrow_mut().record(kind, duration);
+		self.counters.record_latency(kind, duration);
+	}
+
+	/// Logs one in every `slow_request_sample` requests that took longer than the slow request threshold, with the address it was about, if any, and how deep a query for that address goes into the tree.
+	fn log_if_slow(&self, kind: RequestKind, address: Option<Address>, duration: Duration) {
+		match self.slow_request_threshold.get() {
+			Some(threshold) if duration > threshold => {},
+			_ => return,
+		}
+
+		let count = self.slow_requests.get();
+		self.slow_requests.set(count + 1);
+
+		if count % u64::from(self.slow_request_sample.get()) != 0 {
+			return;
+		}
+
+		let micros = duration.as_micros() as u64;
+
+		match address {
+			Some(address) => {
+				let (prefix_bits, lookups) = self.tree.borrow().query_depth(&address);
+				warn!(kind = kind.name(), micros, %address, prefix_bits, lookups = lookups as u64, "slow request");
+			}
+			None => warn!(kind = kind.name(), micros, "slow request"),
+		}
+	}
+
+	/// Decides what to do with a report for an address, which is to accept it unless it’s in a special-purpose range.
+	fn special_range_policy(&self, address: &Address) -> SpecialRangePolicy {
+		match address.special_range() {
+			Some(_) => self.special_ranges,
+			None => SpecialRangePolicy::Accept,
+		}
+	}
+
+	/// Truncates an address from a request to the size addresses are stored as, if they’re truncated.
+	fn truncated(&self, address: Address) -> Address {
+		self.tree.borrow().truncate(address)
+	}
+
+	/// Queries the snapshot if there is one, or the tree otherwise.
+	fn query(&self, address: &Address) -> QueryResult {
+		let snapshot = self.snapshot.borrow().clone();
+
+		match snapshot {
+			Some(snapshot) => snapshot.query(address),
+			None => self.tree.borrow_mut().query(address, CoarseSystemTime::now()),
+		}
+	}
+
+	/// Performs an operation on the tree for the client with user id `uid`, logging it if it was accepted, and returns whether it was.
+	fn perform(&self, operation: Operation, uid: Option<u32>, trace: Option<&RequestTrace>) -> bool {
+		let Operation(type_, address, user) = operation;
+		let operation = Operation(type_, self.truncated(address), self.salted(user));
+		let now = CoarseSystemTime::now();
+		let serialized = SerializedTreeOperation::new(&operation, now);
+
+		let start = Instant::now();
+		let performed = self.tree.borrow_mut().perform(operation.clone(), now);
+		self.trace_step(trace, "tree", start);
+
+		if !performed {
+			return false;
+		}
+
+		self.append_log(&serialized, trace);
+		self.audit(uid, &TreeOperation::Perform(operation));
+		true
+	}
+
+	/// Applies an operation from the primary to the tree and logs it, like replaying the log.
+	fn apply_replicated(&self, serialized: &SerializedTreeOperation) -> io::Result<()> {
+		let (operation, time) = serialized.parse().ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "invalid operation from the primary"))?;
+
+		match operation {
+			TreeOperation::Perform(operation) => self.tree.borrow_mut().perform(operation, time),
+			TreeOperation::Retract(retraction) => self.tree.borrow_mut().retract(&retraction, time),
+		};
+
+		self.append_log(serialized, None);
+		self.next_replicated.set(self.next_replicated.get() + 1);
+		Ok(())
+	}
+
+	/// Retracts reports from the tree for the client with user id `uid`, logging a tombstone if there were any, and returns whether there were.
+	fn retract(&self, retraction: Retraction, uid: Option<u32>, trace: Option<&RequestTrace>) -> bool {
+		let retraction =
+			match retraction {
+				Retraction::Report(type_, address, user) => Retraction::Report(type_, self.truncated(address), self.salted(user)),
+				Retraction::User(user) => Retraction::User(self.salted(user)),
+			};
+		let now = CoarseSystemTime::now();
+		let start = Instant::now();
+		let retracted = self.tree.borrow_mut().retract(&retraction, now);
+		self.trace_step(trace, "tree", start);
+
+		if retracted {
+			self.append_log(&SerializedTreeOperation::retraction(&retraction, now), trace);
+			self.audit(uid, &TreeOperation::Retract(retraction));
+		}
+
+		retracted
+	}
+
+	/// Sets or removes an override and saves the overrides, returning whether that succeeded.
+	fn set_override(&self, prefix: AddressPrefix, verdict: Option<Verdict>) -> bool {
+		let mut tree = self.tree.borrow_mut();
+		tree.overrides_mut().set(prefix, verdict);
+
+		// The snapshot has the old overrides, so queries use the tree until the next one.
+		*self.snapshot.borrow_mut() = None;
+
+		match tree.overrides().write(&self.overrides_path) {
+			Ok(()) => true,
+			Err(err) => {
+				error!(error = %err, "failed to save overrides");
+				false
+			}
+		}
+	}
+
+	/// Sets or removes a label and saves the labels, returning whether that succeeded.
+	fn set_label(&self, prefix: AddressPrefix, label: Option<String>) -> bool {
+		let mut labels = self.labels.borrow_mut();
+		labels.set(prefix, label);
+
+		match labels.write(&self.labels_path) {
+			Ok(()) => true,
+			Err(err) => {
+				error!(error = %err, "failed to save labels");
+				false
+			}
+		}
+	}
+}
+
+/// Makes a tree with the configured settings and the saved overrides.
+fn new_tree(options: &ServeOptions) -> io::Result<SpamTree> {
+	let mut tree = SpamTree::new(options.tree_settings.clone());
+	*tree.overrides_mut() = Overrides::read(&options.persist_path.join(OVERRIDES_FILE_NAME))?;
+	Ok(tree)
+}
+
+/// What was in the persistence directory when starting, for the startup report.
+struct Replayed {
+	/// Whether there was an operation log, instead of a new one being started.
+	existed: bool,
+	/// The number of operations in the log.
+	operations: u64,
+	/// How long it took to replay the log and merge in imports.
+	duration: Duration,
+}
+
+/// Makes a relative path absolute by joining it to the working directory, to log where files are.
+fn absolute(path: &Path) -> PathBuf {
+	env::current_dir().map_or_else(|_| path.to_owned(), |directory| directory.join(path))
+}
+
+/// Logs the effective configuration, where the daemon is listening, and what it found in the persistence directory, so a misconfiguration shows up in the log right away instead of in how the daemon behaves.
+fn log_startup(options: &ServeOptions, sockets: &[(&'static str, PathBuf)], replayed: &Replayed, tree: &SpamTree) {
+	let config: Vec<String> = options.summary().iter().map(|(name, value)| format!("{}={}", name, value)).collect();
+	info!(config = %config.join(", "), "effective configuration");
+
+	for (name, path) in sockets {
+		info!(socket = name, path = %path.display(), "listening");
+	}
+
+	let size = tree.size();
+
+	info!(
+		persist_path = %absolute(&options.persist_path).display(),
+		log_existed = replayed.existed,
+		operations = replayed.operations,
+		imports = options.import_paths.len(),
+		overrides = tree.overrides().iter().count(),
+		prefixes = size.prefixes,
+		users = size.users,
+		milliseconds = replayed.duration.as_millis() as u64,
+		"replayed operation log");
+}
+
+/// Merges the operations logged by other deployments into a tree whose own log has been replayed.
+fn merge_imports(tree: &mut SpamTree, options: &ServeOptions) -> io::Result<()> {
+	for import_path in &options.import_paths {
+		let mut imported = SpamTree::new(options.tree_settings.clone());
+		OperationLog::replay_file(&import_path.join(LOG_FILE_NAME), &mut imported)?;
+		tree.merge(imported);
+	}
+
+	Ok(())
+}
+
+/// Rebuilds the snapshot that queries are served from every interval, until a shutdown is requested.
+async fn refresh_snapshot(shared: Rc<Shared>, interval: Duration, mut shutdown: watch::Receiver<bool>) {
+	let mut ticks = time::interval(interval);
+
+	loop {
+		tokio::select! {
+			_ = ticks.tick() => {},
+			_ = shutdown_requested(&mut shutdown) => break,
+		}
+
+		let start = Instant::now();
+		let snapshot = shared.tree.borrow_mut().snapshot(CoarseSystemTime::now());
+		*shared.snapshot.borrow_mut() = Some(Arc::new(snapshot));
+
+		if let Some(statsd) = &shared.statsd {
+			statsd.send(&[Metric::Time("snapshot", start.elapsed())]);
+		}
+	}
+}
+
+/// Sends the counts since the last time and the size of the tree to a StatsD agent every `interval`, and once more when a shutdown is requested.
+async fn send_metrics(shared: Rc<Shared>, statsd: Rc<Statsd>, interval: Duration, mut shutdown: watch::Receiver<bool>) {
+	let mut ticks = time::interval(interval);
+
+	// The first tick is immediate.
+	ticks.tick().await;
+
+	loop {
+		let stopping = tokio::select! {
+			_ = ticks.tick() => false,
+			_ = shutdown_requested(&mut shutdown) => true,
+		};
+
+		let (size, memory) = {
+			let tree = shared.tree.borrow();
+			(tree.size(), tree.memory_usage())
+		};
+
+		statsd.send(&shared.counters.take());
+
+		let latencies = shared.counters.take_latencies();
+		let mut latency_gauges = Vec::new();
+
+		for (kind, histogram) in latencies.iter().filter(|(_, histogram)| histogram.count() != 0) {
+			for &(name, micros) in &[("p50", histogram.percentile(500)), ("p99", histogram.percentile(990)), ("max", histogram.max())] {
+				latency_gauges.push((format!("latency.{}.{}", kind.name(), name), micros));
+			}
+		}
+
+		statsd.send(&latency_gauges.iter().map(|(name, micros)| Metric::Gauge(name, u64::from(*micros))).collect::<Vec<_>>());
+		statsd.send(&[
+			Metric::Gauge("prefixes", size.prefixes as u64),
+			Metric::Gauge("users", size.users as u64),
+			Metric::Gauge("user_window_entries", size.user_window_entries as u64),
+			Metric::Gauge("address_window_entries", size.address_window_entries as u64),
+			Metric::Gauge("estimated_bytes", size.estimated_bytes as u64),
+		]);
+
+		let memory_gauges: Vec<(String, usize)> =
+			memory.parts().iter()
+				.map(|&(name, bytes)| (format!("memory.{}", name), bytes))
+				.chain(iter::once(("memory.connection_buffers".to_owned(), shared.connection_buffer_bytes())))
+				.collect();
+
+		statsd.send(&memory_gauges.iter().map(|(name, bytes)| Metric::Gauge(name, *bytes as u64)).collect::<Vec<_>>());
+
+		if stopping {
+			break;
+		}
+	}
+}
+
+/// Logs how many client errors from each user id weren’t logged individually, once their window ends, until a shutdown is requested.
+async fn summarize_client_errors(shared: Rc<Shared>, mut shutdown: watch::Receiver<bool>) {
+	let mut ticks = time::interval(CLIENT_ERROR_WINDOW);
+
+	loop {
+		tokio::select! {
+			_ = ticks.tick() => {},
+			_ = shutdown_requested(&mut shutdown) => break,
+		}
+
+		shared.summarize_client_errors(Some(Instant::now()));
+	}
+}
+
+/// Retries writing the operation log while writes to it are failing, so the daemon recovers once they succeed even if it’s refusing writes, and shuts down if it stays degraded for longer than `exit_after`.
+async fn watch_log_failures(shared: Rc<Shared>, exit_after: Option<Duration>, mut shutdown: watch::Receiver<bool>) {
+	let mut ticks = time::interval(LOG_RETRY_INTERVAL);
+
+	loop {
+		tokio::select! {
+			_ = ticks.tick() => {},
+			_ = shutdown_requested(&mut shutdown) => break,
+		}
+
+		if shared.log_failures.get() == 0 {
+			continue;
+		}
+
+		// Writing out what’s buffered is the write that failed last.
+		shared.flush_log();
+
+		if let (Some(since), Some(exit_after)) = (shared.degraded_since.get(), exit_after) {
+			if since.elapsed() >= exit_after {
+				error!(seconds = exit_after.as_secs(), "degraded for too long, exiting");
+				shared.persistence_failed.set(Some(exit_after));
+				let _ = shared.shutdown.broadcast(true);
+				break;
+			}
+		}
+	}
+}
+
+/// Writes a diagnostic dump to the dump file, or stderr if there isn’t one, each time SIGUSR1 arrives, until a shutdown is requested.
+#[cfg(unix)]
+async fn dump_on_signal(shared: Rc<Shared>, config: Vec<(&'static str, String)>, dump_path: Option<PathBuf>, mut shutdown: watch::Receiver<bool>) {
+	use std::fs::OpenOptions;
+	use tokio::signal::unix::{SignalKind, signal};
+
+	let mut dump_requested =
+		match signal(SignalKind::user_defined1()) {
+			Ok(signal) => signal,
+			Err(err) => {
+				error!(error = %err, "failed to handle SIGUSR1");
+				return;
+			}
+		};
+
+	loop {
+		tokio::select! {
+			_ = dump_requested.recv() => {},
+			_ = shutdown_requested(&mut shutdown) => break,
+		}
+
+		let (size, memory, windows, top_networks) = {
+			let tree = shared.tree.borrow();
+			(tree.size(), tree.memory_usage(), tree.window_stats(), tree.top_networks(TOP_NETWORKS))
+		};
+
+		let tunables: Vec<(&'static str, String)> = Tunable::ALL.iter().map(|&tunable| (tunable.name(), shared.tunable(tunable))).collect();
+		let connections = shared.connections.borrow();
+		let latencies = shared.latencies.borrow();
+		let diagnostics = Diagnostics {
+			config: &config,
+			tunables: &tunables,
+			size,
+			memory,
+			connection_buffer_bytes: shared.connection_buffer_bytes(),
+			windows,
+			top_networks,
+			connections: &connections,
+			latencies: &latencies,
+		};
+
+		let result =
+			match &dump_path {
+				Some(dump_path) => OpenOptions::new().append(true).create(true).open(dump_path).and_then(|mut file| diagnostics.write(&mut file)),
+				None => diagnostics.write(&mut io::stderr().lock()),
+			};
+
+		match result {
+			Ok(()) => info!("wrote diagnostic dump"),
+			Err(err) => error!(error = %err, "failed to write diagnostic dump"),
+		}
+	}
+}
+
+/// Sends the spans recorded for requests to the OpenTelemetry collector every `OTLP_EXPORT_INTERVAL`, and once more when a shutdown is requested.
+async fn export_spans(tracer: Rc<Tracer>, mut shutdown: watch::Receiver<bool>) {
+	let mut ticks = time::interval(OTLP_EXPORT_INTERVAL);
+
+	// The first tick is immediate.
+	ticks.tick().await;
+
+	loop {
+		let stopping = tokio::select! {
+			_ = ticks.tick() => false,
+			_ = shutdown_requested(&mut shutdown) => true,
+		};
+
+		match time::timeout(OTLP_EXPORT_INTERVAL, tracer.export()).await {
+			Ok(Ok(())) => {},
+			Ok(Err(err)) => warn!(error = %err, "failed to send spans to the OpenTelemetry collector"),
+			Err(_) => warn!("timed out sending spans to the OpenTelemetry collector"),
+		}
+
+		if stopping {
+			break;
+		}
+	}
+}
+
+/// Expires old entries every `EXPIRY_INTERVAL`, until a shutdown is requested.
+async fn expire_entries(shared: Rc<Shared>, mut shutdown: watch::Receiver<bool>) {
+	let mut ticks = time::interval(EXPIRY_INTERVAL);
+
+	loop {
+		tokio::select! {
+			_ = ticks.tick() => {},
+			_ = shutdown_requested(&mut shutdown) => break,
+		}
+
+		// Expire a batch at a time, letting clients in between, until caught up.
+		while !shared.tree.borrow_mut().expire(CoarseSystemTime::now()) {
+			task::yield_now().await;
+		}
+	}
+}
+
+/// Measures how late the event loop is to run a task every `LAG_CHECK_INTERVAL`, keeping the longest since the last health check, until a shutdown is requested.
+async fn measure_loop_lag(shared: Rc<Shared>, mut shutdown: watch::Receiver<bool>) {
+	let mut ticks = time::interval(LAG_CHECK_INTERVAL);
+
+	loop {
+		let deadline = tokio::select! {
+			deadline = ticks.tick() => deadline,
+			_ = shutdown_requested(&mut shutdown) => break,
+		};
+
+		let lag = time::Instant::now().saturating_duration_since(deadline);
+		shared.loop_lag.set(shared.loop_lag.get().max(lag));
+	}
+}
+
+/// Connects to the primary and applies its operations until the connection is lost.
+async fn apply_from_primary(shared: &Shared, primary: SocketAddr) -> io::Result<()> {
+	let next = shared.next_replicated.get();
+	let mut subscription = Subscription::connect(primary, next).await?;
+	info!(sequence = next, "following the primary");
+	shared.following.set(true);
+
+	loop {
+		let serialized = subscription.next().await?;
+		shared.apply_replicated(&serialized)?;
+	}
+}
+
+/// Follows the primary, reconnecting every `REPLICA_RETRY_INTERVAL` while the connection is lost, until a shutdown is requested.
+async fn follow_primary(shared: Rc<Shared>, primary: SocketAddr, mut shutdown: watch::Receiver<bool>) {
+	loop {
+		let result = tokio::select! {
+			result = apply_from_primary(&shared, primary) => result,
+			_ = shutdown_requested(&mut shutdown) => break,
+		};
+
+		shared.following.set(false);
+
+		if let Err(err) = result {
+			error!(error = %err, primary = %primary, "not following the primary");
+		}
+
+		tokio::select! {
+			_ = time::delay_for(REPLICA_RETRY_INTERVAL) => {},
+			_ = shutdown_requested(&mut shutdown) => break,
+		}
+	}
+}
+
+/// Starts the tasks that run alongside clients until a shutdown: refreshing the snapshot, expiring entries, measuring the event loop’s lag, sending metrics and spans, and following the primary.
+fn spawn_background_tasks(shared: &Rc<Shared>, options: &ServeOptions, shutdown: &watch::Receiver<bool>) {
+	if let Some(interval) = options.snapshot_interval {
+		task::spawn_local(refresh_snapshot(shared.clone(), interval, shutdown.clone()));
+	}
+
+	task::spawn_local(expire_entries(shared.clone(), shutdown.clone()));
+	task::spawn_local(measure_loop_lag(shared.clone(), shutdown.clone()));
+	task::spawn_local(summarize_client_errors(shared.clone(), shutdown.clone()));
+	task::spawn_local(watch_log_failures(shared.clone(), options.degraded_exit_after, shutdown.clone()));
+
+	if let Some(statsd) = &options.statsd {
+		task::spawn_local(send_metrics(shared.clone(), statsd.clone(), options.statsd_interval, shutdown.clone()));
+	}
+
+	if let Some(tracer) = &options.tracer {
+		task::spawn_local(export_spans(tracer.clone(), shutdown.clone()));
+	}
+
+	if let Some(primary) = options.primary_address {
+		task::spawn_local(follow_primary(shared.clone(), primary, shutdown.clone()));
+	}
+
+	#[cfg(unix)]
+	task::spawn_local(dump_on_signal(shared.clone(), options.summary(), options.dump_path.clone(), shutdown.clone()));
+}
+
+/// Waits until a shutdown is requested.
+async fn shutdown_requested(receiver: &mut watch::Receiver<bool>) {
+	while let Some(false) = receiver.recv().await {}
+}
+
+async fn read_request_within<T: AsyncRead + Unpin>(reader: &mut BufReader<T>, idle_timeout: Option<Duration>) -> Result<(Request, Instant), ReadError> {
+	match idle_timeout {
+		Some(idle_timeout) =>
+			match time::timeout(idle_timeout, read_request(reader)).await {
+				Ok(request) => request,
+				Err(_) => Err(ReadError::Timeout),
+			},
+		None => read_request(reader).await,
+	}
+}
+
+/// Encodes the response to a write sent to a replica as [`REDIRECT_TO_PRIMARY`, *length*, *primary*×*length*], with the primary’s address as text.
+fn redirect_response(primary: SocketAddr) -> Vec<u8> {
+	let primary = primary.to_string();
+	let mut response = vec![REDIRECT_TO_PRIMARY, primary.len() as u8];
+	response.extend_from_slice(primary.as_bytes());
+	response
+}
+
+/// Encodes a query’s result as [*count*×4 for each count, *bits*]. Weights and probabilities are sent as the bits of an `f32`.
+fn query_response(counts: &[u32], prefix_bits: u8, form: AddressForm) -> Vec<u8> {
+	let mut response = Vec::with_capacity(4 * counts.len() + 1);
+
+	for count in counts {
+		response.extend_from_slice(&count.to_be_bytes());
+	}
+
+	response.push(
+		match form {
+			// Relative to the IPv4 address. Results for IPv4 addresses never come from prefixes shorter than ::ffff:0:0/96, but a lack of results is still 0.
+			AddressForm::Ipv4 => prefix_bits.saturating_sub(IPV4_OFFSET_BITS),
+			AddressForm::Full => prefix_bits,
+		}
+	);
+
+	response
+}
+
+/// Serves a client, whose user id is `uid` if it’s known and which connected to the admin socket if `admin` is set, until it disconnects or a shutdown is requested. The `_active` sender is only held to let shutdown wait for connections to finish.
+async fn interact<R: AsyncRead + Unpin, W: AsyncWrite + Unpin>(shared: Rc<Shared>, client_read: R, mut client_write: W, uid: Option<u32>, admin: bool, mut shutdown: watch::Receiver<bool>, _active: mpsc::Sender<()>) {
+	shared.counters.count_connection();
+	shared.connections.borrow_mut().connect();
+
+	let mut reader = BufReader::with_capacity(CLIENT_READ_BUFFER_BYTES, client_read);
+
+	// Clients whose user id isn’t known are rate-limited per connection.
+	let mut connection_bucket = None;
+
+	let result: Result<(), ReadError> = try {
+		loop {
+			let (request, received) = tokio::select! {
+				request = read_request_within(&mut reader, shared.idle_timeout.get()) => request?,
+				_ = shutdown_requested(&mut shutdown) => break,
+			};
 
-use std::cell::RefCell;
-use std::env;
-use std::error::Error;
-use std::ffi::OsString;
-use std::fmt;
-use std::path::Path;
-use std::process::ExitCode;
-use std::rc::Rc;
-use tokio::io::{AsyncWriteExt, BufReader};
-use tokio::net::{UnixListener, UnixStream};
-use tokio::runtime;
-use tokio::task;
+			if !admin && (request.is_admin_only() || shared.admin_socket && request.is_admin()) {
+				Err(ReadError::Forbidden)?;
+			}
 
-use self::protocol::{ReadError, Request, read_request};
-use self::time_list::CoarseSystemTime;
-use self::tree::SpamTree;
+			let wait =
+				match &shared.rate_limits {
+					Some(rate_limits) if !admin => rate_limits.borrow_mut().take(uid, &mut connection_bucket, Instant::now()),
+					_ => None,
+				};
 
-#[derive(Clone, Debug)]
-struct UsageError(&'static str);
+			if let Some(wait) = wait {
+				shared.counters.count_throttled();
+				shared.connections.borrow_mut().count_throttled();
 
-impl Error for UsageError {}
+				tokio::select! {
+					_ = time::delay_for(wait) => {},
+					_ = shutdown_requested(&mut shutdown) => break,
+				}
+			}
 
-impl fmt::Display for UsageError {
-	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-		write!(f, "{}", self.0)
-	}
-}
+			// Latency is measured from when the request has been read until its response has been written.
+			let start = Instant::now();
+			let kind = RequestKind::of(&request);
+			let trace = shared.tracer.as_ref().map(|tracer| tracer.start_trace());
+			shared.counters.count_request(&request);
+			shared.connections.borrow_mut().count_request(uid);
 
-fn show_usage() {
-	eprintln!("Usage: iptooled <persist-path> <socket-path>");
-}
+			let request =
+				if shared.unwrap_tunnels {
+					request.map_address(Address::unwrap_tunnel)
+				} else {
+					request
+				};
 
-async fn interact(tree: Rc<RefCell<SpamTree>>, mut client: UnixStream) {
-	let (client_read, mut client_write) = client.split();
-	let mut reader = BufReader::new(client_read);
+			let address = request.address().cloned();
 
-	let result: Result<!, ReadError> = try {
-		loop {
-			match read_request(&mut reader).await? {
-				Request::Query(address) => {
-					let query_result = tree.borrow_mut().query(&address, CoarseSystemTime::now());
-					let mut response = [0; 9];
+			match request {
+				Request::Query(address, form) => {
+					let QueryResult { stats, prefix_bits, .. } = shared.query(&address);
+					client_write.write_all(&query_response(&[stats.trusted_users, stats.spam_users], prefix_bits, form)).await?;
+				}
+				Request::CategoryQuery(address, form) => {
+					let QueryResult { stats, prefix_bits, .. } = shared.query(&address);
+					let counts = [stats.trusted_users, stats.spam_users, stats.abuse_users, stats.phishing_users, stats.bruteforce_users];
+					client_write.write_all(&query_response(&counts, prefix_bits, form)).await?;
+				}
+				Request::WeightedQuery(address, form) => {
+					let WeightedResult { weights, prefix_bits } = shared.tree.borrow_mut().query_weights(&address, CoarseSystemTime::now());
+					// Only the categories reported by people are in the response, which predates automated reports.
+					let weights: Vec<u32> = weights.0[..OperationType::CATEGORIES.len()].iter().map(|&weight| (weight as f32).to_bits()).collect();
+					client_write.write_all(&query_response(&weights, prefix_bits, form)).await?;
+				}
+				Request::SourceQuery(address, form) => {
+					let QueryResult { stats, prefix_bits, .. } = shared.query(&address);
+					let counts = [stats.trusted_users, stats.spam_users, stats.automated_trusted_users, stats.automated_spam_users];
+					client_write.write_all(&query_response(&counts, prefix_bits, form)).await?;
+				}
+				Request::ScoredQuery(address, form) => {
+					let QueryResult { stats, prefix_bits, .. } = shared.query(&address);
+					let probability = stats.spam_probability(&shared.prior.get()) as f32;
+					client_write.write_all(&query_response(&[stats.trusted_users, stats.spam_users, probability.to_bits()], prefix_bits, form)).await?;
+				}
+				Request::ConfidenceQuery(address, form) => {
+					let QueryResult { stats, prefix_bits, .. } = shared.query(&address);
+					let lower_bound = stats.spam_lower_bound(CONFIDENCE_Z) as f32;
+					client_write.write_all(&query_response(&[stats.trusted_users, stats.spam_users, lower_bound.to_bits()], prefix_bits, form)).await?;
+				}
+				Request::SeenQuery(address, form) => {
+					let SeenResult { stats, prefix_bits, seen } = shared.tree.borrow_mut().query_seen(&address, CoarseSystemTime::now());
+					let (first_seen, last_seen) = seen.map_or((0, 0), |(first, last)| (first.epoch_hours(), last.epoch_hours()));
+					client_write.write_all(&query_response(&[stats.trusted_users, stats.spam_users, first_seen, last_seen], prefix_bits, form)).await?;
+				}
+				Request::DistinctQuery(address, form) => {
+					let DistinctResult { stats, prefix_bits, users } = shared.tree.borrow_mut().query_distinct(&address, CoarseSystemTime::now());
+					client_write.write_all(&query_response(&[stats.trusted_users, stats.spam_users, users.unwrap_or(0)], prefix_bits, form)).await?;
+				}
+				Request::CountryQuery(address, form) => {
+					let QueryResult { stats, prefix_bits, .. } = shared.query(&address);
+					let country = shared.tree.borrow().country(&address);
+
+					let mut response = query_response(&[stats.trusted_users, stats.spam_users], prefix_bits, form);
+
+					// The country code goes before the prefix size.
+					let bits_index = response.len() - 1;
+					response.splice(bits_index..bits_index, country.unwrap_or([0, 0]).iter().cloned());
+
+					client_write.write_all(&response).await?;
+				}
+				Request::VerdictQuery(address, form) => {
+					let QueryResult { stats, prefix_bits, verdict } = shared.query(&address);
+					let mut response = query_response(&[stats.trusted_users, stats.spam_users], prefix_bits, form);
+
+					// The verdict goes before the prefix size.
+					response.insert(response.len() - 1, verdict.map_or(0, Verdict::code));
+
+					client_write.write_all(&response).await?;
+				}
+				Request::VelocityQuery(address, form) => {
+					let VelocityResult { stats, prefix_bits, recent_spam } = shared.tree.borrow_mut().query_velocity(&address, CoarseSystemTime::now());
+					client_write.write_all(&query_response(&[stats.trusted_users, stats.spam_users, recent_spam.unwrap_or(0)], prefix_bits, form)).await?;
+				}
+				Request::LabelledQuery(address, form) => {
+					let QueryResult { stats, prefix_bits, .. } = shared.query(&address);
+					let mut response = query_response(&[stats.trusted_users, stats.spam_users], prefix_bits, form);
+
+					match shared.labels.borrow().find(&address) {
+						Some((prefix, label)) => {
+							response.push(
+								match form {
+									AddressForm::Ipv4 => prefix.bits().saturating_sub(IPV4_OFFSET_BITS),
+									AddressForm::Full => prefix.bits(),
+								}
+							);
+							response.push(label.len() as u8);
+							response.extend_from_slice(label.as_bytes());
+						}
+						None => response.extend_from_slice(&[0, 0]),
+					}
+
+					client_write.write_all(&response).await?;
+				}
+				Request::HintedQuery(address, form) => {
+					let QueryResult { stats, prefix_bits, .. } = shared.query(&address);
+
+					// Reports can add hints while the response is sent.
+					let response = {
+						let mut response = query_response(&[stats.trusted_users, stats.spam_users], prefix_bits, form);
+						let hints = shared.hints.borrow();
+						let most_common = hints.most_common(&address, prefix_bits);
+
+						response.push(most_common.len() as u8);
+
+						for (hint, count) in most_common {
+							response.extend_from_slice(&count.to_be_bytes());
+							response.push(hint.len() as u8);
+							response.extend_from_slice(hint.as_bytes());
+						}
+
+						response
+					};
+
+					client_write.write_all(&response).await?;
+				}
+				Request::Report(..) | Request::HintedReport(..) | Request::Retract(_) if shared.primary.is_some() => {
+					// Replicas only apply the primary’s operations.
+					client_write.write_all(&redirect_response(shared.primary.unwrap())).await?;
+				}
+				Request::Report(type_, address, user) => {
+					let response =
+						match shared.special_range_policy(&address) {
+							SpecialRangePolicy::Accept if shared.refusing_writes() => 1,
+							SpecialRangePolicy::Accept => {
+								shared.perform(Operation(type_, address, user), uid, trace.as_ref());
+								0
+							}
+							SpecialRangePolicy::Ignore => 0,
+							SpecialRangePolicy::Reject => 1,
+						};
+
+					client_write.write_u8(response).await?;
+				}
+				Request::HintedReport(address, user, hint) => {
+					let response =
+						match shared.special_range_policy(&address) {
+							SpecialRangePolicy::Accept if shared.refusing_writes() => 1,
+							SpecialRangePolicy::Accept => {
+								// Hints from reports that aren’t accepted would let a user past their limit skew them.
+								if shared.perform(Operation(OperationType::Spam, address.clone(), user), uid, trace.as_ref()) {
+									shared.hints.borrow_mut().add(&shared.truncated(address), &hint);
+								}
+
+								0
+							}
+							SpecialRangePolicy::Ignore => 0,
+							SpecialRangePolicy::Reject => 1,
+						};
+
+					client_write.write_u8(response).await?;
+				}
+				Request::Retract(retraction) => {
+					let response =
+						if shared.refusing_writes() {
+							2
+						} else if shared.retract(retraction, uid, trace.as_ref()) {
+							0
+						} else {
+							1
+						};
+
+					client_write.write_u8(response).await?;
+				}
+				Request::SetOverride(prefix, verdict) => {
+					let succeeded = shared.set_override(prefix, verdict);
+					client_write.write_u8(if succeeded { 0 } else { 1 }).await?;
+				}
+				Request::SetLabel(prefix, label) => {
+					let succeeded = shared.set_label(prefix, label);
+					client_write.write_u8(if succeeded { 0 } else { 1 }).await?;
+				}
+				Request::ListOverrides => {
+					let response = {
+						let tree = shared.tree.borrow();
+						let overrides: Vec<_> = tree.overrides().iter().collect();
+						let mut response = Vec::with_capacity(4 + overrides.len() * (ADDRESS_BYTES + 2));
+
+						response.extend_from_slice(&(overrides.len() as u32).to_be_bytes());
+
+						for (prefix, verdict) in overrides {
+							response.extend_from_slice(&prefix.first().0);
+							response.push(prefix.bits());
+							response.push(verdict.code());
+						}
+
+						response
+					};
+
+					client_write.write_all(&response).await?;
+				}
+				Request::ListPrefixes => {
+					// Writes can continue while the list is sent, so it comes from a snapshot.
+					let snapshot = shared.tree.borrow_mut().snapshot(CoarseSystemTime::now());
+					client_write.write_all(&(snapshot.prefix_count() as u32).to_be_bytes()).await?;
+
+					let mut prefixes = snapshot.prefixes().peekable();
+
+					while prefixes.peek().is_some() {
+						let mut response = Vec::with_capacity(PREFIX_LIST_CHUNK * (ADDRESS_BYTES + 1 + 5 * 4));
+
+						for (prefix, stats) in prefixes.by_ref().take(PREFIX_LIST_CHUNK) {
+							response.extend_from_slice(&prefix.first().0);
+							response.push(prefix.bits());
+
+							for count in &[stats.trusted_users, stats.spam_users, stats.abuse_users, stats.phishing_users, stats.bruteforce_users] {
+								response.extend_from_slice(&count.to_be_bytes());
+							}
+						}
+
+						client_write.write_all(&response).await?;
+					}
+				}
+				Request::UserEntries(user) => {
+					let entries = shared.tree.borrow_mut().user_entries(shared.salted(user), CoarseSystemTime::now());
+					let mut response = Vec::with_capacity(4 + entries.len() * (1 + ADDRESS_BYTES + 4));
+
+					response.extend_from_slice(&(entries.len() as u32).to_be_bytes());
 
-					response[0..4].copy_from_slice(&query_result.stats.trusted_users.to_be_bytes());
-					response[4..8].copy_from_slice(&query_result.stats.spam_users.to_be_bytes());
-					response[8] = query_result.prefix_bits;
+					for entry in entries {
+						response.push(entry.type_.code());
+						response.extend_from_slice(&entry.address.0);
+						response.extend_from_slice(&entry.time.epoch_hours().to_be_bytes());
+					}
 
 					client_write.write_all(&response).await?;
 				}
-				Request::Trust(address, user) => {
-					tree.borrow_mut().trust(address, user, CoarseSystemTime::now());
+				Request::Stats => {
+					let (size, country_counts) = {
+						let tree = shared.tree.borrow();
+						(tree.size(), tree.country_counts())
+					};
+
+					let mut response = Vec::with_capacity(5 * 8);
+
+					for value in &[size.prefixes, size.users, size.user_window_entries, size.address_window_entries, size.estimated_bytes] {
+						response.extend_from_slice(&(*value as u64).to_be_bytes());
+					}
+
+					if let Some(country_counts) = country_counts {
+						response.extend_from_slice(&(country_counts.len() as u32).to_be_bytes());
+
+						for (country, stats) in country_counts {
+							response.extend_from_slice(&country);
+
+							for count in &[stats.trusted_users, stats.spam_users, stats.abuse_users, stats.phishing_users, stats.bruteforce_users] {
+								response.extend_from_slice(&count.to_be_bytes());
+							}
+						}
+					}
+
+					client_write.write_all(&response).await?;
+				}
+				Request::MemoryStats => {
+					let memory = shared.tree.borrow().memory_usage();
+					let parts = memory.parts();
+					let mut response = Vec::with_capacity((parts.len() + 1) * 8);
+
+					for &(_, bytes) in parts.iter() {
+						response.extend_from_slice(&(bytes as u64).to_be_bytes());
+					}
+
+					response.extend_from_slice(&(shared.connection_buffer_bytes() as u64).to_be_bytes());
+					client_write.write_all(&response).await?;
+				}
+				Request::WindowStats => {
+					let stats = shared.tree.borrow().window_stats();
+					let mut response = Vec::with_capacity(10 * 8);
+					response.extend_from_slice(&stats.clamped_times.to_be_bytes());
+
+					for window in &[stats.user_window, stats.address_window, stats.velocity_window] {
+						for value in &[window.entries, window.bytes, window.max_entries] {
+							response.extend_from_slice(&(*value as u64).to_be_bytes());
+						}
+					}
+
+					client_write.write_all(&response).await?;
+				}
+				Request::LatencyStats => {
+					let response = {
+						let latencies = shared.latencies.borrow();
+						let mut response = Vec::with_capacity(RequestKind::ALL.len() * (8 + 4 * (PERCENTILES.len() + 1)));
+
+						for (_, histogram) in latencies.iter() {
+							response.extend_from_slice(&histogram.count().to_be_bytes());
+
+							for &per_mille in &PERCENTILES {
+								response.extend_from_slice(&histogram.percentile(per_mille).to_be_bytes());
+							}
+
+							response.extend_from_slice(&histogram.max().to_be_bytes());
+						}
+
+						response
+					};
+
+					client_write.write_all(&response).await?;
+				}
+				Request::ConnectionStats => {
+					let response = {
+						let connections = shared.connections.borrow();
+						let requests_by_uid = connections.requests_by_uid();
+						let mut response = Vec::with_capacity(6 * 8 + 4 + requests_by_uid.len() * 12);
+
+						for value in [connections.accepted(), connections.active()].iter().chain(connections.disconnects()) {
+							response.extend_from_slice(&value.to_be_bytes());
+						}
+
+						response.extend_from_slice(&(requests_by_uid.len() as u32).to_be_bytes());
+
+						for (uid, requests) in requests_by_uid {
+							response.extend_from_slice(&uid.to_be_bytes());
+							response.extend_from_slice(&requests.to_be_bytes());
+						}
+
+						response
+					};
+
+					client_write.write_all(&response).await?;
+				}
+				Request::StructureStats => {
+					let structure = shared.tree.borrow().structure();
+					let mut response = Vec::with_capacity(structure.prefixes.len() * 16 + structure.children.len() * 8);
+
+					for (prefixes, estimated_bytes) in structure.prefixes.iter().zip(&structure.estimated_bytes) {
+						response.extend_from_slice(&(*prefixes as u64).to_be_bytes());
+						response.extend_from_slice(&(*estimated_bytes as u64).to_be_bytes());
+					}
+
+					for count in &structure.children {
+						response.extend_from_slice(&(*count as u64).to_be_bytes());
+					}
+
+					client_write.write_all(&response).await?;
+				}
+				Request::Keepalive => {
 					client_write.write_u8(0).await?;
 				}
-				Request::Spam(address, user) => {
-					tree.borrow_mut().spam(address, user, CoarseSystemTime::now());
+				Request::Shutdown => {
+					info!("shutdown requested");
 					client_write.write_u8(0).await?;
+					let _ = shared.shutdown.broadcast(true);
+				}
+				Request::Version => {
+					let features = build_info::features().join(",");
+					let mut response = vec![PROTOCOL_VERSION];
+
+					for field in &[build_info::VERSION, build_info::COMMIT, &features] {
+						response.push(field.len() as u8);
+						response.extend_from_slice(field.as_bytes());
+					}
+
+					client_write.write_all(&response).await?;
+				}
+				Request::ListTunables => {
+					let mut response = vec![Tunable::ALL.len() as u8];
+
+					for &tunable in Tunable::ALL.iter() {
+						let value = shared.tunable(tunable);
+
+						for field in &[tunable.name(), &value] {
+							response.push(field.len() as u8);
+							response.extend_from_slice(field.as_bytes());
+						}
+					}
+
+					client_write.write_all(&response).await?;
+				}
+				Request::SetTunable(name, value) => {
+					let result =
+						match Tunable::from_name(&name) {
+							Some(tunable) => shared.set_tunable(tunable, &value),
+							None => Err("isn’t a tunable".to_owned()),
+						};
+
+					match result {
+						Ok(()) => client_write.write_u8(0).await?,
+						Err(reason) => {
+							warn!(name = %name, value = %value, reason = %reason, "rejected a tunable’s value");
+							client_write.write_u8(1).await?;
+						}
+					}
+				}
+				Request::Flush => {
+					shared.flush_log();
+					client_write.write_u8(if shared.log_failures.get() == 0 { 0 } else { 1 }).await?;
+				}
+				Request::Health => {
+					// Flushing checks that writes succeed now, instead of whenever the buffer next fills.
+					shared.flush_log();
+
+					let lag = shared.loop_lag.replace(Duration::from_secs(0));
+					let mut status = 0;
+
+					if shared.log_failures.get() != 0 {
+						status |= HEALTH_LOG_FAILING;
+					}
+
+					if shared.degraded_since.get().is_some() {
+						status |= HEALTH_DEGRADED;
+					}
+
+					if lag > MAX_HEALTHY_LAG {
+						status |= HEALTH_LAGGING;
+					}
+
+					if shared.primary.is_some() && !shared.following.get() {
+						status |= HEALTH_NOT_FOLLOWING;
+					}
+
+					let mut response = vec![status];
+					response.extend_from_slice(&(lag.as_millis().min(u128::from(u32::max_value())) as u32).to_be_bytes());
+					client_write.write_all(&response).await?;
 				}
 			}
+
+			if let (Some(tracer), Some(trace)) = (&shared.tracer, &trace) {
+				tracer.record_request(trace, kind, uid, received, start);
+			}
+
+			let duration = start.elapsed();
+			shared.record_latency(kind, duration);
+			shared.log_if_slow(kind, address, duration);
 		}
 	};
 
-	match result {
-		Ok(_) => unreachable!(),
-		Err(ReadError::End) => {},
-		Err(err) => eprintln!("client error: {}", err),
-	}
+	let reason =
+		match result {
+			// The daemon is shutting down; closing the connection tells the client.
+			Ok(()) => {
+				let _ = client_write.shutdown().await;
+				Disconnect::Kicked
+			},
+			Err(ReadError::End) => Disconnect::End,
+			Err(ReadError::Timeout) => {
+				info!("client idle timeout");
+				Disconnect::Timeout
+			},
+			Err(err) => {
+				let kind =
+					match err {
+						ReadError::FormatError(_) => ClientErrorKind::Format,
+						ReadError::Forbidden => ClientErrorKind::Forbidden,
+						_ => ClientErrorKind::Io,
+					};
+
+				if shared.client_errors.borrow_mut().record(uid, kind, Instant::now()) {
+					warn!(error = %err, "client error");
+				}
+
+				Disconnect::Error
+			},
+		};
+
+	shared.connections.borrow_mut().disconnect(reason);
 
 	// TODO: dropping the socket seems to close it, but is that reliable?
 }
 
-async fn async_main(socket_path: OsString) -> Result<(), Box<dyn Error>> {
-	let tree = Rc::new(RefCell::new(SpamTree::new()));
-	let mut listener = UnixListener::bind(Path::new(&socket_path))?;
+/// Waits for SIGTERM or SIGINT. The handlers are installed immediately, not when the future is first polled.
+#[cfg(unix)]
+fn stop_signal() -> io::Result<impl Future<Output = ()>> {
+	use tokio::signal::unix::{SignalKind, signal};
+
+	let mut terminate = signal(SignalKind::terminate())?;
+	let mut interrupt = signal(SignalKind::interrupt())?;
+
+	Ok(async move {
+		tokio::select! {
+			_ = terminate.recv() => {},
+			_ = interrupt.recv() => {},
+		}
+	})
+}
+
+/// Waits for Ctrl+C (or Ctrl+Break).
+#[cfg(windows)]
+fn stop_signal() -> io::Result<impl Future<Output = ()>> {
+	Ok(async {
+		let _ = tokio::signal::ctrl_c().await;
+	})
+}
+
+/// Serves until `stop` completes or a shutdown is requested, returning `stop`’s result if it was what stopped serving.
+async fn async_main<T>(tree: SpamTree, log: OperationLog, mut listener: Listener, mut admin_listener: Option<Listener>, replication_listener: Option<TcpListener>, stop: impl Future<Output = T>, options: &ServeOptions) -> Result<Option<T>, Box<dyn Error>> {
+	let (shared, shutdown_receiver) = Shared::new(tree, log, options)?;
+	let (active_sender, mut active_receiver) = mpsc::channel(1);
+	spawn_background_tasks(&shared, options, &shutdown_receiver);
+
+	if let (Some(replication), Some(replication_listener)) = (&shared.replication, replication_listener) {
+		task::spawn_local(accept_followers(replication.clone(), replication_listener, shutdown_receiver.clone()));
+	}
+
+	let mut shutdown = shutdown_receiver.clone();
+	let mut stopped = None;
+	let mut next_connection_id: u64 = 0;
+	tokio::pin!(stop);
 
 	loop {
+		let (accepted, admin) = tokio::select! {
+			accepted = listener.accept() => (accepted, false),
+			accepted = async { admin_listener.as_mut().unwrap().accept().await }, if admin_listener.is_some() => (accepted, true),
+			result = &mut stop => {
+				stopped = Some(result);
+				break;
+			},
+			_ = shutdown_requested(&mut shutdown) => break,
+		};
+
 		let client =
-			match listener.accept().await {
+			match accepted {
 				Err(err) => {
-					eprintln!("accept failed: {}", err);
+					error!(error = %err, "accept failed");
 					continue;
 				}
-				Ok((client, _)) => {
-					eprintln!("new client: {:?}", client.peer_cred());
-					client
+				Ok(client) => client,
+			};
+
+		// Everything logged for the connection is tagged with its span.
+		let uid = peer_uid(&client);
+		let span =
+			if admin {
+				info_span!("admin", id = next_connection_id, uid = ?uid)
+			} else {
+				info_span!("connection", id = next_connection_id, uid = ?uid)
+			};
+		span.in_scope(|| info!("new client"));
+		next_connection_id += 1;
+
+		let (client_read, client_write) = tokio::io::split(client);
+		task::spawn_local(interact(shared.clone(), client_read, client_write, uid, admin, shutdown_receiver.clone(), active_sender.clone()).instrument(span));
+	}
+
+	info!("shutting down");
+	drop(listener);
+
+	// Tell connections to close after their current request, then wait for them to do so: the receiver only returns `None` once every sender is gone.
+	let _ = shared.shutdown.broadcast(true);
+	drop(active_sender);
+
+	if time::timeout(SHUTDOWN_DEADLINE, active_receiver.recv()).await.is_err() {
+		warn!("some clients didn’t finish before the shutdown deadline");
+	}
+
+	shared.summarize_client_errors(None);
+
+	if let Some(degraded_for) = shared.persistence_failed.get() {
+		return Err(Box::new(PersistenceFailed(degraded_for)));
+	}
+
+	shared.log.borrow_mut().flush()?;
+
+	Ok(stopped)
+}
+
+/// Accepts followers on the replication listener and streams operations to each one, until a shutdown is requested.
+async fn accept_followers(replication: Rc<RefCell<ReplicationLog>>, mut listener: TcpListener, mut shutdown: watch::Receiver<bool>) {
+	loop {
+		let accepted = tokio::select! {
+			accepted = listener.accept() => accepted,
+			_ = shutdown_requested(&mut shutdown) => break,
+		};
+
+		let (stream, address) =
+			match accepted {
+				Err(err) => {
+					error!(error = %err, "accept failed");
+					continue;
 				}
+				Ok(accepted) => accepted,
 			};
 
-		task::spawn_local(interact(tree.clone(), client));
+		let span = info_span!("follower", address = %address);
+		span.in_scope(|| info!("new follower"));
+
+		let replication = replication.clone();
+		let mut shutdown = shutdown.clone();
+
+		task::spawn_local(async move {
+			if let Err(err) = replication::stream_to_follower(replication, stream, shutdown_requested(&mut shutdown)).await {
+				warn!(error = %err, "stopped streaming to follower");
+			}
+		}.instrument(span));
 	}
 }
 
-fn main() -> ExitCode {
-	let result: Result<(), Box<dyn Error>> = try {
-		let mut args = env::args_os();
-		let _ = args.next();
+/// Serves one client until it disconnects, `stop` completes, or a shutdown is requested.
+#[cfg(unix)]
+async fn single_session<R: AsyncRead + Unpin, W: AsyncWrite + Unpin>(tree: SpamTree, log: OperationLog, client_read: R, client_write: W, stop: impl Future<Output = ()>, options: &ServeOptions) -> Result<(), Box<dyn Error>> {
+	let (shared, shutdown_receiver) = Shared::new(tree, log, options)?;
+	let (active_sender, _) = mpsc::channel(1);
+	spawn_background_tasks(&shared, options, &shutdown_receiver);
 
-		let socket_path =
-			match args.next() {
-				Some(path) => path,
-				None => {
-					show_usage();
-					Err(UsageError("Socket path is required"))?
-				},
+	let session = interact(shared.clone(), client_read, client_write, None, false, shutdown_receiver, active_sender).instrument(info_span!("stdio"));
+	tokio::pin!(session);
+
+	tokio::select! {
+		_ = &mut session => {},
+		_ = stop => {
+			// Let the client’s current request finish, like a connection to the socket.
+			let _ = shared.shutdown.broadcast(true);
+
+			if time::timeout(SHUTDOWN_DEADLINE, session).await.is_err() {
+				warn!("the client didn’t finish before the shutdown deadline");
+			}
+		},
+	}
+
+	shared.summarize_client_errors(None);
+
+	if let Some(degraded_for) = shared.persistence_failed.get() {
+		return Err(Box::new(PersistenceFailed(degraded_for)));
+	}
+
+	shared.log.borrow_mut().flush()?;
+
+	Ok(())
+}
+
+/// Runs a future on a single-threaded runtime, where tasks can be spawned with `spawn_local`.
+fn run_local<T>(future: impl Future<Output = Result<T, Box<dyn Error>>>) -> Result<T, Box<dyn Error>> {
+	let mut single_threaded_runtime =
+		runtime::Builder::new()
+			.enable_io()
+			.enable_time()
+			.basic_scheduler()
+			.build()?;
+
+	task::LocalSet::new().block_on(&mut single_threaded_runtime, future)
+}
+
+/// Serves one client over stdin and stdout, for inetd. Log messages go to the log file if there is one and are otherwise discarded, since inetd connects stderr to the client too.
+#[cfg(unix)]
+fn serve_stdio(options: &ServeOptions) -> Result<(), Box<dyn Error>> {
+	daemon::redirect_stderr(options.log_path.as_deref().unwrap_or_else(|| Path::new("/dev/null")))?;
+
+	if let Some(chroot_path) = &options.chroot_path {
+		daemon::change_root(chroot_path)?;
+	}
+
+	let mut tree = new_tree(options)?;
+	let log = OperationLog::open(&options.persist_path.join(LOG_FILE_NAME), &mut tree)?;
+	merge_imports(&mut tree, options)?;
+
+	run_local(async {
+		let stop = stop_signal()?;
+		let (client_read, client_write) = stdio::stdin_stdout()?;
+
+		#[cfg(all(target_os = "linux", feature = "sandbox"))]
+		{
+			if options.sandbox {
+				sandbox::restrict(&options.persist_path, &[])?;
+			}
+		}
+
+		single_session(tree, log, client_read, client_write, stop, options).await
+	})
+}
+
+/// Binds the admin socket so only the daemon’s own user can connect to it, replacing the one a daemon being taken over leaves behind.
+#[cfg(unix)]
+fn bind_admin_socket(path: &Path, replace: bool) -> io::Result<(StdUnixListener, RemovableFile)> {
+	use std::fs::{self, Permissions};
+	use std::os::unix::fs::PermissionsExt;
+
+	if replace {
+		match fs::remove_file(path) {
+			Err(err) if err.kind() != io::ErrorKind::NotFound => return Err(err),
+			_ => {}
+		}
+	}
+
+	let listener = StdUnixListener::bind(path)?;
+	let file = RemovableFile::new(path)?;
+	fs::set_permissions(path, Permissions::from_mode(0o600))?;
+
+	Ok((listener, file))
+}
+
+#[cfg(unix)]
+fn serve(options: &ServeOptions) -> Result<(), Box<dyn Error>> {
+	let socket_path =
+		match &options.socket_path {
+			Some(socket_path) => socket_path,
+			None => return serve_stdio(options),
+		};
+
+	if let Some(log_path) = &options.log_path {
+		if !options.daemonize {
+			daemon::redirect_stderr(log_path)?;
+		}
+	}
+
+	// Resolved before changing root, since that’s where they’re bound.
+	let mut sockets = vec![("main", absolute(socket_path))];
+	sockets.extend(options.admin_socket_path.as_deref().map(|path| ("admin", absolute(path))));
+	sockets.extend(options.handoff_path.as_deref().map(|path| ("handoff", absolute(path))));
+
+	// A running daemon’s listening socket is taken over instead of binding a new one, so no connections are refused.
+	let (listener, takeover) =
+		match options.handoff_path.as_deref().map(handoff::take_over).transpose()?.flatten() {
+			Some((listener, takeover)) => (listener, Some(takeover)),
+			None => (StdUnixListener::bind(socket_path)?, None),
+		};
+
+	let socket_file = RemovableFile::new(socket_path)?;
+
+	let (admin_listener, admin_socket_file) =
+		match &options.admin_socket_path {
+			Some(admin_socket_path) => {
+				let (admin_listener, admin_socket_file) = bind_admin_socket(admin_socket_path, takeover.is_some())?;
+				(Some(admin_listener), Some(admin_socket_file))
+			}
+			None => (None, None),
+		};
+
+	let replication_listener = options.replication_address.as_deref().map(StdTcpListener::bind).transpose()?;
+
+	let (handoff_listener, handoff_file) =
+		match &options.handoff_path {
+			Some(handoff_path) => (Some(handoff::bind(handoff_path)?), Some(RemovableFile::new(handoff_path)?)),
+			None => (None, None),
+		};
+
+	let daemonizer =
+		if options.daemonize {
+			Some(Daemonizer::prepare(options.pid_path.as_deref(), options.log_path.as_deref())?)
+		} else {
+			if let Some(pid_path) = &options.pid_path {
+				daemon::write_pid_file(pid_path)?;
+			}
+
+			None
+		};
+
+	let pid_file =
+		match &options.pid_path {
+			Some(pid_path) => Some(RemovableFile::new(pid_path)?),
+			None => None,
+		};
+
+	if let Some(chroot_path) = &options.chroot_path {
+		daemon::change_root(chroot_path)?;
+	}
+
+	// Replay the log before daemonizing, so that errors are visible.
+	let replay_start = Instant::now();
+	let mut tree = new_tree(options)?;
+	let log_path = options.persist_path.join(LOG_FILE_NAME);
+	let log_existed = log_path.exists();
+
+	let log =
+		match takeover {
+			// Most of the log can be replayed while the old daemon finishes up, leaving only what it wrote in the meantime.
+			Some(takeover) => {
+				let offset = OperationLog::replay_in_progress(&log_path, &mut tree)?;
+				takeover.wait()?;
+				OperationLog::open_from(&log_path, &mut tree, offset)?
+			}
+			None => OperationLog::open(&log_path, &mut tree)?,
+		};
+
+	merge_imports(&mut tree, options)?;
+
+	let replayed = Replayed {
+		existed: log_existed,
+		operations: operation_count(log_path.metadata()?.len()),
+		duration: replay_start.elapsed(),
+	};
+
+	if let Some(daemonizer) = daemonizer {
+		daemonizer.detach()?;
+	}
+
+	// Once daemonized, so it goes to the log file.
+	log_startup(options, &sockets, &replayed, &tree);
+
+	// The socket, PID file, and handoff socket are removed when dropped, including on errors.
+	let handed_off = run_local(async {
+		// Everything that opens files or installs handlers has to happen before the sandbox is applied.
+		let signal = stop_signal()?;
+		let listener = Listener::from_std(listener)?;
+		let admin_listener = admin_listener.map(Listener::from_std).transpose()?;
+		let replication_listener = replication_listener.map(TcpListener::from_std).transpose()?;
+
+		let handoff =
+			match handoff_listener {
+				Some(handoff_listener) => Some(handoff::hand_off(UnixListener::from_std(handoff_listener)?, listener.as_raw_fd())),
+				None => None,
 			};
 
-		if !args.next().is_none() {
-			show_usage();
-			Err(UsageError("Too many arguments"))?;
+		let stop = async move {
+			match handoff {
+				Some(handoff) => tokio::select! {
+					_ = signal => None,
+					stream = handoff => Some(stream),
+				},
+				None => {
+					signal.await;
+					None
+				}
+			}
+		};
+
+		#[cfg(all(target_os = "linux", feature = "sandbox"))]
+		{
+			if options.sandbox {
+				let mut removable = vec![&socket_file];
+				removable.extend(&pid_file);
+				removable.extend(&admin_socket_file);
+				removable.extend(&handoff_file);
+				sandbox::restrict(&options.persist_path, &removable)?;
+			}
 		}
 
-		let mut single_threaded_runtime =
-			runtime::Builder::new()
-				.enable_io()
-				.basic_scheduler()
-				.build()?;
+		Ok(async_main(tree, log, listener, admin_listener, replication_listener, stop, options).await?.flatten())
+	})?;
+
+	// The new daemon has replaced these files, and finds out that the log is complete when the handoff connection closes on exit.
+	if handed_off.is_some() {
+		mem::forget(socket_file);
+		mem::forget(pid_file);
+		mem::forget(admin_socket_file);
+		mem::forget(handoff_file);
+	}
+
+	Ok(())
+}
 
-		let local = task::LocalSet::new();
+#[cfg(windows)]
+fn serve(options: &ServeOptions) -> Result<(), Box<dyn Error>> {
+	let replay_start = Instant::now();
+	let mut tree = new_tree(options)?;
+	let log_path = options.persist_path.join(LOG_FILE_NAME);
+	let log_existed = log_path.exists();
+	let log = OperationLog::open(&log_path, &mut tree)?;
+	merge_imports(&mut tree, options)?;
+	let replication_listener = options.replication_address.as_deref().map(StdTcpListener::bind).transpose()?;
 
-		local.block_on(
-			&mut single_threaded_runtime,
-			async_main(socket_path)
-		)?
+	let replayed = Replayed {
+		existed: log_existed,
+		operations: operation_count(log_path.metadata()?.len()),
+		duration: replay_start.elapsed(),
 	};
 
+	// Named pipes aren’t in the file system, so their names are logged as they are.
+	log_startup(options, &[("main", options.socket_path.clone().unwrap())], &replayed, &tree);
+
+	run_local(async {
+		let stop = stop_signal()?;
+		// Only optional for `--stdio`, which doesn’t exist on Windows.
+		let listener = Listener::bind(options.socket_path.as_ref().unwrap())?;
+
+		let replication_listener = replication_listener.map(TcpListener::from_std).transpose()?;
+
+		async_main(tree, log, listener, None, replication_listener, stop, options).await?;
+		Ok(())
+	})
+}
+
+/// Logs panics with a backtrace wherever log messages go, then flushes the operation log and aborts, so a bug neither loses the reports still buffered nor leaves the daemon serving a tree it was in the middle of changing.
+fn install_panic_hook() {
+	panic::set_hook(Box::new(|info| {
+		error!(backtrace = %Backtrace::force_capture(), "{}", info);
+
+		// The log can’t be flushed if the panic happened while writing to it.
+		let shared = SERVING.try_with(|serving| serving.borrow().upgrade()).ok().flatten();
+
+		if let Some(log) = shared.as_ref().and_then(|shared| shared.log.try_borrow_mut().ok()).as_mut() {
+			match log.flush() {
+				Ok(()) => info!("flushed operation log after panic"),
+				Err(err) => error!(error = %err, "failed to flush operation log after panic"),
+			}
+		}
+
+		process::abort();
+	}));
+}
+
+fn main() -> ExitCode {
+	let command = cli::parse_args();
+
+	let logging_result =
+		match &command {
+			Command::Serve(options) => logging::init(&options.log_filter, options.log_format, options.log_target),
+			_ => logging::init(logging::DEFAULT_FILTER, LogFormat::Text, LogTarget::Stderr),
+		};
+
+	if let Err(err) = logging_result {
+		eprintln!("Error: couldn’t connect to the system log: {}", err);
+		return ExitCode::FAILURE;
+	}
+
+	let result =
+		match command {
+			Command::Serve(options) => {
+				install_panic_hook();
+				serve(&options)
+			}
+			Command::Dump(persist_path) => inspect::dump(&persist_path),
+			Command::Verify(persist_path) => inspect::verify(&persist_path),
+			Command::Diff(persist_path, other_persist_path) => inspect::diff(&persist_path, &other_persist_path),
+			Command::Replay(options) => inspect::replay(&options),
+			#[cfg(any(test, feature = "bench"))]
+			Command::Bench(options) => {
+				bench::bench(&options);
+				Ok(())
+			}
+		};
+
 	match result {
 		Ok(()) => ExitCode::SUCCESS,
 		Err(err) => {
-			eprintln!("Error: {}", err);
-			ExitCode::FAILURE
+			error!("{}", err);
+
+			if err.is::<PersistenceFailed>() {
+				ExitCode::from(EXIT_PERSISTENCE_FAILED)
+			} else {
+				ExitCode::FAILURE
+			}
 		},
 	}
 }
@@ -0,0 +1,277 @@
+use std::convert::TryInto;
+use std::fs;
+use std::io::{self, ErrorKind};
+use std::path::Path;
+
+use super::address::{ADDRESS_BITS, Address, IPV4_OFFSET_BITS};
+
+/// Marks the start of the metadata, which is in the last 128 KiB of the file.
+const METADATA_MARKER: &[u8] = b"\xab\xcd\xefMaxMind.com";
+
+/// The zero bytes between the search tree and the data section.
+const DATA_SEPARATOR_BYTES: usize = 16;
+
+/// How deeply maps and arrays can nest, so a malformed database can’t overflow the stack.
+const MAX_DEPTH: u32 = 32;
+
+/// A value from the data section. Only the types that GeoIP2 and GeoLite2 databases use are distinguished.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+	String(String),
+	Unsigned(u64),
+	Signed(i32),
+	Float(f64),
+	Boolean(bool),
+	Map(Vec<(String, Value)>),
+	Array(Vec<Value>),
+	/// Bytes and 128-bit integers, which aren’t used for anything here.
+	Other,
+}
+
+impl Value {
+	/// Gets the value for a key in a map.
+	pub fn get(&self, key: &str) -> Option<&Value> {
+		match self {
+			Self::Map(entries) => entries.iter().find(|(k, _)| k == key).map(|(_, value)| value),
+			_ => None,
+		}
+	}
+}
+
+/// Reads values from a data section, following pointers.
+struct Decoder<'a> {
+	data: &'a [u8],
+}
+
+impl<'a> Decoder<'a> {
+	fn bytes(&self, offset: usize, count: usize) -> Option<&'a [u8]> {
+		self.data.get(offset..offset.checked_add(count)?)
+	}
+
+	fn unsigned(&self, offset: usize, count: usize) -> Option<u64> {
+		if count > 8 {
+			return None;
+		}
+
+		Some(self.bytes(offset, count)?.iter().fold(0, |n, &b| n << 8 | u64::from(b)))
+	}
+
+	/// Decodes the value at an offset, returning it and the offset after it.
+	fn decode(&self, offset: usize, depth: u32) -> Option<(Value, usize)> {
+		if depth > MAX_DEPTH {
+			return None;
+		}
+
+		let control = *self.data.get(offset)?;
+		let mut offset = offset + 1;
+		let mut type_ = control >> 5;
+
+		if type_ == 1 {
+			let size_bits = (control >> 3) & 0b11;
+			let high = usize::from(control & 0b111);
+
+			let (target, length) =
+				match size_bits {
+					0 => ((high << 8 | self.unsigned(offset, 1)? as usize), 1),
+					1 => ((high << 16 | self.unsigned(offset, 2)? as usize) + 2048, 2),
+					2 => ((high << 24 | self.unsigned(offset, 3)? as usize) + 526_336, 3),
+					_ => (self.unsigned(offset, 4)? as usize, 4),
+				};
+
+			let (value, _) = self.decode(target, depth + 1)?;
+			return Some((value, offset + length));
+		}
+
+		if type_ == 0 {
+			type_ = self.data.get(offset)?.checked_add(7)?;
+			offset += 1;
+		}
+
+		let size =
+			match control & 0x1f {
+				29 => {
+					offset += 1;
+					29 + self.unsigned(offset - 1, 1)? as usize
+				}
+				30 => {
+					offset += 2;
+					285 + self.unsigned(offset - 2, 2)? as usize
+				}
+				31 => {
+					offset += 3;
+					65_821 + self.unsigned(offset - 3, 3)? as usize
+				}
+				size => usize::from(size),
+			};
+
+		Some(
+			match type_ {
+				2 => (Value::String(String::from_utf8(self.bytes(offset, size)?.to_vec()).ok()?), offset + size),
+				3 if size == 8 => (Value::Float(f64::from_bits(self.unsigned(offset, 8)?)), offset + 8),
+				15 if size == 4 => (Value::Float(f64::from(f32::from_bits(self.unsigned(offset, 4)? as u32))), offset + 4),
+				5 | 6 | 9 => (Value::Unsigned(self.unsigned(offset, size)?), offset + size),
+				8 if size <= 4 => {
+					let n = self.unsigned(offset, size)? as u32;
+					(Value::Signed(n as i32), offset + size)
+				}
+				14 => (Value::Boolean(size != 0), offset),
+				4 | 10 => {
+					self.bytes(offset, size)?;
+					(Value::Other, offset + size)
+				}
+				7 => {
+					let mut entries = Vec::with_capacity(size.min(64));
+
+					for _ in 0..size {
+						let (key, after_key) = self.decode(offset, depth + 1)?;
+						let (value, after_value) = self.decode(after_key, depth + 1)?;
+
+						match key {
+							Value::String(key) => entries.push((key, value)),
+							_ => return None,
+						}
+
+						offset = after_value;
+					}
+
+					(Value::Map(entries), offset)
+				}
+				11 => {
+					let mut values = Vec::with_capacity(size.min(64));
+
+					for _ in 0..size {
+						let (value, after) = self.decode(offset, depth + 1)?;
+						values.push(value);
+						offset = after;
+					}
+
+					(Value::Array(values), offset)
+				}
+				_ => return None,
+			}
+		)
+	}
+}
+
+/// A MaxMind DB file, like GeoLite2-ASN and GeoLite2-Country, read into memory.
+#[derive(Debug)]
+pub struct Database {
+	contents: Vec<u8>,
+	node_count: usize,
+	record_bits: usize,
+	ip_version: u64,
+	/// The node reached by following 96 zero bits, where IPv4 addresses start in an IPv6 database.
+	ipv4_start: usize,
+}
+
+fn invalid(message: &str) -> io::Error {
+	io::Error::new(ErrorKind::InvalidData, message.to_owned())
+}
+
+impl Database {
+	pub fn read(path: &Path) -> io::Result<Self> {
+		let contents = fs::read(path)?;
+
+		let metadata_start =
+			contents.windows(METADATA_MARKER.len())
+				.rposition(|window| window == METADATA_MARKER)
+				.ok_or_else(|| invalid("not a MaxMind DB file"))?
+				+ METADATA_MARKER.len();
+
+		let (metadata, _) =
+			Decoder { data: &contents[metadata_start..] }.decode(0, 0)
+				.ok_or_else(|| invalid("invalid MaxMind DB metadata"))?;
+
+		let field = |name| match metadata.get(name) {
+			Some(&Value::Unsigned(n)) => Ok(n),
+			_ => Err(invalid("MaxMind DB metadata is missing a field")),
+		};
+
+		let node_count = field("node_count")? as usize;
+		let record_bits = field("record_size")? as usize;
+		let ip_version = field("ip_version")?;
+
+		if record_bits != 24 && record_bits != 28 && record_bits != 32 {
+			return Err(invalid("unsupported MaxMind DB record size"));
+		}
+
+		if ip_version != 4 && ip_version != 6 {
+			return Err(invalid("unsupported MaxMind DB IP version"));
+		}
+
+		if node_count.checked_mul(record_bits / 4).map_or(true, |tree_bytes| tree_bytes + DATA_SEPARATOR_BYTES > metadata_start) {
+			return Err(invalid("MaxMind DB search tree is larger than the file"));
+		}
+
+		let mut database = Self {
+			contents,
+			node_count,
+			record_bits,
+			ip_version,
+			ipv4_start: 0,
+		};
+
+		if ip_version == 6 {
+			for _ in 0..IPV4_OFFSET_BITS {
+				if database.ipv4_start >= node_count {
+					break;
+				}
+
+				database.ipv4_start = database.record(database.ipv4_start, false);
+			}
+		}
+
+		Ok(database)
+	}
+
+	/// Reads the left or right record of a node.
+	fn record(&self, node: usize, right: bool) -> usize {
+		let node_bytes = self.record_bits / 4;
+		let bytes = &self.contents[node * node_bytes..][..node_bytes];
+
+		let value =
+			match (self.record_bits, right) {
+				(24, false) => u32::from_be_bytes([0, bytes[0], bytes[1], bytes[2]]),
+				(24, true) => u32::from_be_bytes([0, bytes[3], bytes[4], bytes[5]]),
+				(28, false) => u32::from_be_bytes([bytes[3] >> 4, bytes[0], bytes[1], bytes[2]]),
+				(28, true) => u32::from_be_bytes([bytes[3] & 0x0f, bytes[4], bytes[5], bytes[6]]),
+				(_, false) => u32::from_be_bytes(bytes[..4].try_into().unwrap()),
+				(_, true) => u32::from_be_bytes(bytes[4..].try_into().unwrap()),
+			};
+
+		value as usize
+	}
+
+	/// Looks up an address, returning its data and the size of the network it’s in, in bits within the address space, where IPv4 addresses are in ::ffff:0:0/96 even though IPv6 databases put them in ::/96. Malformed data is treated as missing.
+	pub fn lookup(&self, address: &Address) -> Option<(Value, u8)> {
+		let (mut node, first_bit) =
+			match (address.is_ipv4(), self.ip_version) {
+				(true, 6) => (self.ipv4_start, IPV4_OFFSET_BITS),
+				(true, _) => (0, IPV4_OFFSET_BITS),
+				(false, 6) => (0, 0),
+				(false, _) => return None,
+			};
+
+		let mut bit = first_bit;
+
+		while node < self.node_count {
+			if bit == ADDRESS_BITS {
+				return None;
+			}
+
+			let byte = address.0[usize::from(bit / 8)];
+			node = self.record(node, byte & (0x80 >> (bit % 8)) != 0);
+			bit += 1;
+		}
+
+		if node == self.node_count {
+			return None;
+		}
+
+		let data_start = self.node_count * self.record_bits / 4 + DATA_SEPARATOR_BYTES;
+		let offset = (node - self.node_count).checked_sub(DATA_SEPARATOR_BYTES)?;
+		let (value, _) = Decoder { data: self.contents.get(data_start..)? }.decode(offset, 0)?;
+
+		Some((value, bit))
+	}
+}
@@ -0,0 +1,251 @@
+use std::cell::{Cell, RefCell};
+use std::fmt::Write;
+use std::io::{self, ErrorKind};
+use std::mem;
+use std::net::{SocketAddr, ToSocketAddrs};
+use std::process;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+use super::latency::RequestKind;
+
+/// The most spans to keep until the next export, so spans don’t pile up while the collector is down.
+const MAX_PENDING_SPANS: usize = 8192;
+
+/// The most bytes of the collector’s response to read, which only has to include the status line.
+const MAX_RESPONSE_BYTES: usize = 1024;
+
+/// OTLP’s span kind for a span that handles a request from a client.
+const SPAN_KIND_SERVER: u8 = 2;
+
+/// OTLP’s span kind for a span within the same process as its parent.
+const SPAN_KIND_INTERNAL: u8 = 1;
+
+/// The trace and span ids of a request being handled, for adding spans to its trace.
+#[derive(Clone, Copy, Debug)]
+pub struct RequestTrace {
+	trace_id: u128,
+	span_id: u64,
+}
+
+enum AttributeValue {
+	String(&'static str),
+	Int(u64),
+}
+
+/// A finished span waiting to be exported.
+struct Span {
+	trace_id: u128,
+	span_id: u64,
+	parent_id: Option<u64>,
+	name: &'static str,
+	start: Instant,
+	end: Instant,
+	attributes: Vec<(&'static str, AttributeValue)>,
+}
+
+/// Records spans for requests and sends them to an OpenTelemetry collector with OTLP over HTTP, in its JSON encoding.
+pub struct Tracer {
+	address: SocketAddr,
+	/// The `Host` header, as given in the endpoint.
+	host: String,
+	/// The path of the traces endpoint, including any path in the endpoint.
+	path: String,
+	/// A time as both an `Instant` and a `SystemTime`, to turn the monotonic times spans are recorded in into Unix times.
+	epoch: (Instant, SystemTime),
+	/// The state of the generator of trace and span ids.
+	id_state: Cell<u64>,
+	pending: RefCell<Vec<Span>>,
+}
+
+impl Tracer {
+	/// Looks up the address of a collector’s endpoint, like `http://localhost:4318`, once. Traces are sent to `/v1/traces` under it.
+	pub fn new(endpoint: &str) -> io::Result<Self> {
+		let invalid = |message| io::Error::new(ErrorKind::InvalidInput, message);
+
+		if !endpoint.starts_with("http://") {
+			return Err(invalid("only http:// endpoints are supported"));
+		}
+
+		let authority_and_path = &endpoint["http://".len()..];
+
+		let (host, path) =
+			match authority_and_path.find('/') {
+				Some(index) => authority_and_path.split_at(index),
+				None => (authority_and_path, ""),
+			};
+
+		let address =
+			match host.to_socket_addrs() {
+				Ok(mut addresses) => addresses.next(),
+				// Without a port, the default one for HTTP.
+				Err(_) => (host, 80).to_socket_addrs()?.next(),
+			}
+			.ok_or_else(|| io::Error::new(ErrorKind::NotFound, "no addresses found"))?;
+
+		let now = SystemTime::now();
+		let seed = now.duration_since(UNIX_EPOCH).map_or(0, |since_epoch| since_epoch.as_nanos() as u64) ^ (u64::from(process::id()) << 32);
+
+		Ok(Self {
+			address,
+			host: host.to_owned(),
+			path: format!("{}/v1/traces", path.trim_end_matches('/')),
+			epoch: (Instant::now(), now),
+			id_state: Cell::new(seed),
+			pending: RefCell::new(Vec::new()),
+		})
+	}
+
+	/// Generates a random id with SplitMix64, which is plenty to keep traces apart and doesn’t need a source of randomness once sandboxed.
+	fn next_id(&self) -> u64 {
+		let state = self.id_state.get().wrapping_add(0x9e37_79b9_7f4a_7c15);
+		self.id_state.set(state);
+
+		let mut z = state;
+		z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+		z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+		// Zero isn’t a valid id.
+		(z ^ (z >> 31)).max(1)
+	}
+
+	/// Starts a new trace for a request.
+	pub fn start_trace(&self) -> RequestTrace {
+		RequestTrace {
+			trace_id: u128::from(self.next_id()) << 64 | u128::from(self.next_id()),
+			span_id: self.next_id(),
+		}
+	}
+
+	fn push(&self, span: Span) {
+		let mut pending = self.pending.borrow_mut();
+
+		if pending.len() < MAX_PENDING_SPANS {
+			pending.push(span);
+		}
+	}
+
+	/// Records a step of handling a request, like updating the tree, from `start` until now.
+	pub fn record_step(&self, trace: &RequestTrace, name: &'static str, start: Instant) {
+		self.push(Span {
+			trace_id: trace.trace_id,
+			span_id: self.next_id(),
+			parent_id: Some(trace.span_id),
+			name,
+			start,
+			end: Instant::now(),
+			attributes: Vec::new(),
+		});
+	}
+
+	/// Records a request that was answered just now, from when its first byte arrived, along with how long it took to parse it.
+	pub fn record_request(&self, trace: &RequestTrace, kind: RequestKind, uid: Option<u32>, received: Instant, parsed: Instant) {
+		self.push(Span {
+			trace_id: trace.trace_id,
+			span_id: self.next_id(),
+			parent_id: Some(trace.span_id),
+			name: "parse",
+			start: received,
+			end: parsed,
+			attributes: Vec::new(),
+		});
+
+		let mut attributes = vec![("iptooled.request.kind", AttributeValue::String(kind.name()))];
+		attributes.extend(uid.map(|uid| ("iptooled.client.uid", AttributeValue::Int(u64::from(uid)))));
+
+		self.push(Span {
+			trace_id: trace.trace_id,
+			span_id: trace.span_id,
+			parent_id: None,
+			name: "request",
+			start: received,
+			end: Instant::now(),
+			attributes,
+		});
+	}
+
+	/// Gets the time of an instant in nanoseconds since the Unix epoch.
+	fn unix_nanos(&self, instant: Instant) -> u128 {
+		let (epoch_instant, epoch_time) = self.epoch;
+		let epoch_nanos = epoch_time.duration_since(UNIX_EPOCH).map_or(0, |since_epoch| since_epoch.as_nanos());
+
+		if instant >= epoch_instant {
+			epoch_nanos + (instant - epoch_instant).as_nanos()
+		} else {
+			epoch_nanos.saturating_sub((epoch_instant - instant).as_nanos())
+		}
+	}
+
+	/// Encodes spans as an OTLP `ExportTraceServiceRequest` in JSON. Names and attributes are all fixed strings and numbers, so nothing needs escaping.
+	fn encode(&self, spans: &[Span]) -> String {
+		let mut json = String::from(r#"{"resourceSpans":[{"resource":{"attributes":[{"key":"service.name","value":{"stringValue":"iptooled"}}]},"scopeSpans":[{"scope":{"name":"iptooled"},"spans":["#);
+
+		for (i, span) in spans.iter().enumerate() {
+			if i != 0 {
+				json.push(',');
+			}
+
+			let _ = write!(json, r#"{{"traceId":"{:032x}","spanId":"{:016x}","#, span.trace_id, span.span_id);
+
+			if let Some(parent_id) = span.parent_id {
+				let _ = write!(json, r#""parentSpanId":"{:016x}","#, parent_id);
+			}
+
+			let kind = if span.parent_id.is_none() { SPAN_KIND_SERVER } else { SPAN_KIND_INTERNAL };
+			let _ = write!(json, r#""name":"{}","kind":{},"startTimeUnixNano":"{}","endTimeUnixNano":"{}","attributes":["#, span.name, kind, self.unix_nanos(span.start), self.unix_nanos(span.end));
+
+			for (j, (key, value)) in span.attributes.iter().enumerate() {
+				if j != 0 {
+					json.push(',');
+				}
+
+				let _ =
+					match value {
+						AttributeValue::String(value) => write!(json, r#"{{"key":"{}","value":{{"stringValue":"{}"}}}}"#, key, value),
+						AttributeValue::Int(value) => write!(json, r#"{{"key":"{}","value":{{"intValue":"{}"}}}}"#, key, value),
+					};
+			}
+
+			json.push_str("]}");
+		}
+
+		json.push_str("]}]}]}");
+		json
+	}
+
+	/// Sends the spans recorded since the last export to the collector, dropping them if that fails.
+	pub async fn export(&self) -> io::Result<()> {
+		let spans = mem::take(&mut *self.pending.borrow_mut());
+
+		if spans.is_empty() {
+			return Ok(());
+		}
+
+		let body = self.encode(&spans);
+		let request = format!("POST {} HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}", self.path, self.host, body.len(), body);
+
+		let mut stream = TcpStream::connect(self.address).await?;
+		stream.write_all(request.as_bytes()).await?;
+
+		let mut response = Vec::new();
+
+		while !response.contains(&b'\n') && response.len() < MAX_RESPONSE_BYTES {
+			let mut buffer = [0; 256];
+			let read = stream.read(&mut buffer).await?;
+
+			if read == 0 {
+				break;
+			}
+
+			response.extend_from_slice(&buffer[..read]);
+		}
+
+		let status_line = String::from_utf8_lossy(&response);
+		let status_line = status_line.lines().next().unwrap_or("");
+
+		match status_line.split(' ').nth(1) {
+			Some(status) if status.starts_with('2') => Ok(()),
+			_ => Err(io::Error::new(ErrorKind::Other, format!("the collector responded with {:?}", status_line))),
+		}
+	}
+}
@@ -0,0 +1,124 @@
+use std::fs;
+use std::io::{self, ErrorKind};
+use std::path::Path;
+
+use super::address::{Address, AddressPrefix};
+use super::prefix_map::PrefixMap;
+
+/// The name of the file of overrides within the persistence directory.
+pub const OVERRIDES_FILE_NAME: &str = "overrides";
+
+/// A fixed result for a prefix, set by an administrator.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Verdict {
+	Trusted,
+	Spam,
+	Neutral,
+}
+
+impl Verdict {
+	/// Gets the verdict with a code used in requests and responses.
+	pub fn from_code(code: u8) -> Option<Self> {
+		Some(
+			match code {
+				1 => Self::Trusted,
+				2 => Self::Spam,
+				3 => Self::Neutral,
+				_ => return None,
+			}
+		)
+	}
+
+	pub fn code(self) -> u8 {
+		match self {
+			Self::Trusted => 1,
+			Self::Spam => 2,
+			Self::Neutral => 3,
+		}
+	}
+
+	fn from_name(name: &str) -> Option<Self> {
+		Some(
+			match name {
+				"trusted" => Self::Trusted,
+				"spam" => Self::Spam,
+				"neutral" => Self::Neutral,
+				_ => return None,
+			}
+		)
+	}
+
+	fn name(self) -> &'static str {
+		match self {
+			Self::Trusted => "trusted",
+			Self::Spam => "spam",
+			Self::Neutral => "neutral",
+		}
+	}
+}
+
+/// Prefixes pinned to verdicts. Unlike the allowlist and denylist, they can nest, and the longest matching prefix applies.
+#[derive(Clone, Debug, Default)]
+pub struct Overrides(PrefixMap<Verdict>);
+
+impl Overrides {
+	/// Reads overrides saved by `write`, or none if the file doesn’t exist yet.
+	pub fn read(path: &Path) -> io::Result<Self> {
+		let contents =
+			match fs::read_to_string(path) {
+				Ok(contents) => contents,
+				Err(err) if err.kind() == ErrorKind::NotFound => return Ok(Self::default()),
+				Err(err) => return Err(err),
+			};
+
+		let mut result = PrefixMap::new();
+
+		for (i, line) in contents.lines().enumerate() {
+			let mut fields = line.split_whitespace();
+
+			match (fields.next().and_then(AddressPrefix::parse), fields.next().and_then(Verdict::from_name), fields.next()) {
+				(Some(prefix), Some(verdict), None) => {
+					result.insert(prefix, verdict);
+				}
+				_ => return Err(io::Error::new(ErrorKind::InvalidData, format!("line {} of the overrides isn’t a prefix and a verdict", i + 1))),
+			}
+		}
+
+		Ok(Self(result))
+	}
+
+	/// Saves the overrides as lines of [*prefix*, *verdict*], replacing the file atomically.
+	pub fn write(&self, path: &Path) -> io::Result<()> {
+		let mut contents = String::new();
+
+		for (prefix, verdict) in self.0.iter() {
+			contents.push_str(&format!("{} {}\n", prefix, verdict.name()));
+		}
+
+		let temporary_path = path.with_extension("new");
+		fs::write(&temporary_path, contents)?;
+		fs::rename(&temporary_path, path)
+	}
+
+	/// Pins a prefix to a verdict, or removes its override if the verdict is `None`.
+	pub fn set(&mut self, prefix: AddressPrefix, verdict: Option<Verdict>) {
+		match verdict {
+			Some(verdict) => {
+				self.0.insert(prefix, verdict);
+			}
+			None => {
+				self.0.remove(&prefix);
+			}
+		}
+	}
+
+	/// Finds the longest overridden prefix containing an address.
+	pub fn find(&self, address: &Address) -> Option<(&AddressPrefix, Verdict)> {
+		self.0.longest_match(address).map(|(prefix, &verdict)| (prefix, verdict))
+	}
+
+
+	pub fn iter(&self) -> impl Iterator<Item = (&AddressPrefix, Verdict)> {
+		self.0.iter().map(|(prefix, &verdict)| (prefix, verdict))
+	}
+}
@@ -0,0 +1,257 @@
+#[cfg(test)]
+mod tests;
+
+use std::convert::TryInto;
+use std::error::Error;
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+#[cfg(not(feature = "io-uring"))]
+use std::io::BufWriter;
+use std::io::{self, ErrorKind, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::slice::ChunksExact;
+use std::time::Duration;
+use tracing::warn;
+
+use super::address::{ADDRESS_BYTES, Address};
+use super::time_list::CoarseSystemTime;
+use super::tree::{USER_BYTES, Operation, OperationType, Retraction, SpamTree, TreeOperation, User};
+#[cfg(feature = "io-uring")]
+use super::uring::UringWriter;
+
+/// The name of the operation log within the persistence directory.
+pub const LOG_FILE_NAME: &str = "operations";
+
+/// Identifies an operation log and the version of its format.
+const LOG_HEADER: &[u8] = b"iptooled\x01";
+
+pub const OPERATION_BYTES: usize = 1 + ADDRESS_BYTES + USER_BYTES + 4;
+
+/// Set in a record’s type when it retracts a report of that type, as in requests.
+const RETRACT_FLAG: u8 = 0x40;
+
+/// The type of a record that forgets a user, which has an all-zero address.
+const FORGET_USER_CODE: u8 = 14;
+
+/// An operation or retraction and the time it was performed, as [*type*, *address*×*address-bytes*, *user*×*user-bytes*, *epoch-hours*×4], where *type* uses the same codes as the corresponding requests.
+#[derive(Clone)]
+pub struct SerializedTreeOperation(pub [u8; OPERATION_BYTES]);
+
+impl SerializedTreeOperation {
+	fn from_parts(code: u8, address: &Address, user: User, time: CoarseSystemTime) -> Self {
+		let mut result = [0; OPERATION_BYTES];
+
+		result[0] = code;
+		result[1..][..ADDRESS_BYTES].copy_from_slice(&address.0);
+		result[1 + ADDRESS_BYTES..][..USER_BYTES].copy_from_slice(&user.to_bytes());
+		result[1 + ADDRESS_BYTES + USER_BYTES..].copy_from_slice(&time.epoch_hours().to_be_bytes());
+
+		Self(result)
+	}
+
+	pub fn new(operation: &Operation, time: CoarseSystemTime) -> Self {
+		let Operation(type_, address, user) = operation;
+		Self::from_parts(type_.code(), address, *user, time)
+	}
+
+	/// Serializes a retraction as a tombstone.
+	pub fn retraction(retraction: &Retraction, time: CoarseSystemTime) -> Self {
+		match retraction {
+			Retraction::Report(type_, address, user) => Self::from_parts(type_.code() | RETRACT_FLAG, address, *user, time),
+			Retraction::User(user) => Self::from_parts(FORGET_USER_CODE, &Address([0; ADDRESS_BYTES]), *user, time),
+		}
+	}
+
+	/// Copies a record from a slice of exactly `OPERATION_BYTES`.
+	pub fn from_slice(record: &[u8]) -> Self {
+		Self(record.try_into().unwrap())
+	}
+
+	pub fn parse(&self) -> Option<(TreeOperation, CoarseSystemTime)> {
+		let bytes = &self.0;
+		let address = Address(bytes[1..][..ADDRESS_BYTES].try_into().unwrap());
+		let user = User::from_bytes(bytes[1 + ADDRESS_BYTES..][..USER_BYTES].try_into().unwrap());
+		let time = CoarseSystemTime::from_epoch_hours(u32::from_be_bytes(bytes[1 + ADDRESS_BYTES + USER_BYTES..].try_into().unwrap()));
+
+		if bytes[0] == FORGET_USER_CODE {
+			return Some((TreeOperation::Retract(Retraction::User(user)), time));
+		}
+
+		let type_ =
+			match bytes[0] & !RETRACT_FLAG {
+				1 => OperationType::Trust,
+				2 => OperationType::Spam,
+				7 => OperationType::Abuse,
+				8 => OperationType::Phishing,
+				9 => OperationType::Bruteforce,
+				26 => OperationType::AutomatedTrust,
+				27 => OperationType::AutomatedSpam,
+				_ => return None,
+			};
+
+		let operation =
+			if bytes[0] & RETRACT_FLAG == 0 {
+				TreeOperation::Perform(Operation(type_, address, user))
+			} else {
+				TreeOperation::Retract(Retraction::Report(type_, address, user))
+			};
+
+		Some((operation, time))
+	}
+}
+
+/// Gets the number of operations in a log of a given size without an incomplete operation at the end.
+pub fn operation_count(log_bytes: u64) -> u64 {
+	log_bytes.saturating_sub(LOG_HEADER.len() as u64) / OPERATION_BYTES as u64
+}
+
+/// Reads up to `count` operations from the log at a path, starting with the one at index `from`. Operations that are still buffered aren’t read.
+pub fn read_operations(path: &Path, from: u64, count: usize) -> io::Result<Vec<SerializedTreeOperation>> {
+	let mut file = File::open(path)?;
+	let mut header = vec![0; LOG_HEADER.len()];
+	file.read_exact(&mut header)?;
+
+	if header != LOG_HEADER {
+		return Err(io::Error::new(ErrorKind::InvalidData, "not an operation log, or an unsupported version of one"));
+	}
+
+	file.seek(SeekFrom::Start(LOG_HEADER.len() as u64 + from * OPERATION_BYTES as u64))?;
+
+	let mut contents = Vec::with_capacity(count * OPERATION_BYTES);
+	file.take((count * OPERATION_BYTES) as u64).read_to_end(&mut contents)?;
+
+	Ok(contents.chunks_exact(OPERATION_BYTES).map(SerializedTreeOperation::from_slice).collect())
+}
+
+/// Checks a log’s header and splits the rest of it into records. An incomplete record at the end is left in the iterator’s remainder.
+pub fn read_records(contents: &[u8]) -> io::Result<ChunksExact<'_, u8>> {
+	if !contents.starts_with(LOG_HEADER) {
+		return Err(io::Error::new(ErrorKind::InvalidData, "not an operation log, or an unsupported version of one"));
+	}
+
+	Ok(contents[LOG_HEADER.len()..].chunks_exact(OPERATION_BYTES))
+}
+
+/// Replays the complete operations in a log’s contents that start at or after an offset, returning the size of the incomplete operation at the end, if any.
+fn replay(contents: &[u8], offset: u64, tree: &mut SpamTree) -> io::Result<usize> {
+	let records = read_records(contents)?;
+	let incomplete = records.remainder().len();
+	let skip = (offset as usize).saturating_sub(LOG_HEADER.len()) / OPERATION_BYTES;
+
+	for record in records.skip(skip) {
+		let (operation, time) =
+			SerializedTreeOperation::from_slice(record).parse()
+				.ok_or_else(|| io::Error::new(ErrorKind::InvalidData, "invalid operation in log"))?;
+
+		match operation {
+			TreeOperation::Perform(operation) => tree.perform(operation, time),
+			TreeOperation::Retract(retraction) => tree.retract(&retraction, time),
+		};
+	}
+
+	Ok(incomplete)
+}
+
+#[cfg(not(feature = "io-uring"))]
+type LogWriter = BufWriter<File>;
+
+#[cfg(feature = "io-uring")]
+type LogWriter = UringWriter;
+
+#[cfg(not(feature = "io-uring"))]
+fn log_writer(file: File) -> io::Result<LogWriter> {
+	Ok(BufWriter::new(file))
+}
+
+#[cfg(feature = "io-uring")]
+fn log_writer(file: File) -> io::Result<LogWriter> {
+	UringWriter::new(file)
+}
+
+/// An append-only log of accepted operations and retractions. Writes are buffered, so some can be lost if the process doesn’t exit cleanly.
+pub struct OperationLog {
+	file: LogWriter,
+	/// The number of operations in the log, including buffered ones.
+	operations: u64,
+}
+
+impl OperationLog {
+	/// Opens the log at a path, creating it if it doesn’t exist, and replays its operations into a tree. It’s opened for appending, so processes serving one client each (with `--stdio`) don’t overwrite each other’s operations.
+	pub fn open(path: &Path, tree: &mut SpamTree) -> io::Result<Self> {
+		Self::open_from(path, tree, 0)
+	}
+
+	/// Opens the log like `open`, but only replays the operations from an offset returned by `replay_in_progress` onwards.
+	pub fn open_from(path: &Path, tree: &mut SpamTree, offset: u64) -> io::Result<Self> {
+		let mut file = OpenOptions::new()
+			.read(true)
+			.append(true)
+			.create(true)
+			.open(path)?;
+
+		let mut contents = Vec::new();
+		file.read_to_end(&mut contents)?;
+
+		let operations =
+			if contents.is_empty() {
+				file.write_all(LOG_HEADER)?;
+				0
+			} else {
+				let incomplete = replay(&contents, offset, tree)?;
+
+				if incomplete != 0 {
+					// A write was interrupted, so the operation is lost anyway.
+					warn!("discarding incomplete operation at end of log");
+					file.set_len((contents.len() - incomplete) as u64)?;
+					file.seek(SeekFrom::End(0))?;
+				}
+
+				operation_count((contents.len() - incomplete) as u64)
+			};
+
+		Ok(Self {
+			file: log_writer(file)?,
+			operations,
+		})
+	}
+
+	/// Replays the complete operations in a log without opening it for writing, e.g. one from another deployment.
+	pub fn replay_file(path: &Path, tree: &mut SpamTree) -> io::Result<()> {
+		Self::replay_in_progress(path, tree).map(|_| ())
+	}
+
+	/// Replays the complete operations in a log that another process is still appending to, returning the offset to continue from with `open_from` once it’s done.
+	pub fn replay_in_progress(path: &Path, tree: &mut SpamTree) -> io::Result<u64> {
+		let contents = fs::read(path)?;
+		let incomplete = replay(&contents, 0, tree)?;
+
+		Ok((contents.len() - incomplete) as u64)
+	}
+
+	pub fn append(&mut self, operation: &SerializedTreeOperation) -> io::Result<()> {
+		self.file.write_all(&operation.0)?;
+		self.operations += 1;
+		Ok(())
+	}
+
+	/// Gets the number of operations in the log, including ones that are still buffered.
+	pub fn operations(&self) -> u64 {
+		self.operations
+	}
+
+	pub fn flush(&mut self) -> io::Result<()> {
+		self.file.flush()
+	}
+}
+
+/// An error from writes to the operation log failing for longer than the daemon is allowed to stay degraded.
+#[derive(Clone, Copy, Debug)]
+pub struct PersistenceFailed(pub Duration);
+
+impl fmt::Display for PersistenceFailed {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "writes to the operation log kept failing for {} seconds", self.0.as_secs())
+	}
+}
+
+impl Error for PersistenceFailed {}
@@ -0,0 +1,44 @@
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use super::super::tree::tests::{History, same_entries};
+use super::super::tree::{SpamTree, TreeOperation, TreeSettings};
+use super::{OperationLog, SerializedTreeOperation};
+
+/// Gets a path for a log that no other test uses.
+fn log_path() -> PathBuf {
+	static NEXT: AtomicUsize = AtomicUsize::new(0);
+	env::temp_dir().join(format!("iptooled-test-{}-{}", process::id(), NEXT.fetch_add(1, Ordering::Relaxed)))
+}
+
+/// Checks that replaying a log of what a tree accepted, including retractions and forgotten users, gives the same tree.
+#[quickcheck]
+fn replay_matches_live_tree(history: History) -> bool {
+	let now = history.end();
+	let path = log_path();
+	let mut live = SpamTree::new(TreeSettings::DEFAULT);
+	let mut log = OperationLog::open(&path, &mut live).unwrap();
+
+	for (operation, time) in history.apply(&mut live) {
+		let serialized =
+			match &operation {
+				TreeOperation::Perform(operation) => SerializedTreeOperation::new(operation, time),
+				TreeOperation::Retract(retraction) => SerializedTreeOperation::retraction(retraction, time),
+			};
+
+		log.append(&serialized).unwrap();
+	}
+
+	log.flush().unwrap();
+	drop(log);
+
+	let mut replayed = SpamTree::new(TreeSettings::DEFAULT);
+	let reopened = OperationLog::open(&path, &mut replayed);
+	fs::remove_file(&path).unwrap();
+	reopened.unwrap();
+
+	same_entries(&mut live, &mut replayed, now)
+}
@@ -0,0 +1,71 @@
+use std::fs;
+use std::io::{self, ErrorKind};
+use std::path::Path;
+
+use super::address::{Address, AddressPrefix};
+use super::prefix_map::PrefixSet;
+
+/// Reads a file of prefixes in CIDR notation, one per line, in the order they appear. Anything after a `#` or `;` is a comment, as in Spamhaus’s DROP lists and Tor’s exit lists, and blank lines are ignored.
+pub fn read_prefixes(path: &Path) -> io::Result<Vec<AddressPrefix>> {
+	let contents = fs::read_to_string(path)?;
+	let mut result = Vec::new();
+
+	for (i, line) in contents.lines().enumerate() {
+		let line = line.split(|c| c == '#' || c == ';').next().unwrap().trim();
+
+		if line.is_empty() {
+			continue;
+		}
+
+		match AddressPrefix::parse(line) {
+			Some(prefix) => result.push(prefix),
+			None => return Err(io::Error::new(ErrorKind::InvalidData, format!("line {} isn’t a prefix: {}", i + 1, line))),
+		}
+	}
+
+	Ok(result)
+}
+
+/// A fixed set of prefixes, with prefixes contained in other prefixes removed so that the one containing an address is the list’s widest.
+#[derive(Clone, Debug)]
+pub struct PrefixList(PrefixSet);
+
+impl PrefixList {
+	pub const EMPTY: Self = Self(PrefixSet::new());
+
+	/// Reads a file of prefixes in the format `read_prefixes` takes.
+	pub fn read(path: &Path) -> io::Result<Self> {
+		read_prefixes(path).map(Self::from_prefixes)
+	}
+
+	/// Makes a list of prefixes in any order, which can overlap.
+	pub fn from_prefixes(mut prefixes: Vec<AddressPrefix>) -> Self {
+		prefixes.sort();
+
+		let mut result = PrefixSet::new();
+		let mut last: Option<AddressPrefix> = None;
+
+		for prefix in prefixes {
+			// A prefix sorts after any prefix containing it, and before anything that doesn’t that sorts after the containing prefix.
+			match &last {
+				Some(last) if last.contains(&prefix) => {}
+				_ => {
+					result.insert(prefix.clone(), ());
+					last = Some(prefix);
+				}
+			}
+		}
+
+		Self(result)
+	}
+
+	/// Gets the number of prefixes in the list, not counting ones contained in others.
+	pub fn prefix_count(&self) -> usize {
+		self.0.iter().count()
+	}
+
+	/// Finds the prefix in the list containing an address, if there is one.
+	pub fn find(&self, address: &Address) -> Option<&AddressPrefix> {
+		self.0.longest_match(address).map(|(prefix, ())| prefix)
+	}
+}
@@ -0,0 +1,177 @@
+#[cfg(test)]
+mod tests;
+
+use super::address::{ADDRESS_BITS, Address, AddressPrefix};
+
+/// A set of prefixes, for lists where only whether an address is in one matters.
+pub type PrefixSet = PrefixMap<()>;
+
+/// Values for prefixes, which can nest, in a path-compressed binary trie, so that finding the longest prefix containing an address takes at most one step per bit of the address.
+#[derive(Clone, Debug)]
+pub struct PrefixMap<V> {
+	root: Option<Box<Node<V>>>,
+}
+
+/// A prefix in the trie, which only has a value if it was inserted, and otherwise joins two subtrees.
+#[derive(Clone, Debug)]
+struct Node<V> {
+	prefix: AddressPrefix,
+	value: Option<V>,
+	/// The subtrees of the prefixes contained in this one, by their next bit.
+	children: [Option<Box<Node<V>>>; 2],
+}
+
+/// Gets the bit of an address at an index, counting from the most significant bit, as the index of the child containing it.
+fn bit(address: &Address, index: u8) -> usize {
+	usize::from(address.0[usize::from(index / 8)] >> (7 - index % 8) & 1)
+}
+
+impl<V> Node<V> {
+	fn leaf(prefix: AddressPrefix, value: V) -> Box<Self> {
+		Box::new(Self {
+			prefix,
+			value: Some(value),
+			children: [None, None],
+		})
+	}
+
+	/// Gets the slot for the child that a prefix this one contains belongs under.
+	fn child_mut(&mut self, prefix: &AddressPrefix) -> &mut Option<Box<Self>> {
+		&mut self.children[bit(prefix.first(), self.prefix.bits())]
+	}
+}
+
+impl<V> PrefixMap<V> {
+	pub const fn new() -> Self {
+		Self {
+			root: None,
+		}
+	}
+
+	/// Sets a prefix’s value, returning the value it replaced, if any.
+	pub fn insert(&mut self, prefix: AddressPrefix, value: V) -> Option<V> {
+		Self::insert_at(&mut self.root, prefix, value)
+	}
+
+	fn insert_at(slot: &mut Option<Box<Node<V>>>, prefix: AddressPrefix, value: V) -> Option<V> {
+		let node =
+			match slot {
+				Some(node) => node,
+				None => {
+					*slot = Some(Node::leaf(prefix, value));
+					return None;
+				}
+			};
+
+		if node.prefix == prefix {
+			return node.value.replace(value);
+		}
+
+		if node.prefix.contains(&prefix) {
+			return Self::insert_at(node.child_mut(&prefix), prefix, value);
+		}
+
+		// The prefix contains the node or is beside it, so it goes where the two meet.
+		let common = AddressPrefix::common_prefix(&node.prefix, &prefix);
+		let old = slot.take().unwrap();
+		let old_index = bit(old.prefix.first(), common.bits());
+
+		let mut branch =
+			if common == prefix {
+				Node::leaf(prefix, value)
+			} else {
+				let mut branch = Box::new(Node { prefix: common, value: None, children: [None, None] });
+				branch.children[1 - old_index] = Some(Node::leaf(prefix, value));
+				branch
+			};
+
+		branch.children[old_index] = Some(old);
+		*slot = Some(branch);
+		None
+	}
+
+	/// Removes a prefix’s value, returning it if there was one.
+	pub fn remove(&mut self, prefix: &AddressPrefix) -> Option<V> {
+		Self::remove_at(&mut self.root, prefix)
+	}
+
+	fn remove_at(slot: &mut Option<Box<Node<V>>>, prefix: &AddressPrefix) -> Option<V> {
+		let node =
+			match slot {
+				Some(node) if node.prefix.contains(prefix) => node,
+				_ => return None,
+			};
+
+		let removed =
+			if node.prefix == *prefix {
+				node.value.take()
+			} else {
+				Self::remove_at(node.child_mut(prefix), prefix)
+			};
+
+		// Nodes without values are only kept to join two subtrees.
+		if node.value.is_none() && !node.children.iter().all(Option::is_some) {
+			let child = node.children.iter_mut().find_map(Option::take);
+			*slot = child;
+		}
+
+		removed
+	}
+
+	/// Finds the longest prefix with a value containing an address.
+	pub fn longest_match(&self, address: &Address) -> Option<(&AddressPrefix, &V)> {
+		let mut result = None;
+		let mut next = self.root.as_ref();
+
+		while let Some(node) = next {
+			if !node.prefix.is_prefix_of(address) {
+				break;
+			}
+
+			if let Some(value) = &node.value {
+				result = Some((&node.prefix, value));
+			}
+
+			if node.prefix.bits() == ADDRESS_BITS {
+				break;
+			}
+
+			next = node.children[bit(address, node.prefix.bits())].as_ref();
+		}
+
+		result
+	}
+
+	/// Iterates over the prefixes with values in order.
+	pub fn iter(&self) -> Iter<'_, V> {
+		Iter {
+			stack: self.root.iter().map(|node| &**node).collect(),
+		}
+	}
+}
+
+impl<V> Default for PrefixMap<V> {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+/// An iterator over a `PrefixMap`’s prefixes and values. A prefix sorts right before the prefixes it contains, so visiting each node before its children, and the 0 child before the 1 child, gives them in order.
+pub struct Iter<'a, V> {
+	stack: Vec<&'a Node<V>>,
+}
+
+impl<'a, V> Iterator for Iter<'a, V> {
+	type Item = (&'a AddressPrefix, &'a V);
+
+	fn next(&mut self) -> Option<Self::Item> {
+		loop {
+			let node = self.stack.pop()?;
+			self.stack.extend(node.children.iter().rev().flatten().map(|child| &**child));
+
+			if let Some(value) = &node.value {
+				return Some((&node.prefix, value));
+			}
+		}
+	}
+}
@@ -0,0 +1,29 @@
+use std::collections::BTreeMap;
+
+use super::super::address::{Address, AddressPrefix};
+use super::PrefixMap;
+
+/// Checks a map built by inserting and removing prefixes against a `BTreeMap` searched by brute force.
+#[quickcheck]
+fn prefix_map_matches_btree_map(changes: Vec<(AddressPrefix, Option<u8>)>, addresses: Vec<Address>) -> bool {
+	let mut map = PrefixMap::new();
+	let mut expected = BTreeMap::new();
+
+	for (prefix, value) in changes {
+		let matches =
+			match value {
+				Some(value) => map.insert(prefix.clone(), value) == expected.insert(prefix, value),
+				None => map.remove(&prefix) == expected.remove(&prefix),
+			};
+
+		if !matches {
+			return false;
+		}
+	}
+
+	map.iter().eq(expected.iter())
+		&& addresses.iter().all(|address| {
+			let longest = expected.iter().filter(|(prefix, _)| prefix.is_prefix_of(address)).max_by_key(|(prefix, _)| prefix.bits());
+			map.longest_match(address) == longest
+		})
+}
@@ -1,15 +1,56 @@
 use std::error::Error;
 use std::fmt;
+use std::time::Instant;
 use tokio::io::{self, AsyncRead, AsyncReadExt, BufReader, ErrorKind};
 
-use super::address::{ADDRESS_BYTES, Address};
-use super::tree::{USER_BYTES, User};
+use super::address::{ADDRESS_BITS, ADDRESS_BYTES, Address, AddressPrefix, IPV4_BYTES, IPV4_OFFSET_BITS};
+use super::hints::is_valid_hint;
+use super::labels::is_valid_label;
+use super::overrides::Verdict;
+use super::tree::{OperationType, Retraction, USER_BYTES, User};
 
 #[derive(Copy, Clone, Debug, Eq, Hash, PartialEq)]
 enum RequestType {
 	Query,
 	Trust,
 	Spam,
+	Keepalive,
+	Shutdown,
+	SetOverride,
+	ListOverrides,
+	Abuse,
+	Phishing,
+	Bruteforce,
+	CategoryQuery,
+	WeightedQuery,
+	ScoredQuery,
+	Stats,
+	ForgetUser,
+	SeenQuery,
+	UserEntries,
+	DistinctQuery,
+	CountryQuery,
+	VerdictQuery,
+	VelocityQuery,
+	ListPrefixes,
+	SetLabel,
+	LabelledQuery,
+	ConfidenceQuery,
+	StructureStats,
+	AutomatedTrust,
+	AutomatedSpam,
+	SourceQuery,
+	HintedSpam,
+	HintedQuery,
+	WindowStats,
+	LatencyStats,
+	ConnectionStats,
+	Health,
+	MemoryStats,
+	Flush,
+	Version,
+	ListTunables,
+	SetTunable,
 }
 
 impl RequestType {
@@ -19,17 +60,212 @@ impl RequestType {
 				0 => Self::Query,
 				1 => Self::Trust,
 				2 => Self::Spam,
+				3 => Self::Keepalive,
+				4 => Self::Shutdown,
+				5 => Self::SetOverride,
+				6 => Self::ListOverrides,
+				7 => Self::Abuse,
+				8 => Self::Phishing,
+				9 => Self::Bruteforce,
+				10 => Self::CategoryQuery,
+				11 => Self::WeightedQuery,
+				12 => Self::ScoredQuery,
+				13 => Self::Stats,
+				14 => Self::ForgetUser,
+				15 => Self::SeenQuery,
+				16 => Self::UserEntries,
+				17 => Self::DistinctQuery,
+				18 => Self::CountryQuery,
+				19 => Self::VerdictQuery,
+				20 => Self::VelocityQuery,
+				21 => Self::ListPrefixes,
+				22 => Self::SetLabel,
+				23 => Self::LabelledQuery,
+				24 => Self::ConfidenceQuery,
+				25 => Self::StructureStats,
+				26 => Self::AutomatedTrust,
+				27 => Self::AutomatedSpam,
+				28 => Self::SourceQuery,
+				29 => Self::HintedSpam,
+				30 => Self::HintedQuery,
+				31 => Self::WindowStats,
+				32 => Self::LatencyStats,
+				33 => Self::ConnectionStats,
+				34 => Self::Health,
+				35 => Self::MemoryStats,
+				36 => Self::Flush,
+				37 => Self::Version,
+				38 => Self::ListTunables,
+				39 => Self::SetTunable,
 				_ => return None,
 			}
 		)
 	}
+
+	/// Gets the type of report a request makes, if it’s a report.
+	fn operation_type(self) -> Option<OperationType> {
+		match self {
+			Self::Trust => Some(OperationType::Trust),
+			Self::Spam => Some(OperationType::Spam),
+			Self::Abuse => Some(OperationType::Abuse),
+			Self::Phishing => Some(OperationType::Phishing),
+			Self::Bruteforce => Some(OperationType::Bruteforce),
+			Self::AutomatedTrust => Some(OperationType::AutomatedTrust),
+			Self::AutomatedSpam => Some(OperationType::AutomatedSpam),
+			_ => None,
+		}
+	}
+}
+
+/// The version of the protocol, which changes when existing requests change in ways that break clients, but not when requests are added.
+pub const PROTOCOL_VERSION: u8 = 1;
+
+/// Set in a request’s type byte when its address is a 4-byte IPv4 address.
+const IPV4_FLAG: u8 = 0x80;
+
+/// Set in a report’s type byte to retract the report instead of making it.
+const RETRACT_FLAG: u8 = 0x40;
+
+/// The form an address was sent in.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum AddressForm {
+	Full,
+	Ipv4,
 }
 
 #[derive(Clone, Debug)]
 pub enum Request {
-	Query(Address),
-	Trust(Address, User),
-	Spam(Address, User),
+	Query(Address, AddressForm),
+	/// A query for the counts of every report category.
+	CategoryQuery(Address, AddressForm),
+	/// A query for the weights of every report category, which decay if configured to.
+	WeightedQuery(Address, AddressForm),
+	/// A query for the counts and the estimated probability that the address is spam.
+	ScoredQuery(Address, AddressForm),
+	/// A query for the counts and a lower confidence bound on the proportion of spam.
+	ConfidenceQuery(Address, AddressForm),
+	/// A query for the counts and when the prefix was first and most recently reported.
+	SeenQuery(Address, AddressForm),
+	/// A query for the counts and the number of distinct users behind them.
+	DistinctQuery(Address, AddressForm),
+	/// A query for the counts and the address’s country.
+	CountryQuery(Address, AddressForm),
+	/// A query for the counts and a verdict that tells addresses without data apart from ones with balanced data.
+	VerdictQuery(Address, AddressForm),
+	/// A query for the counts and the number of recent spam reports.
+	VelocityQuery(Address, AddressForm),
+	/// A query for the counts and the label of the longest labelled prefix.
+	LabelledQuery(Address, AddressForm),
+	/// A query for the counts of trust and spam reported by people and by automated sources.
+	SourceQuery(Address, AddressForm),
+	/// A query for the counts and the most common domain hints sent with spam reports within the prefix.
+	HintedQuery(Address, AddressForm),
+	Stats,
+	/// Gets the number of prefixes of each size and how they branch.
+	StructureStats,
+	/// Gets statistics about the windows entries are kept in for their times.
+	WindowStats,
+	/// Gets how long requests of each kind took to answer.
+	LatencyStats,
+	/// Gets counts of connections, how they ended, and the requests made by each local user.
+	ConnectionStats,
+	/// Checks that the event loop is keeping up and writes to the operation log are succeeding.
+	Health,
+	/// Gets estimates of the memory used by each part of the tree and by connections.
+	MemoryStats,
+	Report(OperationType, Address, User),
+	/// A spam report with a domain hint, like the HELO name, in lowercase.
+	HintedReport(Address, User, String),
+	Retract(Retraction),
+	Keepalive,
+	/// Shuts the daemon down, like SIGTERM.
+	Shutdown,
+	/// Pins a prefix to a verdict, or removes its override if the verdict is `None`.
+	SetOverride(AddressPrefix, Option<Verdict>),
+	ListOverrides,
+	/// Attaches a label to a prefix, or removes its label if the label is `None`.
+	SetLabel(AddressPrefix, Option<String>),
+	/// Lists every prefix with entries and its counts.
+	ListPrefixes,
+	/// Lists the reports still counting toward a user.
+	UserEntries(User),
+	/// Writes out the operation log’s buffer.
+	Flush,
+	/// Gets the daemon’s version, commit, protocol version, and features.
+	Version,
+	/// Lists the settings that can be changed while running and their values.
+	ListTunables,
+	/// Changes a setting while running, by the name of its command-line option, to a value in the option’s form.
+	SetTunable(String, String),
+}
+
+impl Request {
+	/// Replaces the address a request is about, if it’s about one. Prefixes, as in overrides and labels, are left alone.
+	pub fn map_address(self, f: impl FnOnce(Address) -> Address) -> Self {
+		match self {
+			Self::Query(address, form) => Self::Query(f(address), form),
+			Self::CategoryQuery(address, form) => Self::CategoryQuery(f(address), form),
+			Self::WeightedQuery(address, form) => Self::WeightedQuery(f(address), form),
+			Self::ScoredQuery(address, form) => Self::ScoredQuery(f(address), form),
+			Self::ConfidenceQuery(address, form) => Self::ConfidenceQuery(f(address), form),
+			Self::SeenQuery(address, form) => Self::SeenQuery(f(address), form),
+			Self::DistinctQuery(address, form) => Self::DistinctQuery(f(address), form),
+			Self::CountryQuery(address, form) => Self::CountryQuery(f(address), form),
+			Self::VerdictQuery(address, form) => Self::VerdictQuery(f(address), form),
+			Self::VelocityQuery(address, form) => Self::VelocityQuery(f(address), form),
+			Self::LabelledQuery(address, form) => Self::LabelledQuery(f(address), form),
+			Self::SourceQuery(address, form) => Self::SourceQuery(f(address), form),
+			Self::HintedQuery(address, form) => Self::HintedQuery(f(address), form),
+			Self::Report(type_, address, user) => Self::Report(type_, f(address), user),
+			Self::HintedReport(address, user, hint) => Self::HintedReport(f(address), user, hint),
+			Self::Retract(Retraction::Report(type_, address, user)) => Self::Retract(Retraction::Report(type_, f(address), user)),
+			_ => self,
+		}
+	}
+
+	/// Whether the request is only accepted on the admin socket when there is one: stats, overrides, labels, listing prefixes, forgetting users and listing their entries, tunables, shutting down, and flushing.
+	pub fn is_admin(&self) -> bool {
+		match self {
+			Self::Stats | Self::StructureStats | Self::WindowStats | Self::LatencyStats | Self::ConnectionStats | Self::MemoryStats
+			| Self::SetOverride(..) | Self::ListOverrides
+			| Self::SetLabel(..) | Self::ListPrefixes
+			| Self::Retract(Retraction::User(_)) | Self::UserEntries(_)
+			| Self::ListTunables | Self::SetTunable(..)
+			| Self::Shutdown | Self::Flush => true,
+			_ => false,
+		}
+	}
+
+	/// Whether the request is only accepted on the admin socket even when there isn’t one, so any client that can reach the main socket can’t stop the daemon.
+	pub fn is_admin_only(&self) -> bool {
+		match self {
+			Self::Shutdown | Self::Flush => true,
+			_ => false,
+		}
+	}
+
+	/// Gets the address a request is about, if it’s about one, as with `map_address`.
+	pub fn address(&self) -> Option<&Address> {
+		match self {
+			Self::Query(address, _)
+			| Self::CategoryQuery(address, _)
+			| Self::WeightedQuery(address, _)
+			| Self::ScoredQuery(address, _)
+			| Self::ConfidenceQuery(address, _)
+			| Self::SeenQuery(address, _)
+			| Self::DistinctQuery(address, _)
+			| Self::CountryQuery(address, _)
+			| Self::VerdictQuery(address, _)
+			| Self::VelocityQuery(address, _)
+			| Self::LabelledQuery(address, _)
+			| Self::SourceQuery(address, _)
+			| Self::HintedQuery(address, _)
+			| Self::Report(_, address, _)
+			| Self::HintedReport(address, _, _)
+			| Self::Retract(Retraction::Report(_, address, _)) => Some(address),
+			_ => None,
+		}
+	}
 }
 
 #[derive(Debug)]
@@ -37,6 +273,9 @@ pub enum ReadError {
 	End,
 	FormatError(Vec<u8>),
 	IoError(io::Error),
+	Timeout,
+	/// A request that’s only accepted on the admin socket.
+	Forbidden,
 }
 
 impl Error for ReadError {}
@@ -53,15 +292,49 @@ impl From<io::Error> for ReadError {
 	}
 }
 
-pub async fn read_request<T: AsyncRead + Unpin>(source: &mut BufReader<T>) -> Result<Request, ReadError> {
+/// Reads a request, along with when its first byte arrived.
+pub async fn read_request<T: AsyncRead + Unpin>(source: &mut BufReader<T>) -> Result<(Request, Instant), ReadError> {
 	let request_type_byte =
 		match source.read_u8().await {
 			Err(err) if err.kind() == ErrorKind::UnexpectedEof => return Err(ReadError::End),
 			other => other?,
 		};
 
+	let received = Instant::now();
+	let request = read_request_after(request_type_byte, source).await?;
+
+	Ok((request, received))
+}
+
+/// Reads a string of up to 255 bytes prefixed with its length, or `None` if it isn’t UTF-8.
+async fn read_short_string<T: AsyncRead + Unpin>(source: &mut BufReader<T>) -> io::Result<Option<String>> {
+	let length = source.read_u8().await?;
+	let mut bytes = vec![0; usize::from(length)];
+	source.read_exact(&mut bytes).await?;
+	Ok(String::from_utf8(bytes).ok())
+}
+
+/// Reads the rest of a request whose type byte has been read.
+async fn read_request_after<T: AsyncRead + Unpin>(request_type_byte: u8, source: &mut BufReader<T>) -> Result<Request, ReadError> {
+	let form =
+		if request_type_byte & IPV4_FLAG == 0 {
+			AddressForm::Full
+		} else {
+			AddressForm::Ipv4
+		};
+
+	let retract = request_type_byte & RETRACT_FLAG != 0;
+
 	let request_type =
-		match RequestType::from(request_type_byte) {
+		match RequestType::from(request_type_byte & !(IPV4_FLAG | RETRACT_FLAG)) {
+			// Only requests with addresses can have the IPv4 flag.
+			Some(RequestType::Keepalive) | Some(RequestType::Shutdown) | Some(RequestType::ListOverrides) | Some(RequestType::ListPrefixes) | Some(RequestType::Stats) | Some(RequestType::StructureStats) | Some(RequestType::WindowStats) | Some(RequestType::LatencyStats) | Some(RequestType::ConnectionStats) | Some(RequestType::Health) | Some(RequestType::MemoryStats) | Some(RequestType::Flush) | Some(RequestType::Version) | Some(RequestType::ListTunables) | Some(RequestType::SetTunable) | Some(RequestType::ForgetUser) | Some(RequestType::UserEntries) if form == AddressForm::Ipv4 => {
+				return Err(ReadError::FormatError(vec![request_type_byte]));
+			}
+			// Only reports can be retracted.
+			Some(t) if retract && t.operation_type().is_none() => {
+				return Err(ReadError::FormatError(vec![request_type_byte]));
+			}
 			Some(t) => t,
 			None => {
 				let mut context = [0; 1 + ADDRESS_BYTES + USER_BYTES];
@@ -71,9 +344,115 @@ pub async fn read_request<T: AsyncRead + Unpin>(source: &mut BufReader<T>) -> Re
 			},
 		};
 
-	let mut address = [0; ADDRESS_BYTES];
-	source.read_exact(&mut address).await?;
-	let address = Address(address);
+	match request_type {
+		RequestType::Keepalive => return Ok(Request::Keepalive),
+		RequestType::Shutdown => return Ok(Request::Shutdown),
+		RequestType::ListOverrides => return Ok(Request::ListOverrides),
+		RequestType::ListPrefixes => return Ok(Request::ListPrefixes),
+		RequestType::Stats => return Ok(Request::Stats),
+		RequestType::StructureStats => return Ok(Request::StructureStats),
+		RequestType::WindowStats => return Ok(Request::WindowStats),
+		RequestType::LatencyStats => return Ok(Request::LatencyStats),
+		RequestType::ConnectionStats => return Ok(Request::ConnectionStats),
+		RequestType::Health => return Ok(Request::Health),
+		RequestType::MemoryStats => return Ok(Request::MemoryStats),
+		RequestType::Flush => return Ok(Request::Flush),
+		RequestType::Version => return Ok(Request::Version),
+		RequestType::ListTunables => return Ok(Request::ListTunables),
+		RequestType::SetTunable => {
+			let name = read_short_string(source).await?;
+			let value = read_short_string(source).await?;
+
+			return match (name, value) {
+				(Some(name), Some(value)) => Ok(Request::SetTunable(name, value)),
+				_ => Err(ReadError::FormatError(vec![request_type_byte])),
+			};
+		}
+		RequestType::ForgetUser => {
+			let mut user = [0; USER_BYTES];
+			source.read_exact(&mut user).await?;
+			return Ok(Request::Retract(Retraction::User(User::from_bytes(user))));
+		}
+		RequestType::UserEntries => {
+			let mut user = [0; USER_BYTES];
+			source.read_exact(&mut user).await?;
+			return Ok(Request::UserEntries(User::from_bytes(user)));
+		}
+		_ => {},
+	}
+
+	let address =
+		match form {
+			AddressForm::Full => {
+				let mut address = [0; ADDRESS_BYTES];
+				source.read_exact(&mut address).await?;
+				Address(address)
+			}
+			AddressForm::Ipv4 => {
+				let mut address = [0; IPV4_BYTES];
+				source.read_exact(&mut address).await?;
+				Address::from_ipv4(address)
+			}
+		};
+
+	if request_type == RequestType::SetOverride {
+		let bits = source.read_u8().await?;
+		let verdict_code = source.read_u8().await?;
+
+		// Prefix sizes for IPv4 addresses are relative to the IPv4 address.
+		let prefix_bits =
+			match form {
+				AddressForm::Full if bits <= ADDRESS_BITS => bits,
+				AddressForm::Ipv4 if usize::from(bits) <= 8 * IPV4_BYTES => IPV4_OFFSET_BITS + bits,
+				_ => return Err(ReadError::FormatError(vec![request_type_byte, bits, verdict_code])),
+			};
+
+		let verdict =
+			match verdict_code {
+				0 => None,
+				code => Some(Verdict::from_code(code).ok_or_else(|| ReadError::FormatError(vec![request_type_byte, bits, verdict_code]))?),
+			};
+
+		return Ok(Request::SetOverride(address.prefix(prefix_bits), verdict));
+	}
+
+	if request_type == RequestType::SetLabel {
+		let bits = source.read_u8().await?;
+		let length = source.read_u8().await?;
+		let mut label = vec![0; usize::from(length)];
+		source.read_exact(&mut label).await?;
+
+		let prefix_bits =
+			match form {
+				AddressForm::Full if bits <= ADDRESS_BITS => bits,
+				AddressForm::Ipv4 if usize::from(bits) <= 8 * IPV4_BYTES => IPV4_OFFSET_BITS + bits,
+				_ => return Err(ReadError::FormatError(vec![request_type_byte, bits, length])),
+			};
+
+		let label =
+			match length {
+				0 => None,
+				_ => match String::from_utf8(label) {
+					Ok(label) if is_valid_label(&label) => Some(label),
+					_ => return Err(ReadError::FormatError(vec![request_type_byte, bits, length])),
+				},
+			};
+
+		return Ok(Request::SetLabel(address.prefix(prefix_bits), label));
+	}
+
+	if request_type == RequestType::HintedSpam {
+		let mut user = [0; USER_BYTES];
+		source.read_exact(&mut user).await?;
+		let length = source.read_u8().await?;
+		let mut hint = vec![0; usize::from(length)];
+		source.read_exact(&mut hint).await?;
+
+		return match String::from_utf8(hint) {
+			Ok(hint) if is_valid_hint(&hint) => Ok(Request::HintedReport(address, User::from_bytes(user), hint.to_ascii_lowercase())),
+			_ => Err(ReadError::FormatError(vec![request_type_byte, length])),
+		};
+	}
 
 	let get_user = async move || -> io::Result<User> {
 		let mut user = [0; USER_BYTES];
@@ -81,11 +460,35 @@ pub async fn read_request<T: AsyncRead + Unpin>(source: &mut BufReader<T>) -> Re
 		Ok(User::from_bytes(user))
 	};
 
+	if let Some(type_) = request_type.operation_type() {
+		let user = get_user().await?;
+
+		return Ok(
+			if retract {
+				Request::Retract(Retraction::Report(type_, address, user))
+			} else {
+				Request::Report(type_, address, user)
+			}
+		);
+	}
+
 	Ok(
 		match request_type {
-			RequestType::Query => Request::Query(address),
-			RequestType::Trust => Request::Trust(address, get_user().await?),
-			RequestType::Spam => Request::Spam(address, get_user().await?),
+			RequestType::Query => Request::Query(address, form),
+			RequestType::CategoryQuery => Request::CategoryQuery(address, form),
+			RequestType::WeightedQuery => Request::WeightedQuery(address, form),
+			RequestType::ScoredQuery => Request::ScoredQuery(address, form),
+			RequestType::ConfidenceQuery => Request::ConfidenceQuery(address, form),
+			RequestType::SeenQuery => Request::SeenQuery(address, form),
+			RequestType::DistinctQuery => Request::DistinctQuery(address, form),
+			RequestType::CountryQuery => Request::CountryQuery(address, form),
+			RequestType::VerdictQuery => Request::VerdictQuery(address, form),
+			RequestType::VelocityQuery => Request::VelocityQuery(address, form),
+			RequestType::LabelledQuery => Request::LabelledQuery(address, form),
+			RequestType::SourceQuery => Request::SourceQuery(address, form),
+			RequestType::HintedQuery => Request::HintedQuery(address, form),
+			RequestType::Trust | RequestType::Spam | RequestType::Abuse | RequestType::Phishing | RequestType::Bruteforce | RequestType::AutomatedTrust | RequestType::AutomatedSpam => unreachable!(),
+			RequestType::Keepalive | RequestType::Shutdown | RequestType::SetOverride | RequestType::SetLabel | RequestType::HintedSpam | RequestType::ListOverrides | RequestType::ListPrefixes | RequestType::Stats | RequestType::StructureStats | RequestType::WindowStats | RequestType::LatencyStats | RequestType::ConnectionStats | RequestType::Health | RequestType::MemoryStats | RequestType::Flush | RequestType::Version | RequestType::ListTunables | RequestType::SetTunable | RequestType::ForgetUser | RequestType::UserEntries => unreachable!(),
 		}
 	)
 }
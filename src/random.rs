@@ -0,0 +1,68 @@
+use super::address::{ADDRESS_BYTES, Address, IPV4_BYTES};
+
+/// The number of IPv4 /24s and IPv6 /48s generated addresses are in.
+const NETWORKS: u64 = 256;
+
+/// The number of /64s used within each /48.
+const SUBNETS_PER_SITE: u64 = 16;
+
+/// A source of random numbers for making up addresses, which the benchmark and tests get from different places.
+pub trait RandomSource {
+	fn next_u64(&mut self) -> u64;
+
+	/// Gets a number less than `n`, which is slightly biased unless `n` is a power of two.
+	fn below(&mut self, n: u64) -> u64 {
+		self.next_u64() % n
+	}
+
+	fn fill(&mut self, bytes: &mut [u8]) {
+		for chunk in bytes.chunks_mut(8) {
+			chunk.copy_from_slice(&self.next_u64().to_be_bytes()[..chunk.len()]);
+		}
+	}
+}
+
+/// A xorshift generator, which is plenty for making up addresses and doesn’t need a dependency.
+pub struct XorShift(pub u64);
+
+impl RandomSource for XorShift {
+	fn next_u64(&mut self) -> u64 {
+		let mut x = self.0;
+		x ^= x << 13;
+		x ^= x >> 7;
+		x ^= x << 17;
+		self.0 = x;
+		x
+	}
+}
+
+#[cfg(test)]
+impl<R: rand::RngCore> RandomSource for R {
+	fn next_u64(&mut self) -> u64 {
+		rand::RngCore::next_u64(self)
+	}
+}
+
+/// Spreads the number of a network over all of its bits, so networks don’t all share a prefix.
+fn scatter(network: u64) -> [u8; 8] {
+	(network + 1).wrapping_mul(0x9e37_79b9_7f4a_7c15).to_be_bytes()
+}
+
+/// Makes an address clustered the way reported addresses are in practice: a quarter are IPv4 addresses in one of a few hundred /24s, and the rest are IPv6 addresses in a few /64s of one of a few hundred /48s.
+pub fn address<R: RandomSource + ?Sized>(random: &mut R) -> Address {
+	let network = scatter(random.below(NETWORKS));
+
+	if random.below(4) == 0 {
+		let mut ipv4 = [0; IPV4_BYTES];
+		ipv4[..3].copy_from_slice(&network[..3]);
+		ipv4[3] = random.next_u64() as u8;
+		return Address::from_ipv4(ipv4);
+	}
+
+	let mut bytes = [0; ADDRESS_BYTES];
+	bytes[..2].copy_from_slice(&[0x20, 0x01]);
+	bytes[2..6].copy_from_slice(&network[..4]);
+	bytes[6..8].copy_from_slice(&(random.below(SUBNETS_PER_SITE) as u16).to_be_bytes());
+	random.fill(&mut bytes[8..]);
+	Address(bytes)
+}
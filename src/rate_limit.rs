@@ -0,0 +1,54 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+/// The tokens a client has left for making requests, which can go negative while it waits for the ones it already took.
+#[derive(Clone, Copy, Debug)]
+pub struct TokenBucket {
+	tokens: f64,
+	updated: Instant,
+}
+
+/// Limits how fast clients can make requests with a token bucket for each user id, so one service retrying in a loop can’t keep the event loop busy for everyone else. Clients whose user id isn’t known get a bucket for their connection instead.
+#[derive(Debug)]
+pub struct RateLimits {
+	/// The number of tokens added to each bucket per second.
+	rate: f64,
+	/// The most tokens a bucket can hold, which is the number of requests a client can make at once after being idle.
+	burst: f64,
+	/// A bucket for every user id that made requests since startup, which are few on one machine.
+	buckets: HashMap<u32, TokenBucket>,
+}
+
+impl RateLimits {
+	pub fn new(rate: u32, burst: u32) -> Self {
+		Self {
+			rate: f64::from(rate),
+			burst: f64::from(burst),
+			buckets: HashMap::new(),
+		}
+	}
+
+	/// Takes a token for a request from the bucket for user id `uid`, or `connection_bucket` if the user id isn’t known, returning how long the client has to wait before the request is answered if the bucket was empty.
+	pub fn take(&mut self, uid: Option<u32>, connection_bucket: &mut Option<TokenBucket>, now: Instant) -> Option<Duration> {
+		let full = TokenBucket {
+			tokens: self.burst,
+			updated: now,
+		};
+
+		let bucket =
+			match uid {
+				Some(uid) => self.buckets.entry(uid).or_insert(full),
+				None => connection_bucket.get_or_insert(full),
+			};
+
+		let refilled = now.saturating_duration_since(bucket.updated).as_secs_f64() * self.rate;
+		bucket.tokens = (bucket.tokens + refilled).min(self.burst) - 1.0;
+		bucket.updated = now;
+
+		if bucket.tokens >= 0.0 {
+			None
+		} else {
+			Some(Duration::from_secs_f64(-bucket.tokens / self.rate))
+		}
+	}
+}
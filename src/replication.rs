@@ -0,0 +1,183 @@
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::future::Future;
+use std::io::{self, ErrorKind};
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::rc::Rc;
+use tokio::io::{AsyncReadExt, AsyncWriteExt, BufReader, BufWriter};
+use tokio::net::TcpStream;
+use tokio::sync::watch;
+
+use super::persist::{OPERATION_BYTES, SerializedTreeOperation, read_operations};
+
+/// The number of recent operations kept for followers that fall a little behind. It’s well over what the operation log buffers, so older ones can be read from the log.
+const REPLICATION_BACKLOG: usize = 65536;
+
+/// The most operations to read from the operation log at once for a follower that’s further behind.
+const CATCH_UP_BATCH: usize = 4096;
+
+/// Sent to a follower before its operations when the primary has all of them.
+const STREAM_OK: u8 = 0;
+
+/// Sent to a follower that asks for operations that aren’t in the operation log, before disconnecting it.
+const STREAM_TOO_OLD: u8 = 1;
+
+/// Sent to a follower that asks for operations newer than the primary has, before disconnecting it.
+const STREAM_AHEAD: u8 = 2;
+
+/// The operations applied most recently, numbered in the order they were logged, for streaming to followers.
+pub struct ReplicationLog {
+	/// The operation log, where sequence numbers are indexes, for operations older than the ones kept.
+	log_path: PathBuf,
+	/// The sequence number of the oldest operation kept.
+	first: u64,
+	recent: VecDeque<SerializedTreeOperation>,
+	/// Notified of the sequence number of the next operation whenever one is added.
+	next_sender: watch::Sender<u64>,
+	next_receiver: watch::Receiver<u64>,
+}
+
+impl ReplicationLog {
+	/// Starts numbering operations at `next`, the number of operations in the operation log at `log_path`, so a follower with a copy of the log can ask for the ones after it.
+	pub fn new(log_path: PathBuf, next: u64) -> Self {
+		let (next_sender, next_receiver) = watch::channel(next);
+
+		Self {
+			log_path,
+			first: next,
+			recent: VecDeque::new(),
+			next_sender,
+			next_receiver,
+		}
+	}
+
+	/// Gets the sequence number the next operation will have.
+	pub fn next(&self) -> u64 {
+		self.first + self.recent.len() as u64
+	}
+
+	pub fn push(&mut self, operation: &SerializedTreeOperation) {
+		if self.recent.len() == REPLICATION_BACKLOG {
+			self.recent.pop_front();
+			self.first += 1;
+		}
+
+		self.recent.push_back(operation.clone());
+		let _ = self.next_sender.broadcast(self.next());
+	}
+
+	/// Gets the operations from sequence number `from` on, or `None` if there isn’t one with that number yet. Ones that aren’t kept anymore are read from the operation log a batch at a time, so a follower with no operations at all gets all of them.
+	fn since(&self, from: u64) -> io::Result<Option<Vec<SerializedTreeOperation>>> {
+		if from > self.next() {
+			return Ok(None);
+		}
+
+		if from >= self.first {
+			return Ok(Some(self.recent.iter().skip((from - self.first) as usize).cloned().collect()));
+		}
+
+		let count = CATCH_UP_BATCH.min((self.first - from) as usize);
+		let logged = read_operations(&self.log_path, from, count)?;
+
+		if logged.is_empty() {
+			return Err(io::Error::new(ErrorKind::UnexpectedEof, "the operation log doesn’t have the operations the follower needs"));
+		}
+
+		Ok(Some(logged))
+	}
+}
+
+/// Streams operations to a follower, which starts by sending the sequence number of the first operation it needs as 8 bytes. The response is a status byte, then [*sequence*×8, *operation*] for each operation, from the operation log until the follower catches up and then as they’re applied, until the follower disconnects or `shutdown` completes.
+pub async fn stream_to_follower(log: Rc<RefCell<ReplicationLog>>, mut stream: TcpStream, shutdown: impl Future<Output = ()>) -> io::Result<()> {
+	let (mut read, write) = stream.split();
+	let mut write = BufWriter::new(write);
+	let mut next = read.read_u64().await?;
+	let mut updates = log.borrow().next_receiver.clone();
+	let mut first = true;
+	tokio::pin!(shutdown);
+
+	loop {
+		let since = log.borrow().since(next);
+
+		let operations =
+			match since {
+				Ok(Some(operations)) => operations,
+				Ok(None) => {
+					if first {
+						write.write_u8(STREAM_AHEAD).await?;
+						write.flush().await?;
+					}
+
+					return Err(io::Error::new(ErrorKind::Other, "the follower asked for operations the primary doesn’t have"));
+				}
+				Err(err) => {
+					if first {
+						write.write_u8(STREAM_TOO_OLD).await?;
+						write.flush().await?;
+					}
+
+					return Err(err);
+				}
+			};
+
+		if first {
+			write.write_u8(STREAM_OK).await?;
+			first = false;
+		}
+
+		for operation in &operations {
+			write.write_u64(next).await?;
+			write.write_all(&operation.0).await?;
+			next += 1;
+		}
+
+		write.flush().await?;
+
+		// A follower catching up from the log gets the next batch right away.
+		if next == log.borrow().next() {
+			tokio::select! {
+				_ = updates.recv() => {},
+				_ = &mut shutdown => return Ok(()),
+			}
+		}
+	}
+}
+
+/// A follower’s connection to a primary, which is sent operations as the primary applies them.
+pub struct Subscription {
+	stream: BufReader<TcpStream>,
+	/// The sequence number of the next operation.
+	next: u64,
+}
+
+impl Subscription {
+	/// Connects to a primary’s replication listener and asks for the operations from sequence number `next` on.
+	pub async fn connect(primary: SocketAddr, next: u64) -> io::Result<Self> {
+		let mut stream = TcpStream::connect(primary).await?;
+		stream.write_u64(next).await?;
+
+		let mut stream = BufReader::new(stream);
+
+		match stream.read_u8().await? {
+			STREAM_OK => Ok(Self { stream, next }),
+			STREAM_TOO_OLD => Err(io::Error::new(ErrorKind::Other, "the primary’s operation log doesn’t have the operations this replica needs")),
+			_ => Err(io::Error::new(ErrorKind::Other, "the primary doesn’t have the operations this replica needs, so this replica’s log isn’t from it")),
+		}
+	}
+
+	/// Waits for the primary’s next operation.
+	pub async fn next(&mut self) -> io::Result<SerializedTreeOperation> {
+		let sequence = self.stream.read_u64().await?;
+
+		if sequence != self.next {
+			return Err(io::Error::new(ErrorKind::InvalidData, "the primary skipped operations"));
+		}
+
+		let mut operation = [0; OPERATION_BYTES];
+		self.stream.read_exact(&mut operation).await?;
+		self.next += 1;
+
+		Ok(SerializedTreeOperation(operation))
+	}
+}
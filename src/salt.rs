@@ -0,0 +1,88 @@
+use std::convert::TryInto;
+use std::fs;
+use std::io::{self, ErrorKind};
+use std::path::Path;
+
+use super::tree::User;
+
+/// The shortest salt accepted, in bytes.
+const MINIMUM_SALT_BYTES: usize = 16;
+
+/// SipHash-2-4 of a message with a 128-bit key, as specified by Aumasson and Bernstein. Implemented here because the standard library’s hashers aren’t guaranteed to stay the same between versions, and persisted users have to.
+fn siphash(k0: u64, k1: u64, message: &[u8]) -> u64 {
+	let mut v = [
+		k0 ^ 0x736f_6d65_7073_6575,
+		k1 ^ 0x646f_7261_6e64_6f6d,
+		k0 ^ 0x6c79_6765_6e65_7261,
+		k1 ^ 0x7465_6462_7974_6573,
+	];
+
+	fn round(v: &mut [u64; 4]) {
+		v[0] = v[0].wrapping_add(v[1]);
+		v[1] = v[1].rotate_left(13) ^ v[0];
+		v[0] = v[0].rotate_left(32);
+		v[2] = v[2].wrapping_add(v[3]);
+		v[3] = v[3].rotate_left(16) ^ v[2];
+		v[0] = v[0].wrapping_add(v[3]);
+		v[3] = v[3].rotate_left(21) ^ v[0];
+		v[2] = v[2].wrapping_add(v[1]);
+		v[1] = v[1].rotate_left(17) ^ v[2];
+		v[2] = v[2].rotate_left(32);
+	}
+
+	let mut compress = |m: u64| {
+		v[3] ^= m;
+		round(&mut v);
+		round(&mut v);
+		v[0] ^= m;
+	};
+
+	let chunks = message.chunks_exact(8);
+	let remainder = chunks.remainder();
+
+	for chunk in chunks {
+		compress(u64::from_le_bytes(chunk.try_into().unwrap()));
+	}
+
+	let mut last = [0; 8];
+	last[..remainder.len()].copy_from_slice(remainder);
+	last[7] = message.len() as u8;
+	compress(u64::from_le_bytes(last));
+
+	v[2] ^= 0xff;
+
+	for _ in 0..4 {
+		round(&mut v);
+	}
+
+	v[0] ^ v[1] ^ v[2] ^ v[3]
+}
+
+/// A secret that users are hashed with before they’re stored, so that the operation log doesn’t contain the numbers clients use for them.
+#[derive(Clone, Debug)]
+pub struct UserSalt {
+	k0: u64,
+	k1: u64,
+}
+
+impl UserSalt {
+	/// Reads a salt from a file of at least 16 bytes, whose whole contents are hashed into a key.
+	pub fn read(path: &Path) -> io::Result<Self> {
+		let contents = fs::read(path)?;
+
+		if contents.len() < MINIMUM_SALT_BYTES {
+			return Err(io::Error::new(ErrorKind::InvalidData, format!("the salt must be at least {} bytes", MINIMUM_SALT_BYTES)));
+		}
+
+		Ok(Self {
+			k0: siphash(0, 0, &contents),
+			k1: siphash(0, 1, &contents),
+		})
+	}
+
+	/// Hashes a user into another, keeping the low bits of the hash. Different users can collide and share a limit, but rarely.
+	pub fn hash(&self, user: User) -> User {
+		let hash = siphash(self.k0, self.k1, &user.to_bytes());
+		User::from_bytes((hash as u32).to_be_bytes())
+	}
+}
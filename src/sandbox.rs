@@ -0,0 +1,103 @@
+use landlock::{ABI, Access, AccessFs, PathBeneath, PathFd, Ruleset, RulesetAttr, RulesetCreatedAttr, RulesetStatus};
+use seccompiler::{BpfProgram, SeccompAction, SeccompFilter};
+use std::convert::TryInto;
+use std::env;
+use std::error::Error;
+use std::path::Path;
+use tracing::warn;
+
+use super::daemon::RemovableFile;
+
+/// The system calls made while serving: I/O on descriptors that are already open or accepted, the event loop, memory management, signals, time, and creating, replacing, and removing files in the allowed directories.
+const ALLOWED_SYSCALLS: &[libc::c_long] = &[
+	libc::SYS_accept4,
+	libc::SYS_brk,
+	libc::SYS_clock_gettime,
+	libc::SYS_clock_nanosleep,
+	libc::SYS_close,
+	libc::SYS_epoll_ctl,
+	libc::SYS_epoll_pwait,
+	libc::SYS_exit,
+	libc::SYS_exit_group,
+	libc::SYS_fcntl,
+	libc::SYS_fdatasync,
+	libc::SYS_fstat,
+	libc::SYS_fsync,
+	libc::SYS_ftruncate,
+	libc::SYS_futex,
+	libc::SYS_getpid,
+	libc::SYS_getrandom,
+	libc::SYS_getsockopt,
+	libc::SYS_gettid,
+	libc::SYS_ioctl,
+	libc::SYS_lseek,
+	libc::SYS_madvise,
+	libc::SYS_mmap,
+	libc::SYS_mprotect,
+	libc::SYS_mremap,
+	libc::SYS_munmap,
+	libc::SYS_nanosleep,
+	libc::SYS_newfstatat,
+	libc::SYS_openat,
+	libc::SYS_pread64,
+	libc::SYS_pwrite64,
+	libc::SYS_read,
+	libc::SYS_readv,
+	libc::SYS_recvfrom,
+	libc::SYS_recvmsg,
+	libc::SYS_renameat2,
+	libc::SYS_restart_syscall,
+	libc::SYS_rt_sigaction,
+	libc::SYS_rt_sigprocmask,
+	libc::SYS_rt_sigreturn,
+	libc::SYS_sched_yield,
+	libc::SYS_sendmsg,
+	libc::SYS_sendto,
+	libc::SYS_shutdown,
+	libc::SYS_sigaltstack,
+	libc::SYS_statx,
+	libc::SYS_tgkill,
+	libc::SYS_unlinkat,
+	libc::SYS_write,
+	libc::SYS_writev,
+
+	// Older variants that only some architectures have, and that the standard library and mio still use there.
+	#[cfg(target_arch = "x86_64")] libc::SYS_accept,
+	#[cfg(target_arch = "x86_64")] libc::SYS_epoll_wait,
+	#[cfg(target_arch = "x86_64")] libc::SYS_open,
+	#[cfg(target_arch = "x86_64")] libc::SYS_rename,
+	#[cfg(target_arch = "x86_64")] libc::SYS_stat,
+	#[cfg(target_arch = "x86_64")] libc::SYS_unlink,
+
+	#[cfg(feature = "io-uring")] libc::SYS_io_uring_enter,
+];
+
+/// Restricts the process to the persistence directory (which it can do anything with) and the directories containing files it has to remove on exit (which it can only remove files from), then restricts it to `ALLOWED_SYSCALLS`. Other system calls fail with `EPERM`. Filesystem restrictions require Linux 5.13, and are skipped with a warning on older kernels.
+pub fn restrict(persist_path: &Path, removable: &[&RemovableFile]) -> Result<(), Box<dyn Error>> {
+	let abi = ABI::V1;
+
+	let mut ruleset =
+		Ruleset::default()
+			.handle_access(AccessFs::from_all(abi))?
+			.create()?
+			.add_rule(PathBeneath::new(PathFd::new(persist_path)?, AccessFs::from_all(abi)))?;
+
+	for file in removable {
+		ruleset = ruleset.add_rule(PathBeneath::new(file.directory(), AccessFs::RemoveFile))?;
+	}
+
+	if ruleset.restrict_self()?.ruleset != RulesetStatus::FullyEnforced {
+		warn!("the kernel doesn’t fully support Landlock, so filesystem access isn’t restricted");
+	}
+
+	let filter = SeccompFilter::new(
+		ALLOWED_SYSCALLS.iter().map(|&number| (number.into(), Vec::new())).collect(),
+		SeccompAction::Errno(libc::EPERM as u32),
+		SeccompAction::Allow,
+		env::consts::ARCH.try_into()?,
+	)?;
+	let program: BpfProgram = filter.try_into()?;
+	seccompiler::apply_filter(&program)?;
+
+	Ok(())
+}
@@ -0,0 +1,143 @@
+use std::cell::{Cell, RefCell};
+use std::io::{self, ErrorKind};
+use std::mem;
+use std::net::{Ipv4Addr, Ipv6Addr, ToSocketAddrs, UdpSocket};
+use std::time::Duration;
+
+use super::latency::{Latencies, RequestKind};
+use super::protocol::Request;
+
+/// The most bytes to send in one packet, so packets aren’t fragmented on Ethernet.
+const MAX_PACKET_BYTES: usize = 1432;
+
+/// A value to send to a StatsD agent.
+pub enum Metric<'a> {
+	/// The number of times something happened since the last time it was sent.
+	Count(&'a str, u64),
+	Gauge(&'a str, u64),
+	Time(&'a str, Duration),
+}
+
+/// Counts of requests and connections, and the latencies of the requests, since they were last sent.
+#[derive(Debug, Default)]
+pub struct Counters {
+	connections: Cell<u64>,
+	queries: Cell<u64>,
+	reports: Cell<u64>,
+	retractions: Cell<u64>,
+	other_requests: Cell<u64>,
+	/// Requests that had to wait because of the rate limit.
+	throttled: Cell<u64>,
+	latencies: RefCell<Latencies>,
+}
+
+impl Counters {
+	fn increment(counter: &Cell<u64>) {
+		counter.set(counter.get() + 1);
+	}
+
+	pub fn count_connection(&self) {
+		Self::increment(&self.connections);
+	}
+
+	/// Counts a request by what it does.
+	pub fn count_request(&self, request: &Request) {
+		Self::increment(
+			match RequestKind::of(request) {
+				RequestKind::Query => &self.queries,
+				RequestKind::Report => &self.reports,
+				RequestKind::Retract => &self.retractions,
+				_ => &self.other_requests,
+			}
+		);
+	}
+
+	pub fn count_throttled(&self) {
+		Self::increment(&self.throttled);
+	}
+
+	pub fn record_latency(&self, kind: RequestKind, duration: Duration) {
+		self.latencies.borrow_mut().record(kind, duration);
+	}
+
+	/// Gets the counts as metrics and starts them over.
+	pub fn take(&self) -> [Metric<'static>; 6] {
+		[
+			Metric::Count("connections", self.connections.replace(0)),
+			Metric::Count("requests.query", self.queries.replace(0)),
+			Metric::Count("requests.report", self.reports.replace(0)),
+			Metric::Count("requests.retract", self.retractions.replace(0)),
+			Metric::Count("requests.other", self.other_requests.replace(0)),
+			Metric::Count("requests.throttled", self.throttled.replace(0)),
+		]
+	}
+
+	/// Gets the latencies of requests and starts them over.
+	pub fn take_latencies(&self) -> Latencies {
+		mem::take(&mut *self.latencies.borrow_mut())
+	}
+}
+
+/// A connection to a StatsD agent, like Datadog’s, over UDP.
+#[derive(Debug)]
+pub struct Statsd {
+	socket: UdpSocket,
+	/// Prepended to every metric’s name, with a dot.
+	prefix: String,
+}
+
+impl Statsd {
+	/// Connects to an agent at a `host:port` address. The socket has to be opened before the sandbox is applied.
+	pub fn connect(address: &str, prefix: &str) -> io::Result<Self> {
+		let address =
+			address.to_socket_addrs()?
+				.next()
+				.ok_or_else(|| io::Error::new(ErrorKind::NotFound, "no addresses found"))?;
+
+		let socket =
+			if address.is_ipv4() {
+				UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0))?
+			} else {
+				UdpSocket::bind((Ipv6Addr::UNSPECIFIED, 0))?
+			};
+
+		socket.connect(address)?;
+
+		// A full send buffer drops metrics instead of stalling clients.
+		socket.set_nonblocking(true)?;
+
+		Ok(Self {
+			socket,
+			prefix: prefix.to_owned(),
+		})
+	}
+
+	/// Sends metrics in as few packets as possible. Metrics are best-effort, so failures, like the agent not running, are ignored.
+	pub fn send(&self, metrics: &[Metric]) {
+		let mut packet = String::new();
+
+		for metric in metrics {
+			let line =
+				match metric {
+					Metric::Count(name, value) => format!("{}.{}:{}|c", self.prefix, name, value),
+					Metric::Gauge(name, value) => format!("{}.{}:{}|g", self.prefix, name, value),
+					Metric::Time(name, duration) => format!("{}.{}:{}|ms", self.prefix, name, duration.as_millis()),
+				};
+
+			if !packet.is_empty() && packet.len() + 1 + line.len() > MAX_PACKET_BYTES {
+				let _ = self.socket.send(packet.as_bytes());
+				packet.clear();
+			}
+
+			if !packet.is_empty() {
+				packet.push('\n');
+			}
+
+			packet.push_str(&line);
+		}
+
+		if !packet.is_empty() {
+			let _ = self.socket.send(packet.as_bytes());
+		}
+	}
+}
@@ -0,0 +1,64 @@
+use mio::{Evented, Poll, PollOpt, Ready, Token};
+use mio::unix::EventedFd;
+use std::io::{self, Read, Write};
+use std::os::unix::io::RawFd;
+use tokio::io::PollEvented;
+
+/// One of the standard file descriptors, switched to non-blocking mode so it can be driven by the event loop instead of a blocking thread that would keep the runtime from shutting down. It isn’t closed when dropped.
+pub struct StdioFd(RawFd);
+
+impl StdioFd {
+	fn new(fd: RawFd) -> io::Result<Self> {
+		let flags = unsafe { libc::fcntl(fd, libc::F_GETFL) };
+
+		if flags == -1 || unsafe { libc::fcntl(fd, libc::F_SETFL, flags | libc::O_NONBLOCK) } == -1 {
+			return Err(io::Error::last_os_error());
+		}
+
+		Ok(Self(fd))
+	}
+}
+
+impl Evented for StdioFd {
+	fn register(&self, poll: &Poll, token: Token, interest: Ready, opts: PollOpt) -> io::Result<()> {
+		EventedFd(&self.0).register(poll, token, interest, opts)
+	}
+
+	fn reregister(&self, poll: &Poll, token: Token, interest: Ready, opts: PollOpt) -> io::Result<()> {
+		EventedFd(&self.0).reregister(poll, token, interest, opts)
+	}
+
+	fn deregister(&self, poll: &Poll) -> io::Result<()> {
+		EventedFd(&self.0).deregister(poll)
+	}
+}
+
+impl Read for StdioFd {
+	fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+		match unsafe { libc::read(self.0, buffer.as_mut_ptr() as *mut libc::c_void, buffer.len()) } {
+			-1 => Err(io::Error::last_os_error()),
+			count => Ok(count as usize),
+		}
+	}
+}
+
+impl Write for StdioFd {
+	fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+		match unsafe { libc::write(self.0, data.as_ptr() as *const libc::c_void, data.len()) } {
+			-1 => Err(io::Error::last_os_error()),
+			count => Ok(count as usize),
+		}
+	}
+
+	fn flush(&mut self) -> io::Result<()> {
+		Ok(())
+	}
+}
+
+/// Registers stdin and stdout with the runtime. They have to be something epoll supports, like sockets (from inetd) or pipes (from a supervisor), and not regular files.
+pub fn stdin_stdout() -> io::Result<(PollEvented<StdioFd>, PollEvented<StdioFd>)> {
+	Ok((
+		PollEvented::new(StdioFd::new(libc::STDIN_FILENO)?)?,
+		PollEvented::new(StdioFd::new(libc::STDOUT_FILENO)?)?,
+	))
+}
@@ -0,0 +1,193 @@
+use std::fmt::{self, Write};
+use std::io;
+use std::os::unix::net::UnixDatagram;
+use std::process;
+use tracing::field::{Field, Visit};
+use tracing::span::{Attributes, Id};
+use tracing::{Event, Level, Subscriber};
+use tracing_subscriber::layer::{Context, Layer};
+use tracing_subscriber::registry::LookupSpan;
+
+/// The name messages are logged under.
+const IDENTIFIER: &str = "iptooled";
+
+/// The syslog facility for system daemons.
+const FACILITY_DAEMON: u8 = 3;
+
+/// The socket syslog daemons, and journald in their place, receive messages on.
+const SYSLOG_PATH: &str = "/dev/log";
+
+/// The socket journald receives messages in its native format on.
+const JOURNALD_PATH: &str = "/run/systemd/journal/socket";
+
+/// How messages are encoded for the daemon receiving them.
+#[derive(Clone, Copy, Debug)]
+enum Protocol {
+	/// RFC 3164 lines, which the syslog daemon timestamps.
+	Syslog,
+	/// Journald’s native fields, which keep the fields of messages and their spans separate, so they can be filtered on.
+	Journald,
+}
+
+/// Sends log messages to the system log over a Unix datagram socket, which is connected when the layer is made, so it works after a chroot or the sandbox.
+#[derive(Debug)]
+pub struct SystemLog {
+	socket: UnixDatagram,
+	protocol: Protocol,
+}
+
+/// The fields of a span, kept in its extensions until it closes.
+struct SpanFields(Vec<(&'static str, String)>);
+
+/// Collects a message and its other fields.
+#[derive(Default)]
+struct Fields {
+	message: String,
+	fields: Vec<(&'static str, String)>,
+}
+
+impl Visit for Fields {
+	fn record_str(&mut self, field: &Field, value: &str) {
+		if field.name() == "message" {
+			self.message.push_str(value);
+		} else {
+			self.fields.push((field.name(), value.to_owned()));
+		}
+	}
+
+	fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+		if field.name() == "message" {
+			let _ = write!(self.message, "{:?}", value);
+		} else {
+			self.fields.push((field.name(), format!("{:?}", value)));
+		}
+	}
+}
+
+/// Gets the syslog severity of a level.
+fn severity(level: &Level) -> u8 {
+	match *level {
+		Level::ERROR => 3,
+		Level::WARN => 4,
+		Level::INFO => 6,
+		Level::DEBUG | Level::TRACE => 7,
+	}
+}
+
+/// Makes a field name into a journald one, which only has uppercase letters, digits, and underscores.
+fn journald_name(name: &str) -> String {
+	name.chars()
+		.map(|c| if c.is_ascii_alphanumeric() { c.to_ascii_uppercase() } else { '_' })
+		.collect()
+}
+
+/// Appends a field in journald’s native format, using the length-prefixed form for values that span lines.
+fn push_journald_field(datagram: &mut Vec<u8>, name: &str, value: &str) {
+	datagram.extend_from_slice(name.as_bytes());
+
+	if value.contains('\n') {
+		datagram.push(b'\n');
+		datagram.extend_from_slice(&(value.len() as u64).to_le_bytes());
+	} else {
+		datagram.push(b'=');
+	}
+
+	datagram.extend_from_slice(value.as_bytes());
+	datagram.push(b'\n');
+}
+
+impl SystemLog {
+	fn connect(path: &str, protocol: Protocol) -> io::Result<Self> {
+		let socket = UnixDatagram::unbound()?;
+		socket.connect(path)?;
+
+		// A full socket buffer drops messages instead of stalling clients.
+		socket.set_nonblocking(true)?;
+
+		Ok(Self { socket, protocol })
+	}
+
+	pub fn syslog() -> io::Result<Self> {
+		Self::connect(SYSLOG_PATH, Protocol::Syslog)
+	}
+
+	pub fn journald() -> io::Result<Self> {
+		Self::connect(JOURNALD_PATH, Protocol::Journald)
+	}
+}
+
+impl<S: Subscriber + for<'a> LookupSpan<'a>> Layer<S> for SystemLog {
+	fn new_span(&self, attributes: &Attributes<'_>, id: &Id, context: Context<'_, S>) {
+		let mut fields = Fields::default();
+		attributes.record(&mut fields);
+
+		if let Some(span) = context.span(id) {
+			span.extensions_mut().insert(SpanFields(fields.fields));
+		}
+	}
+
+	fn on_event(&self, event: &Event<'_>, context: Context<'_, S>) {
+		let mut fields = Fields::default();
+		event.record(&mut fields);
+
+		// The spans the event is in, outermost first, as `name{field=value …}: ` like stderr’s text format.
+		let mut spans = Vec::new();
+		let mut next = context.lookup_current();
+
+		while let Some(span) = next {
+			next = span.parent();
+			spans.push(span);
+		}
+
+		let mut text = String::new();
+
+		for span in spans.iter().rev() {
+			text.push_str(span.name());
+
+			if let Some(SpanFields(span_fields)) = span.extensions().get::<SpanFields>() {
+				let span_fields: Vec<String> = span_fields.iter().map(|(name, value)| format!("{}={}", name, value)).collect();
+				let _ = write!(text, "{{{}}}", span_fields.join(" "));
+			}
+
+			text.push_str(": ");
+		}
+
+		text.push_str(&fields.message);
+
+		for (name, value) in &fields.fields {
+			let _ = write!(text, " {}={}", name, value);
+		}
+
+		let metadata = event.metadata();
+		let severity = severity(metadata.level());
+
+		let datagram =
+			match self.protocol {
+				Protocol::Syslog => format!("<{}>{}[{}]: {}", FACILITY_DAEMON * 8 + severity, IDENTIFIER, process::id(), text).into_bytes(),
+				Protocol::Journald => {
+					let mut datagram = Vec::new();
+					push_journald_field(&mut datagram, "MESSAGE", &text);
+					push_journald_field(&mut datagram, "PRIORITY", &severity.to_string());
+					push_journald_field(&mut datagram, "SYSLOG_IDENTIFIER", IDENTIFIER);
+					push_journald_field(&mut datagram, "TARGET", metadata.target());
+
+					for span in &spans {
+						if let Some(SpanFields(span_fields)) = span.extensions().get::<SpanFields>() {
+							for (name, value) in span_fields {
+								push_journald_field(&mut datagram, &journald_name(&format!("{}_{}", span.name(), name)), value);
+							}
+						}
+					}
+
+					for (name, value) in &fields.fields {
+						push_journald_field(&mut datagram, &journald_name(name), value);
+					}
+
+					datagram
+				}
+			};
+
+		// Logging is best-effort, like writing to stderr.
+		let _ = self.socket.send(&datagram);
+	}
+}
@@ -0,0 +1,99 @@
+use std::env;
+use std::ffi::OsString;
+use std::fs;
+use std::path::PathBuf;
+use std::process;
+use std::rc::Rc;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use tokio::sync::{mpsc, watch};
+
+use super::address::Address;
+use super::cli::{Command, parse_args_from};
+use super::overrides::Verdict;
+use super::persist::{LOG_FILE_NAME, OperationLog};
+use super::time_list::CoarseSystemTime;
+use super::tree::USER_BYTES;
+use super::{Shared, interact, new_tree, run_local};
+
+/// Makes a persistence directory that no other test uses.
+fn persist_path() -> PathBuf {
+	static NEXT: AtomicUsize = AtomicUsize::new(0);
+	let path = env::temp_dir().join(format!("iptooled-test-serve-{}-{}", process::id(), NEXT.fetch_add(1, Ordering::Relaxed)));
+	fs::create_dir(&path).unwrap();
+	path
+}
+
+/// Sets up the state of a daemon serving from a new persistence directory, with `options` on the command line, returning the directory to remove afterwards.
+fn serving(options: &[&str]) -> (Rc<Shared>, watch::Receiver<bool>, PathBuf) {
+	let persist_path = persist_path();
+	let socket_path = persist_path.join("socket");
+	let args: Vec<OsString> =
+		["iptooled", "serve"].iter().chain(options)
+			.map(OsString::from)
+			.chain(vec![persist_path.clone().into_os_string(), socket_path.into_os_string()])
+			.collect();
+
+	let options =
+		match parse_args_from(args) {
+			Command::Serve(options) => options,
+			_ => unreachable!(),
+		};
+
+	let mut tree = new_tree(&options).unwrap();
+	let log = OperationLog::open(&options.persist_path.join(LOG_FILE_NAME), &mut tree).unwrap();
+	let (shared, shutdown) = Shared::new(tree, log, &options).unwrap();
+	(shared, shutdown, persist_path)
+}
+
+/// Checks that overriding a prefix changes query results right away when they’re served from a snapshot.
+#[test]
+fn override_replaces_snapshot() {
+	let (shared, _shutdown, persist_path) = serving(&["--snapshot-interval", "3600"]);
+	let address: Address = "2001:db8::1".parse().unwrap();
+	let snapshot = shared.tree.borrow_mut().snapshot(CoarseSystemTime::now());
+	*shared.snapshot.borrow_mut() = Some(Arc::new(snapshot));
+
+	assert!(shared.set_override(address.prefix(48), Some(Verdict::Spam)));
+	assert_eq!(shared.query(&address).verdict, Some(Verdict::Spam));
+
+	assert!(shared.set_override(address.prefix(48), None));
+	assert_eq!(shared.query(&address).verdict, None);
+
+	fs::remove_dir_all(persist_path).unwrap();
+}
+
+/// Sends requests to a daemon as one client, on the admin socket or the main one, and gets everything it responds with before the connection ends.
+fn respond(shared: &Rc<Shared>, shutdown: &watch::Receiver<bool>, requests: &[u8], admin: bool) -> Vec<u8> {
+	let mut response = Vec::new();
+	let (active, _) = mpsc::channel(1);
+	run_local(async {
+		interact(Rc::clone(shared), requests, &mut response, None, admin, shutdown.clone(), active).await;
+		Ok(())
+	}).unwrap();
+	response
+}
+
+/// Checks that with an admin socket, the main socket disconnects clients that list a user’s reports, label prefixes, or list every prefix, without answering.
+#[test]
+fn main_socket_rejects_admin_requests() {
+	let (shared, shutdown, persist_path) = serving(&["--admin-socket", "admin"]);
+
+	let mut user_entries = vec![16];
+	user_entries.extend_from_slice(&[0; USER_BYTES]);
+
+	let mut set_label = vec![22];
+	set_label.extend_from_slice(&"2001:db8::".parse::<Address>().unwrap().0);
+	set_label.push(32);
+	set_label.push(5);
+	set_label.extend_from_slice(b"label");
+
+	let list_prefixes = vec![21];
+
+	for request in &[user_entries, set_label, list_prefixes] {
+		assert_ne!(respond(&shared, &shutdown, request, true), b"");
+		assert_eq!(respond(&shared, &shutdown, request, false), b"");
+	}
+
+	fs::remove_dir_all(persist_path).unwrap();
+}
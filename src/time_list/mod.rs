@@ -1,100 +1,196 @@
 #[cfg(test)]
 mod tests;
 
-use std::collections::VecDeque;
+use std::collections::{VecDeque, vec_deque};
 use std::convert::TryFrom;
+use std::fmt;
+use std::marker::PhantomData;
+use std::mem;
 use std::ops::{AddAssign, Sub};
-use std::time::SystemTime;
+use std::time::{Duration, SystemTime};
 
+/// The unit a coarse time or duration counts in.
+pub trait TimeUnit: Clone + Copy + fmt::Debug + Eq + Ord {
+	/// The length of the unit, in seconds.
+	const SECONDS: u64;
+}
+
+/// The unit of most times, including the ones in the operation log.
 #[derive(Clone, Copy, Debug, Eq, Ord, PartialEq, PartialOrd)]
-pub struct CoarseDuration {
-	pub hours: u16,  // 2^16 hours is 7.5 years
+pub struct Hours;
+
+impl TimeUnit for Hours {
+	const SECONDS: u64 = 3600;
 }
 
+/// A finer unit, for windows too short to measure in hours.
 #[derive(Clone, Copy, Debug, Eq, Ord, PartialEq, PartialOrd)]
-pub struct CoarseSystemTime {
-	epoch_hours: u32,
+pub struct Minutes;
+
+impl TimeUnit for Minutes {
+	const SECONDS: u64 = 60;
 }
 
-impl CoarseSystemTime {
-	/// Gets the current time with a precision of one hour.
+#[derive(Clone, Copy, Debug, Eq, Ord, PartialEq, PartialOrd)]
+pub struct CoarseDuration<U: TimeUnit = Hours> {
+	units: u16,  // 2^16 hours is 7.5 years, and 2^16 minutes is 45 days
+	unit: PhantomData<U>,
+}
+
+impl<U: TimeUnit> CoarseDuration<U> {
+	pub const fn new(units: u16) -> Self {
+		Self { units, unit: PhantomData }
+	}
+
+	pub const fn units(self) -> u16 {
+		self.units
+	}
+
+	/// Gets a duration in whole units, rounded down, or `None` if it’s 2^16 units or longer.
+	pub fn from_duration(duration: Duration) -> Option<Self> {
+		u16::try_from(duration.as_secs() / U::SECONDS).ok().map(Self::new)
+	}
+
+	pub fn to_duration(self) -> Duration {
+		Duration::from_secs(u64::from(self.units) * U::SECONDS)
+	}
+}
+
+impl CoarseDuration<Hours> {
+	/// Panics if that’s 2^16 hours or more, which is at compile time in constants.
+	pub const fn from_days(days: u16) -> Self {
+		Self::new(days * 24)
+	}
+}
+
+#[derive(Clone, Copy, Debug, Eq, Ord, PartialEq, PartialOrd)]
+pub struct CoarseSystemTime<U: TimeUnit = Hours> {
+	epoch_units: u32,
+	unit: PhantomData<U>,
+}
+
+impl<U: TimeUnit> CoarseSystemTime<U> {
+	/// Gets the current time with a precision of one unit.
 	pub fn now() -> Self {
 		let epoch_time = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).expect("SystemTime before Unix epoch");
 
-		Self {
-			epoch_hours: u32::try_from(epoch_time.as_secs() / 3600).expect("SystemTime after year 491936"),
-		}
+		Self::from_duration_since_epoch(epoch_time).expect("SystemTime too far in the future")
+	}
+
+	pub const fn from_epoch_units(epoch_units: u32) -> Self {
+		Self { epoch_units, unit: PhantomData }
+	}
+
+	/// Gets the time a duration after the Unix epoch, rounded down, or `None` if it’s 2^32 units or more.
+	pub fn from_duration_since_epoch(duration: Duration) -> Option<Self> {
+		u32::try_from(duration.as_secs() / U::SECONDS).ok().map(Self::from_epoch_units)
+	}
+
+	pub fn duration_since_epoch(self) -> Duration {
+		Duration::from_secs(u64::from(self.epoch_units) * U::SECONDS)
+	}
+
+	/// Adds a duration, stopping at the latest representable time instead of panicking.
+	pub fn saturating_add(self, duration: CoarseDuration<U>) -> Self {
+		Self::from_epoch_units(self.epoch_units.saturating_add(duration.units.into()))
 	}
 
-	/// Gets the time since a reference time, returning zero for times up to one hour later, panicking for times later than that, and panicking for times 2^16 or more hours earlier.
-	pub fn time_since(self, other: Self) -> CoarseDuration {
-		let hours =
-			if self.epoch_hours < other.epoch_hours {
-				if self.epoch_hours + 1 < other.epoch_hours {
-					panic!("Tried to get the time since a time more than an hour in the future");
-				}
+	/// Subtracts a duration, stopping at the Unix epoch instead of panicking.
+	pub fn saturating_sub(self, duration: CoarseDuration<U>) -> Self {
+		Self::from_epoch_units(self.epoch_units.saturating_sub(duration.units.into()))
+	}
 
+	/// Gets the same time in another unit, rounded down.
+	pub fn convert<V: TimeUnit>(self) -> CoarseSystemTime<V> {
+		let epoch_units = u64::from(self.epoch_units) * U::SECONDS / V::SECONDS;
+		CoarseSystemTime::from_epoch_units(u32::try_from(epoch_units).expect("Conversion resulted in a time too far in the future"))
+	}
+
+	/// Gets the time since a reference time, returning zero for later times, like after the clock goes backwards, and panicking for times 2^16 or more units earlier.
+	pub fn time_since(self, other: Self) -> CoarseDuration<U> {
+		let units =
+			if self.epoch_units < other.epoch_units {
 				0
 			} else {
-				u16::try_from(self.epoch_hours - other.epoch_hours)
-					.expect("Tried to get the time since 2^16 or more hours in the past")
+				u16::try_from(self.epoch_units - other.epoch_units)
+					.expect("Tried to get the time since 2^16 or more units in the past")
 			};
 
-		CoarseDuration { hours }
+		CoarseDuration::new(units)
+	}
+}
+
+impl CoarseSystemTime<Hours> {
+	pub const fn from_epoch_hours(epoch_hours: u32) -> Self {
+		Self::from_epoch_units(epoch_hours)
+	}
+
+	pub const fn epoch_hours(self) -> u32 {
+		self.epoch_units
 	}
 }
 
-impl AddAssign<CoarseDuration> for CoarseSystemTime {
-	fn add_assign(&mut self, duration: CoarseDuration) {
-		self.epoch_hours = self.epoch_hours.checked_add(duration.hours.into())
-			.expect("Addition resulted in a time after year 491936");
+impl<U: TimeUnit> AddAssign<CoarseDuration<U>> for CoarseSystemTime<U> {
+	fn add_assign(&mut self, duration: CoarseDuration<U>) {
+		self.epoch_units = self.epoch_units.checked_add(duration.units.into())
+			.expect("Addition resulted in a time too far in the future");
 	}
 }
 
-impl Sub<CoarseDuration> for CoarseSystemTime {
+impl<U: TimeUnit> Sub<CoarseDuration<U>> for CoarseSystemTime<U> {
 	type Output = Self;
 
-	fn sub(self, duration: CoarseDuration) -> Self {
-		let epoch_hours = self.epoch_hours.checked_sub(duration.hours.into())
+	fn sub(self, duration: CoarseDuration<U>) -> Self {
+		let epoch_units = self.epoch_units.checked_sub(duration.units.into())
 			.expect("Subtraction resulted in a time before Unix epoch");
 
-		Self { epoch_hours }
+		Self::from_epoch_units(epoch_units)
 	}
 }
 
 #[derive(Clone, Debug)]
-struct Entry<T> {
+struct Entry<T, U: TimeUnit> {
 	value: T,
-	offset: CoarseDuration,
+	offset: CoarseDuration<U>,
 }
 
 #[derive(Clone, Debug)]
-pub struct TimeList<T> {
-	values: VecDeque<Entry<T>>,
-	head_tail: Option<(CoarseSystemTime, CoarseSystemTime)>,
-	limit: CoarseDuration,
+pub struct TimeList<T, U: TimeUnit = Hours> {
+	values: VecDeque<Entry<T, U>>,
+	head_tail: Option<(CoarseSystemTime<U>, CoarseSystemTime<U>)>,
+	limit: CoarseDuration<U>,
+	/// The number of values pushed with times earlier than the latest time in the list.
+	clamped: u64,
+	/// The most values the list has held at once.
+	max_len: usize,
 }
 
-impl<T> TimeList<T> {
-	pub fn new(limit: CoarseDuration) -> Self {
+impl<T, U: TimeUnit> TimeList<T, U> {
+	pub fn new(limit: CoarseDuration<U>) -> Self {
 		Self {
 			values: VecDeque::new(),
 			head_tail: None,
 			limit,
+			clamped: 0,
+			max_len: 0,
 		}
 	}
 
-	/// Adds a value to the end of the list, associated with a time. Doesn’t trim the list, so the time doesn’t have to be the current time, but it does have to be at least as late as the other times in the list.
-	pub fn push(&mut self, value: T, time: CoarseSystemTime) {
+	/// Adds a value to the end of the list, associated with a time. Doesn’t trim the list, so the time doesn’t have to be the current time, but values are kept in order of time, so a time earlier than the latest time in the list, like after the clock goes backwards, is replaced by the latest time.
+	pub fn push(&mut self, value: T, time: CoarseSystemTime<U>) {
 		let offset =
 			match self.head_tail {
 				None => {
 					self.head_tail = Some((time, time));
-					CoarseDuration { hours: 0 }
+					CoarseDuration::new(0)
 				},
 				Some((_, ref mut tail)) => {
+					if time < *tail {
+						self.clamped += 1;
+					}
+
 					let offset = time.time_since(*tail);
-					*tail = time;
+					*tail = time.max(*tail);
 					offset
 				},
 			};
@@ -103,10 +199,112 @@ impl<T> TimeList<T> {
 			value,
 			offset,
 		});
+
+		self.max_len = self.max_len.max(self.values.len());
+	}
+
+	pub fn len(&self) -> usize {
+		self.values.len()
+	}
+
+	/// Gets the most values the list has held at once, to see how big it gets between expiries.
+	pub fn max_len(&self) -> usize {
+		self.max_len
+	}
+
+	/// Gets the number of values that were pushed with times earlier than the latest time in the list, and were given the latest time instead.
+	pub fn clamped(&self) -> u64 {
+		self.clamped
+	}
+
+	/// Gets the memory allocated for the list’s values, in bytes.
+	pub fn allocated_bytes(&self) -> usize {
+		self.values.capacity() * mem::size_of::<Entry<T, U>>()
+	}
+
+	/// Iterates over the values and their times, in order, without removing them.
+	pub fn iter(&self) -> Iter<'_, T, U> {
+		Iter {
+			values: self.values.iter(),
+			time: self.head_tail.map_or(CoarseSystemTime::from_epoch_units(0), |(head, _)| head),
+		}
+	}
+
+	/// Removes the value at an index, returning it and its time, if there is one.
+	pub fn remove(&mut self, index: usize) -> Option<(T, CoarseSystemTime<U>)> {
+		let (_, time) = self.iter().nth(index)?;
+		let removed = self.values.remove(index).unwrap();
+
+		if self.values.is_empty() {
+			self.head_tail = None;
+			return Some((removed.value, time));
+		}
+
+		let (head, tail) = self.head_tail.as_mut().unwrap();
+
+		// The next value is now that much later than the one before it, or than the head if it’s the first.
+		match self.values.get_mut(index) {
+			Some(next) if index == 0 => {
+				*head += next.offset;
+				next.offset = CoarseDuration::new(0);
+			}
+			Some(next) => {
+				next.offset = CoarseDuration::new(next.offset.units + removed.offset.units);
+			}
+			None => {
+				*tail = time - removed.offset;
+			}
+		}
+
+		Some((removed.value, time))
 	}
 
-	pub fn trim<'a>(&'a mut self, now: CoarseSystemTime) -> Trim<'a, T> {
-		let cutoff = now - self.limit;
+	/// Removes the values matching a predicate of a value and its time, wherever they are in the list, and returns them and their times, in order.
+	pub fn remove_where(&mut self, mut predicate: impl FnMut(&T, CoarseSystemTime<U>) -> bool) -> Vec<(T, CoarseSystemTime<U>)> {
+		let mut removed = Vec::new();
+		let mut kept = VecDeque::with_capacity(self.values.len());
+		let mut kept_head_tail: Option<(CoarseSystemTime<U>, CoarseSystemTime<U>)> = None;
+		let mut time = self.head_tail.map_or(CoarseSystemTime::from_epoch_units(0), |(head, _)| head);
+
+		for Entry { value, offset } in self.values.drain(..) {
+			time += offset;
+
+			if predicate(&value, time) {
+				removed.push((value, time));
+				continue;
+			}
+
+			let offset =
+				match &mut kept_head_tail {
+					None => {
+						kept_head_tail = Some((time, time));
+						CoarseDuration::new(0)
+					}
+					Some((_, tail)) => {
+						let offset = time.time_since(*tail);
+						*tail = time;
+						offset
+					}
+				};
+
+			kept.push_back(Entry { value, offset });
+		}
+
+		self.values = kept;
+		self.head_tail = kept_head_tail;
+		removed
+	}
+
+	/// Removes every value, in order.
+	pub fn drain(&mut self) -> Trim<'_, T, U> {
+		Trim {
+			list: self,
+			cutoff: CoarseSystemTime::from_epoch_units(u32::max_value()),
+		}
+	}
+
+	pub fn trim<'a>(&'a mut self, now: CoarseSystemTime<U>) -> Trim<'a, T, U> {
+		let cutoff = now.saturating_sub(self.limit);
 
 		Trim {
 			list: self,
@@ -115,16 +313,16 @@ impl<T> TimeList<T> {
 	}
 }
 
-pub struct Trim<'a, T> {
-	list: &'a mut TimeList<T>,
-	cutoff: CoarseSystemTime,
+pub struct Trim<'a, T, U: TimeUnit = Hours> {
+	list: &'a mut TimeList<T, U>,
+	cutoff: CoarseSystemTime<U>,
 }
 
-impl<'a, T> Iterator for Trim<'a, T> {
-	type Item = (T, CoarseSystemTime);
+impl<'a, T, U: TimeUnit> Iterator for Trim<'a, T, U> {
+	type Item = (T, CoarseSystemTime<U>);
 
 	fn next(&mut self) -> Option<Self::Item> {
-		let (ref mut head, _) = self.list.head_tail?;
+		let (head, _) = self.list.head_tail.as_mut()?;
 		let trim_time = *head;
 
 		if trim_time >= self.cutoff {
@@ -132,11 +330,11 @@ impl<'a, T> Iterator for Trim<'a, T> {
 		}
 
 		let trimmed = self.list.values.pop_front().unwrap();
-		debug_assert!(trimmed.offset == CoarseDuration { hours: 0 });
+		debug_assert!(trimmed.offset == CoarseDuration::new(0));
 
 		if let Some(next) = self.list.values.front_mut() {
 			*head += next.offset;
-			next.offset = CoarseDuration { hours: 0 };
+			next.offset = CoarseDuration::new(0);
 		} else {
 			self.list.head_tail = None;
 		}
@@ -144,3 +342,19 @@ impl<'a, T> Iterator for Trim<'a, T> {
 		Some((trimmed.value, trim_time))
 	}
 }
+
+pub struct Iter<'a, T, U: TimeUnit = Hours> {
+	values: vec_deque::Iter<'a, Entry<T, U>>,
+	/// The time of the previous value, or the head’s before the first.
+	time: CoarseSystemTime<U>,
+}
+
+impl<'a, T, U: TimeUnit> Iterator for Iter<'a, T, U> {
+	type Item = (&'a T, CoarseSystemTime<U>);
+
+	fn next(&mut self) -> Option<Self::Item> {
+		let entry = self.values.next()?;
+		self.time += entry.offset;
+		Some((&entry.value, self.time))
+	}
+}
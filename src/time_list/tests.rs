@@ -7,7 +7,7 @@ use super::{CoarseDuration, CoarseSystemTime, TimeList};
 
 impl Arbitrary for CoarseDuration {
 	fn arbitrary<G: Gen>(g: &mut G) -> Self {
-		Self { hours: Arbitrary::arbitrary(g) }
+		Self::new(Arbitrary::arbitrary(g))
 	}
 }
 
@@ -26,16 +26,14 @@ impl Arbitrary for CoarseGap {
 		let hours = hours as u16;
 
 		Self {
-			duration: CoarseDuration { hours },
+			duration: CoarseDuration::new(hours),
 		}
 	}
 }
 
 impl Arbitrary for CoarseSystemTime {
 	fn arbitrary<G: Gen>(g: &mut G) -> Self {
-		Self {
-			epoch_hours: g.gen_range(262000, 6400000),  // ~2000 to ~2700
-		}
+		Self::from_epoch_hours(g.gen_range(262000, 6400000))  // ~2000 to ~2700
 	}
 }
 
@@ -46,9 +44,9 @@ impl<T: Arbitrary> Arbitrary for TimeList<T> {
 			g.gen_range(0, s)
 		};
 
-		let mut result = TimeList::new(CoarseDuration {
-			hours: g.gen_range(0, u16::try_from(g.size()).unwrap_or(std::u16::MAX)),
-		});
+		let mut result = TimeList::new(CoarseDuration::new(
+			g.gen_range(0, u16::try_from(g.size()).unwrap_or(std::u16::MAX)),
+		));
 		let mut now: CoarseSystemTime = Arbitrary::arbitrary(g);
 
 		for _ in 0..size {
@@ -61,10 +59,16 @@ impl<T: Arbitrary> Arbitrary for TimeList<T> {
 	}
 }
 
+#[quickcheck]
+fn duration_round_trips(duration: CoarseDuration, time: CoarseSystemTime) -> bool {
+	CoarseDuration::from_duration(duration.to_duration()) == Some(duration)
+		&& CoarseSystemTime::from_duration_since_epoch(time.duration_since_epoch()) == Some(time)
+}
+
 #[quickcheck]
 fn front_time_is_head(list: TimeList<u32>) -> bool {
 	list.values.front().map(|entry| entry.offset)
-		== list.head_tail.map(|_| CoarseDuration { hours: 0 })
+		== list.head_tail.map(|_| CoarseDuration::new(0))
 }
 
 #[quickcheck]
@@ -84,6 +88,68 @@ fn back_time_is_tail(list: TimeList<u32>) -> bool {
 	}) == tail
 }
 
+/// Checks that the most values the list has held is at least as many as it holds now.
+#[quickcheck]
+fn max_len_is_at_least_len(list: TimeList<u32>) -> bool {
+	list.max_len() >= list.len()
+}
+
+/// Checks that trimming reports the times values were pushed with, when only some of them have expired.
+#[quickcheck]
+fn trimmed_times_are_push_times(start: CoarseSystemTime, gaps: Vec<CoarseGap>, limit: CoarseDuration) -> bool {
+	let mut list = TimeList::new(limit);
+	let mut now = start;
+
+	for gap in gaps {
+		list.push(now.epoch_hours(), now);
+		now += gap.duration;
+	}
+
+	let trimmed_correct = list.trim(now).all(|(value, time)| value == time.epoch_hours());
+
+	let head_correct = match (list.head_tail, list.values.front()) {
+		(Some((head, _)), Some(front)) => head.epoch_hours() == front.value,
+		(None, None) => true,
+		_ => false,
+	};
+
+	trimmed_correct && head_correct
+}
+
+/// Checks that iterating gives the same values and times as draining, without removing any.
+#[quickcheck]
+fn iter_matches_drain(list: TimeList<u32>) -> bool {
+	let iterated: Vec<(u32, CoarseSystemTime)> = list.iter().map(|(&value, time)| (value, time)).collect();
+	let len = list.len();
+	let drained: Vec<(u32, CoarseSystemTime)> = list.clone().drain().collect();
+
+	iterated == drained && list.len() == len
+}
+
+/// Checks that removing values keeps the times of the rest and the list’s bookkeeping.
+#[quickcheck]
+fn removal_keeps_times(mut list: TimeList<u32>, index: usize, divisor: u32) -> bool {
+	let times: Vec<(u32, CoarseSystemTime)> = list.iter().map(|(&value, time)| (value, time)).collect();
+	let index = if times.is_empty() { 0 } else { index % times.len() };
+	let divisor = divisor.max(2);
+
+	let mut expected = times.clone();
+	let removed = list.remove(index);
+	let removed_correct = removed == if times.is_empty() { None } else { Some(expected.remove(index)) };
+
+	let removed_where = list.remove_where(|&value, _| value % divisor == 0);
+	let removed_where_correct = removed_where.iter().all(|(value, _)| value % divisor == 0);
+	expected.retain(|(value, _)| value % divisor != 0);
+
+	let remaining: Vec<(u32, CoarseSystemTime)> = list.iter().map(|(&value, time)| (value, time)).collect();
+
+	removed_correct
+		&& removed_where_correct
+		&& remaining == expected
+		&& list.values.front().map(|entry| entry.offset) == list.head_tail.map(|_| CoarseDuration::new(0))
+		&& list.head_tail.map(|(head, _)| list.values.iter().fold(head, |mut m, n| { m += n.offset; m })) == list.head_tail.map(|(_, tail)| tail)
+}
+
 /// Checks that trimmed values are expired and that untrimmed values are unexpired.
 #[quickcheck]
 fn trimmed_values_are_expired(mut list: TimeList<u32>, step: CoarseGap) -> bool {
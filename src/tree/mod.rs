@@ -0,0 +1,1781 @@
+#[cfg(test)]
+pub mod tests;
+
+use std::cell::Cell;
+use std::cmp::{Ordering, Reverse};
+use std::collections::{hash_map, BTreeMap, BinaryHeap, HashMap};
+use std::convert::TryInto;
+use std::fmt;
+use std::hash::Hash;
+use std::mem;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use super::address::{ADDRESS_BITS, Address, AddressPrefix, IPV4_OFFSET_BITS};
+use super::cow_map::{self, CowMap};
+use super::decay::{DecayedWeights, WeightStats};
+use super::distinct::DistinctUsers;
+use super::mmdb::{Database, Value};
+use super::overrides::{Overrides, Verdict};
+use super::prefix_list::PrefixList;
+use super::time_list::{CoarseDuration, CoarseSystemTime, Minutes, TimeList, TimeUnit};
+
+pub const USER_BYTES: usize = 4;
+
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct User(u32);
+
+impl User {
+	pub const fn from_bytes(bytes: [u8; USER_BYTES]) -> Self {
+		Self(u32::from_be_bytes(bytes))
+	}
+
+	pub const fn to_bytes(self) -> [u8; USER_BYTES] {
+		self.0.to_be_bytes()
+	}
+}
+
+impl fmt::Display for User {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "{}", self.0)
+	}
+}
+
+/// The number of entries of each type for a prefix.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct SpamStats {
+	pub trusted_users: u32,
+	pub spam_users: u32,
+	pub abuse_users: u32,
+	pub phishing_users: u32,
+	pub bruteforce_users: u32,
+	pub automated_trusted_users: u32,
+	pub automated_spam_users: u32,
+}
+
+impl SpamStats {
+	pub const EMPTY: Self = Self {
+		trusted_users: 0,
+		spam_users: 0,
+		abuse_users: 0,
+		phishing_users: 0,
+		bruteforce_users: 0,
+		automated_trusted_users: 0,
+		automated_spam_users: 0,
+	};
+
+	pub fn users(&self, type_: OperationType) -> u32 {
+		match type_ {
+			OperationType::Trust => self.trusted_users,
+			OperationType::Spam => self.spam_users,
+			OperationType::Abuse => self.abuse_users,
+			OperationType::Phishing => self.phishing_users,
+			OperationType::Bruteforce => self.bruteforce_users,
+			OperationType::AutomatedTrust => self.automated_trusted_users,
+			OperationType::AutomatedSpam => self.automated_spam_users,
+		}
+	}
+
+	/// Gets the number of entries of every type.
+	pub fn total(&self) -> u32 {
+		OperationType::ALL.iter().map(|&type_| self.users(type_)).sum()
+	}
+
+	/// Estimates the probability that an address is spam rather than trusted: the mean of the posterior given the prior and these counts.
+	pub fn spam_probability(&self, prior: &Prior) -> f64 {
+		let spam = prior.spam + f64::from(self.spam_users);
+		let trusted = prior.trusted + f64::from(self.trusted_users);
+		spam / (spam + trusted)
+	}
+
+	/// Gets the lower bound of the Wilson score interval for the proportion of spam among spam and trust entries, with a z-score, like 1.96 for 95% confidence: a conservative estimate that stays low when there are few entries.
+	pub fn spam_lower_bound(&self, z: f64) -> f64 {
+		let spam = f64::from(self.spam_users);
+		let n = spam + f64::from(self.trusted_users);
+
+		if n == 0.0 {
+			return 0.0;
+		}
+
+		let p = spam / n;
+		let z2 = z * z;
+		let centre = p + z2 / (2.0 * n);
+		let margin = z * (p * (1.0 - p) / n + z2 / (4.0 * n * n)).sqrt();
+
+		((centre - margin) / (1.0 + z2 / n)).max(0.0)
+	}
+
+	/// Gets the verdict the counts lean toward: trusted if there are more trust entries than entries of other types, spam if there are fewer, and neutral if they’re balanced.
+	pub fn verdict(&self) -> Verdict {
+		let trusted = self.trusted_users + self.automated_trusted_users;
+		let reported = self.total() - trusted;
+
+		match trusted.cmp(&reported) {
+			Ordering::Greater => Verdict::Trusted,
+			Ordering::Less => Verdict::Spam,
+			Ordering::Equal => Verdict::Neutral,
+		}
+	}
+
+	fn users_mut(&mut self, type_: OperationType) -> &mut u32 {
+		match type_ {
+			OperationType::Trust => &mut self.trusted_users,
+			OperationType::Spam => &mut self.spam_users,
+			OperationType::Abuse => &mut self.abuse_users,
+			OperationType::Phishing => &mut self.phishing_users,
+			OperationType::Bruteforce => &mut self.bruteforce_users,
+			OperationType::AutomatedTrust => &mut self.automated_trusted_users,
+			OperationType::AutomatedSpam => &mut self.automated_spam_users,
+		}
+	}
+}
+
+/// The entries for a prefix, when the first of them was added, and when one was last added, which is also used for pruning.
+#[derive(Clone, Debug)]
+struct PrefixCounts {
+	stats: SpamStats,
+	created: CoarseSystemTime,
+	updated: CoarseSystemTime,
+	/// The distinct users with entries counting toward their limits, if they’re tracked.
+	users: Option<DistinctUsers>,
+	/// The number of spam entries within the velocity window.
+	recent_spam: u32,
+	/// The distinct users with entries of types other than trust counting toward their limits, if there’s a spam quarantine.
+	spam_reporters: Option<DistinctUsers>,
+	/// The `addition` of the entry that made the prefix.
+	made_by: u64,
+}
+
+impl PrefixCounts {
+	/// Gets the memory allocated for the distinct users and spam reporters, in bytes.
+	fn distinct_bytes(&self) -> usize {
+		self.users.iter().chain(&self.spam_reporters).map(DistinctUsers::allocated_bytes).sum()
+	}
+}
+
+/// Pseudo-counts of spam and trusted entries that every prefix starts with, i.e. the parameters of a beta prior on the probability that an address is spam.
+#[derive(Clone, Copy, Debug)]
+pub struct Prior {
+	pub spam: f64,
+	pub trusted: f64,
+}
+
+impl Prior {
+	/// A uniform prior.
+	pub const DEFAULT: Self = Self {
+		spam: 1.0,
+		trusted: 1.0,
+	};
+}
+
+#[derive(Clone, Debug)]
+pub struct QueryResult {
+	pub stats: SpamStats,
+	pub prefix_bits: u8,
+	/// The verdict of an override or list, or the one the counts lean toward, or `None` if nothing is known about the address.
+	pub verdict: Option<Verdict>,
+}
+
+impl QueryResult {
+	/// The result for an address nothing is known about.
+	pub const EMPTY: Self = Self {
+		stats: SpamStats::EMPTY,
+		prefix_bits: 0,
+		verdict: None,
+	};
+}
+
+#[derive(Clone, Debug)]
+pub struct WeightedResult {
+	pub weights: WeightStats,
+	pub prefix_bits: u8,
+}
+
+#[derive(Clone, Debug)]
+pub struct SeenResult {
+	pub stats: SpamStats,
+	pub prefix_bits: u8,
+	/// When the prefix was first and most recently reported, if the result comes from reports.
+	pub seen: Option<(CoarseSystemTime, CoarseSystemTime)>,
+}
+
+#[derive(Clone, Debug)]
+pub struct DistinctResult {
+	pub stats: SpamStats,
+	pub prefix_bits: u8,
+	/// The number of distinct users with entries for the prefix that still count toward their limits, estimated beyond a few dozen, if they’re tracked and the result comes from reports.
+	pub users: Option<u32>,
+}
+
+#[derive(Clone, Debug)]
+pub struct VelocityResult {
+	pub stats: SpamStats,
+	pub prefix_bits: u8,
+	/// The number of spam entries for the prefix within the velocity window, if there is one and the result comes from reports.
+	pub recent_spam: Option<u32>,
+}
+
+/// The size of a tree, for capacity planning.
+#[derive(Clone, Debug)]
+pub struct TreeSize {
+	pub prefixes: usize,
+	/// The number of users with entries that count toward their limits.
+	pub users: usize,
+	pub user_window_entries: usize,
+	/// Including trust entries, if they expire separately.
+	pub address_window_entries: usize,
+	/// A rough estimate of the memory used by the tree, in bytes.
+	pub estimated_bytes: usize,
+}
+
+/// The size of one of the windows entries are kept in until they expire.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct WindowSize {
+	pub entries: usize,
+	/// The memory allocated for the entries, in bytes.
+	pub bytes: usize,
+	/// The most entries the window has held at once since startup.
+	pub max_entries: usize,
+}
+
+impl WindowSize {
+	fn of<T, U: TimeUnit>(window: &TimeList<T, U>) -> Self {
+		Self {
+			entries: window.len(),
+			bytes: window.allocated_bytes(),
+			max_entries: window.max_len(),
+		}
+	}
+}
+
+/// Estimates of the memory used by each part of a tree, in bytes, so its growth can be attributed without a heap profiler.
+#[derive(Clone, Copy, Debug)]
+pub struct MemoryUsage {
+	/// The prefixes and their counts.
+	pub counts: usize,
+	/// The distinct users and spam reporters of prefixes, if they’re counted.
+	pub distinct_users: usize,
+	/// The number of entries of each user, and within each prefix if that’s limited.
+	pub users: usize,
+	pub user_window: usize,
+	/// Including trust entries, if they expire separately.
+	pub address_window: usize,
+	pub velocity_window: usize,
+	pub decay: usize,
+	/// The counts for autonomous systems, countries, and capped /64s.
+	pub groups: usize,
+	pub empty_cache: usize,
+}
+
+impl MemoryUsage {
+	/// Lists the estimate for each part by name, as used in metric names.
+	pub fn parts(&self) -> [(&'static str, usize); 9] {
+		[
+			("counts", self.counts),
+			("distinct_users", self.distinct_users),
+			("users", self.users),
+			("user_window", self.user_window),
+			("address_window", self.address_window),
+			("velocity_window", self.velocity_window),
+			("decay", self.decay),
+			("groups", self.groups),
+			("empty_cache", self.empty_cache),
+		]
+	}
+}
+
+/// The sizes of the user, address, and velocity windows, so their growth can be watched.
+#[derive(Clone, Debug)]
+pub struct WindowStats {
+	pub user_window: WindowSize,
+	/// Including trust entries, if they expire separately, in which case `max_entries` is the sum of each window’s most.
+	pub address_window: WindowSize,
+	/// All zero if there’s no velocity window.
+	pub velocity_window: WindowSize,
+	/// The number of entries made at times earlier than ones before them, usually because the clock went backwards, which were counted as made at the latest time instead.
+	pub clamped_times: u64,
+}
+
+/// The upper bounds of the buckets prefixes are counted in by their number of children: 0, 1, 2–3, 4–7, 8–15, 16–31, and 32 or more.
+pub const CHILDREN_BUCKETS: [usize; 7] = [0, 1, 3, 7, 15, 31, usize::max_value()];
+
+/// The shape of a tree’s prefixes, for finding out which settings make it use the most memory.
+#[derive(Clone, Debug)]
+pub struct TreeStructure {
+	/// The number of prefixes of each size, from 0 to 128 bits.
+	pub prefixes: Vec<usize>,
+	/// A rough estimate of the memory used by the prefixes of each size, in bytes.
+	pub estimated_bytes: Vec<usize>,
+	/// The number of prefixes in each of `CHILDREN_BUCKETS` by their number of children, i.e. longer prefixes with no prefix between them.
+	pub children: [usize; 7],
+}
+
+/// Estimates the memory used by a `BTreeMap`, whose nodes hold up to 11 entries and tend to be about two thirds full.
+pub fn estimated_btree_bytes<K, V>(map: &BTreeMap<K, V>) -> usize {
+	map.len() * (mem::size_of::<K>() + mem::size_of::<V>()) * 3 / 2
+}
+
+/// The most /64s to remember empty results for before starting over.
+const EMPTY_CACHE_LIMIT: usize = 1 << 16;
+
+/// The most entries to expire from each window at a time, so a request after a long lull doesn’t pay for expiring all of them at once. Later requests and the background expiry get the rest.
+const EXPIRY_BUDGET: usize = 4096;
+
+/// The size of the networks whose influence on shorter prefixes can be capped, which is usually one host or site’s.
+const NETWORK_BITS: u8 = 64;
+
+/// The size of the IPv4 networks diagnostics list, relative to the IPv4 address, which is usually one site’s.
+const IPV4_NETWORK_BITS: u8 = 24;
+
+/// The size of the prefixes one user’s entries are limited within, if they are, which is usually one ISP’s allocation.
+const USER_PREFIX_BITS: u8 = 32;
+
+/// The same for IPv4 addresses, relative to the IPv4 address.
+const IPV4_USER_PREFIX_BITS: u8 = 16;
+
+/// Gets the first 64 bits of an address, which identify its /64.
+fn network_key(address: &Address) -> u64 {
+	u64::from_be_bytes(address.0[..8].try_into().unwrap())
+}
+
+/// Gets the time of a spam entry in the velocity window, which is kept in minutes: the current minute for times in the current hour or later, and the start of the hour for earlier times, like the ones replayed from the log, which are only logged to the hour.
+fn velocity_time(now: CoarseSystemTime) -> CoarseSystemTime<Minutes> {
+	let current = CoarseSystemTime::<Minutes>::now();
+
+	if now >= current.convert() {
+		current
+	} else {
+		now.convert()
+	}
+}
+
+/// The IPv6 prefix sizes ISPs and registries commonly assign: single addresses, subnets, sites, and allocations.
+pub const ALLOCATION_BOUNDARIES: [u8; 5] = [128, 64, 56, 48, 32];
+
+/// The sizes of the prefixes of an address that its entries are counted for.
+#[derive(Clone, Copy, Debug)]
+pub struct PrefixLevels {
+	pub minimum: u8,
+	maximum: u8,
+	boundaries_only: bool,
+}
+
+impl PrefixLevels {
+	pub fn includes(self, bits: u8) -> bool {
+		bits >= self.minimum && bits <= self.maximum && (!self.boundaries_only || ALLOCATION_BOUNDARIES.contains(&bits))
+	}
+
+	/// Iterates over the included prefix sizes, shortest first.
+	pub fn sizes(self) -> impl DoubleEndedIterator<Item = u8> {
+		(self.minimum..=self.maximum).filter(move |&bits| self.includes(bits))
+	}
+}
+
+/// The prefixes an entry was counted for, so it can be removed from exactly those: the ones with a size in `levels` that were made no later than the entry. A prefix that was pruned or merged into a shorter one since then, and made again by later entries, doesn’t have it.
+#[derive(Clone, Copy, Debug)]
+struct Counted {
+	levels: PrefixLevels,
+	/// The number of entries added to the tree before this one.
+	addition: u64,
+}
+
+impl Counted {
+	fn includes(self, counts: &PrefixCounts) -> bool {
+		counts.made_by <= self.addition
+	}
+}
+
+/// Settings that can change between runs. The log is replayed with the current settings, so they apply to old operations too.
+#[derive(Clone, Debug)]
+pub struct TreeSettings {
+	/// The smallest shared prefix size considered meaningful. For IPv6, at least 4, because the entire internet is in 2000::/3.
+	pub prefix_bits_minimum: u8,
+
+	/// The smallest shared prefix size considered meaningful for IPv4 addresses, relative to the IPv4 address. They’re much more densely allocated.
+	pub ipv4_prefix_bits_minimum: u8,
+
+	/// The prefix size IPv6 addresses are truncated to before they’re stored, if they are, so that full addresses are never kept. Entries are only counted for prefixes up to that size.
+	pub truncate_bits: Option<u8>,
+
+	/// The same for IPv4 addresses, relative to the IPv4 address.
+	pub ipv4_truncate_bits: Option<u8>,
+
+	/// The number of entries of any type a user can have within `user_expiry`, except for types with their own limit.
+	pub entries_per_user: u16,
+
+	/// The number of trust entries a user can have within `user_expiry`, if they’re limited separately.
+	pub trust_entries_per_user: Option<u16>,
+
+	/// The number of entries for spam and the other report categories a user can have within `user_expiry`, if they’re limited separately.
+	pub spam_entries_per_user: Option<u16>,
+
+	/// The number of entries of any type a user can have within `user_expiry` in one IPv6 /32 or IPv4 /16, if limited.
+	pub entries_per_user_prefix: Option<u16>,
+
+	/// The time before an entry’s user information is discarded, making the effective number of entries per user `entries_per_user * address_expiry / user_expiry`.
+	pub user_expiry: CoarseDuration,
+
+	/// The time before an entry stops being considered useful and is discarded.
+	pub address_expiry: CoarseDuration,
+
+	/// The time before a trust entry is discarded, if different from `address_expiry`.
+	pub trust_address_expiry: Option<CoarseDuration>,
+
+	/// The time it takes for an entry’s weight to halve, if weights decay instead of staying the same until entries expire.
+	pub decay_half_life: Option<CoarseDuration>,
+
+	/// The time spam entries count as recent for, if the rate of spam reports is tracked.
+	pub velocity_window: Option<CoarseDuration<Minutes>>,
+
+	/// Whether to count IPv6 entries only for the prefix sizes in `ALLOCATION_BOUNDARIES` instead of for every prefix size.
+	pub allocation_boundaries_only: bool,
+
+	/// Whether to track the distinct users reporting each prefix.
+	pub distinct_users: bool,
+
+	/// The number of distinct users a prefix needs for queries to use it, if any, which requires `distinct_users`.
+	pub min_distinct_users: Option<u32>,
+
+	/// The number of distinct users that have to make reports other than trust for a prefix before queries count them, if any.
+	pub spam_quarantine: Option<u32>,
+
+	/// The number of entries a prefix needs before entries are counted for longer prefixes within it, if prefixes are only split off where reports are dense.
+	pub split_threshold: Option<u32>,
+
+	/// The number of entries of each type an IPv6 /64 can contribute to shorter prefixes, if limited, so that one host cycling through its addresses can’t inflate its whole network’s counts.
+	pub network_cap: Option<u32>,
+
+	/// How long to remember that queries for an IPv6 /64 without entries came up empty, if at all.
+	pub empty_cache_ttl: Option<Duration>,
+
+	/// The number of prefixes to track before pruning the ones least recently added to, if any.
+	pub max_prefixes: Option<usize>,
+
+	/// Prefixes that are always fully trusted and can’t be reported as spam.
+	pub allowlist: PrefixList,
+
+	/// Prefixes that are always fully spam, unless they’re also in the allowlist.
+	pub denylist: PrefixList,
+
+	/// A MaxMind-format ASN database, if entries are also counted per autonomous system.
+	pub asn_database: Option<Arc<Database>>,
+
+	/// A MaxMind-format country database, if entries are also counted per country.
+	pub country_database: Option<Arc<Database>>,
+}
+
+impl TreeSettings {
+	pub const DEFAULT: Self = Self {
+		prefix_bits_minimum: 12,
+		ipv4_prefix_bits_minimum: 24,
+		truncate_bits: None,
+		ipv4_truncate_bits: None,
+		entries_per_user: 5,
+		trust_entries_per_user: None,
+		spam_entries_per_user: None,
+		entries_per_user_prefix: None,
+		user_expiry: CoarseDuration::from_days(30),
+		address_expiry: CoarseDuration::from_days(365 * 2),
+		trust_address_expiry: None,
+		decay_half_life: None,
+		velocity_window: None,
+		allocation_boundaries_only: false,
+		distinct_users: false,
+		min_distinct_users: None,
+		spam_quarantine: None,
+		split_threshold: None,
+		network_cap: None,
+		empty_cache_ttl: None,
+		max_prefixes: None,
+		allowlist: PrefixList::EMPTY,
+		denylist: PrefixList::EMPTY,
+		asn_database: None,
+		country_database: None,
+	};
+
+	fn prefix_bits_minimum(&self, address: &Address) -> u8 {
+		if address.is_ipv4() {
+			IPV4_OFFSET_BITS + self.ipv4_prefix_bits_minimum
+		} else {
+			self.prefix_bits_minimum
+		}
+	}
+
+	/// Gets the prefix size an address is truncated to, if addresses like it are.
+	fn truncate_bits(&self, address: &Address) -> Option<u8> {
+		if address.is_ipv4() {
+			self.ipv4_truncate_bits.map(|bits| IPV4_OFFSET_BITS + bits)
+		} else {
+			self.truncate_bits
+		}
+	}
+
+	/// Truncates an address to the first address of its prefix of the size addresses like it are stored as, if they’re truncated.
+	fn truncate(&self, address: Address) -> Address {
+		match self.truncate_bits(&address) {
+			Some(bits) => address.prefix(bits).first().clone(),
+			None => address,
+		}
+	}
+
+	fn prefix_levels(&self, address: &Address) -> PrefixLevels {
+		let minimum = self.prefix_bits_minimum(address);
+
+		PrefixLevels {
+			minimum,
+			maximum: self.truncate_bits(address).map_or(ADDRESS_BITS, |bits| bits.max(minimum)),
+			boundaries_only: self.allocation_boundaries_only && !address.is_ipv4(),
+		}
+	}
+
+	/// Finds the autonomous system an address belongs to and the size of its network, if there’s an ASN database.
+	fn asn(&self, address: &Address) -> Option<(u32, u8)> {
+		let (value, bits) = self.asn_database.as_ref()?.lookup(address)?;
+
+		match value.get("autonomous_system_number") {
+			Some(&Value::Unsigned(asn)) => Some((asn as u32, bits)),
+			_ => None,
+		}
+	}
+
+	/// Finds the two-letter code of the country an address is in, or failing that, the country it’s registered to, if there’s a country database.
+	fn country(&self, address: &Address) -> Option<[u8; 2]> {
+		let (value, _) = self.country_database.as_ref()?.lookup(address)?;
+
+		["country", "registered_country"].iter()
+			.filter_map(|key| match value.get(key)?.get("iso_code") {
+				Some(Value::String(code)) => code.as_bytes().try_into().ok(),
+				_ => None,
+			})
+			.next()
+	}
+
+	/// Gets the prefix of an address that one user’s entries are limited within.
+	fn user_prefix(&self, address: &Address) -> AddressPrefix {
+		address.prefix(if address.is_ipv4() { IPV4_OFFSET_BITS + IPV4_USER_PREFIX_BITS } else { USER_PREFIX_BITS })
+	}
+
+	/// Gets the limit an entry of a type counts toward, and the number of entries it allows.
+	fn user_limit(&self, type_: OperationType) -> (UserLimit, u16) {
+		let separate =
+			if type_.is_trust() {
+				self.trust_entries_per_user.map(|limit| (UserLimit::Trust, limit))
+			} else {
+				self.spam_entries_per_user.map(|limit| (UserLimit::Spam, limit))
+			};
+
+		separate.unwrap_or((UserLimit::Combined, self.entries_per_user))
+	}
+}
+
+/// Which of a user’s limits an entry counts toward.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+enum UserLimit {
+	/// The limit for entries of every type without a separate one.
+	Combined,
+	Trust,
+	Spam,
+}
+
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub enum OperationType {
+	Trust,
+	Spam,
+	Abuse,
+	Phishing,
+	Bruteforce,
+	/// Trust from an automated source, like a filter’s verdict, counted separately from trust reported by people.
+	AutomatedTrust,
+	/// Spam from an automated source, like a honeypot, counted separately from spam reported by people.
+	AutomatedSpam,
+}
+
+pub const OPERATION_TYPES: usize = 7;
+
+impl OperationType {
+	pub const ALL: [Self; OPERATION_TYPES] = [Self::Trust, Self::Spam, Self::Abuse, Self::Phishing, Self::Bruteforce, Self::AutomatedTrust, Self::AutomatedSpam];
+
+	/// The types reported by people, which come first in `ALL`.
+	pub const CATEGORIES: [Self; 5] = [Self::Trust, Self::Spam, Self::Abuse, Self::Phishing, Self::Bruteforce];
+
+	pub fn name(self) -> &'static str {
+		match self {
+			Self::Trust => "trust",
+			Self::Spam => "spam",
+			Self::Abuse => "abuse",
+			Self::Phishing => "phishing",
+			Self::Bruteforce => "bruteforce",
+			Self::AutomatedTrust => "automated-trust",
+			Self::AutomatedSpam => "automated-spam",
+		}
+	}
+
+	/// Gets the code of the request that reports this type, which the operation log uses too.
+	pub fn code(self) -> u8 {
+		match self {
+			Self::Trust => 1,
+			Self::Spam => 2,
+			Self::Abuse => 7,
+			Self::Phishing => 8,
+			Self::Bruteforce => 9,
+			Self::AutomatedTrust => 26,
+			Self::AutomatedSpam => 27,
+		}
+	}
+
+	/// Checks whether this type vouches for an address rather than reporting it.
+	pub fn is_trust(self) -> bool {
+		match self {
+			Self::Trust | Self::AutomatedTrust => true,
+			Self::Spam | Self::Abuse | Self::Phishing | Self::Bruteforce | Self::AutomatedSpam => false,
+		}
+	}
+}
+
+#[derive(Clone, Debug)]
+pub struct Operation(pub OperationType, pub Address, pub User);
+
+#[derive(Clone, Debug)]
+struct AddressOperation(OperationType, Address, Counted);
+
+/// A removal of reports that still count toward their users, logged as a tombstone so that replaying the log removes them again.
+#[derive(Clone, Debug)]
+pub enum Retraction {
+	/// Removes a user’s most recent report of a type for an address.
+	Report(OperationType, Address, User),
+	/// Removes every report from a user.
+	User(User),
+}
+
+/// Anything that changes a tree’s entries, as stored in the operation log.
+#[derive(Clone, Debug)]
+pub enum TreeOperation {
+	Perform(Operation),
+	Retract(Retraction),
+}
+
+/// An operation counted in a tree, with the user it still counts toward, if any.
+#[derive(Clone, Debug, Eq, Ord, PartialEq, PartialOrd)]
+pub struct Entry {
+	pub time: CoarseSystemTime,
+	pub type_: OperationType,
+	pub address: Address,
+	pub user: Option<User>,
+}
+
+/// A step in turning one tree’s entries into another’s.
+#[derive(Clone, Debug)]
+pub enum Change {
+	Add(Entry),
+	Remove(Entry),
+}
+
+#[derive(Clone, Debug)]
+pub struct SpamTree {
+	settings: TreeSettings,
+	overrides: Overrides,
+	users: HashMap<(User, UserLimit), u16>,
+	/// The number of entries each user has within each prefix from `user_prefix`, if limited.
+	user_prefixes: HashMap<(User, AddressPrefix), u16>,
+	/// Shared with snapshots a chunk at a time, and each chunk copied on its next change while any are still around.
+	counts: CowMap<AddressPrefix, PrefixCounts>,
+	/// The entries for each autonomous system, if there’s an ASN database.
+	asn_counts: HashMap<u32, SpamStats>,
+	/// The entries for each country, if there’s a country database.
+	country_counts: HashMap<[u8; 2], SpamStats>,
+	/// The entries for each IPv6 /64, by `network_key`, if their influence is capped.
+	network_counts: HashMap<u64, SpamStats>,
+	/// The number of entries ever added, which numbers them for `Counted`.
+	additions: u64,
+	user_window: TimeList<(Operation, Counted)>,
+	address_window: TimeList<AddressOperation>,
+	/// The trust entries that no longer count toward their users, if they expire at a different time from other entries.
+	trust_address_window: Option<TimeList<AddressOperation>>,
+	decay: Option<DecayedWeights>,
+	/// The addresses of spam entries within the velocity window and the prefixes they were counted for, if there is one.
+	recent_spam_window: Option<TimeList<(Address, Counted), Minutes>>,
+	/// The /64s, by `network_key`, whose queries came up empty until an entry is added near them, and when that stops being remembered.
+	empty_cache: BTreeMap<u64, Instant>,
+	/// The latest time the tree has been used at, which it keeps using until the clock catches up if it goes backwards, so windows only move forward.
+	latest: CoarseSystemTime,
+	/// The number of entries made at times earlier than `latest`, which were counted as made at `latest` instead.
+	clamped: u64,
+	/// The memory allocated for the distinct users of every prefix, kept up to date as they change so estimating it doesn’t go through every prefix.
+	distinct_bytes: usize,
+}
+
+impl SpamTree {
+	pub fn new(settings: TreeSettings) -> Self {
+		Self {
+			overrides: Overrides::default(),
+			users: HashMap::new(),
+			user_prefixes: HashMap::new(),
+			counts: CowMap::new(),
+			asn_counts: HashMap::new(),
+			country_counts: HashMap::new(),
+			network_counts: HashMap::new(),
+			additions: 0,
+			user_window: TimeList::new(settings.user_expiry),
+			address_window: TimeList::new(settings.address_expiry),
+			trust_address_window: settings.trust_address_expiry.map(TimeList::new),
+			decay: settings.decay_half_life.map(DecayedWeights::new),
+			recent_spam_window: settings.velocity_window.map(TimeList::new),
+			empty_cache: BTreeMap::new(),
+			latest: CoarseSystemTime::from_epoch_hours(0),
+			clamped: 0,
+			distinct_bytes: 0,
+			settings,
+		}
+	}
+
+	pub fn structure(&self) -> TreeStructure {
+		let levels = usize::from(ADDRESS_BITS) + 1;
+		let mut prefixes = vec![0; levels];
+		let mut estimated_bytes = vec![0; levels];
+		let mut children = Vec::with_capacity(self.counts.len());
+
+		// Prefixes are ordered so that each one comes after the prefixes containing it, like a depth-first traversal, so the ones that can still have children form a stack.
+		let mut ancestors: Vec<(&AddressPrefix, usize)> = Vec::new();
+
+		for (prefix, counts) in self.counts.iter() {
+			let bits = usize::from(prefix.bits());
+			prefixes[bits] += 1;
+			estimated_bytes[bits] += (mem::size_of::<AddressPrefix>() + mem::size_of::<PrefixCounts>()) * 3 / 2 + counts.distinct_bytes();
+
+			while let Some(&(ancestor, _)) = ancestors.last() {
+				if ancestor.contains(prefix) {
+					break;
+				}
+
+				ancestors.pop();
+			}
+
+			if let Some(&(_, index)) = ancestors.last() {
+				children[index] += 1;
+			}
+
+			ancestors.push((prefix, children.len()));
+			children.push(0);
+		}
+
+		let mut buckets = [0; 7];
+
+		for count in children {
+			buckets[CHILDREN_BUCKETS.iter().position(|&maximum| count <= maximum).unwrap()] += 1;
+		}
+
+		TreeStructure {
+			prefixes,
+			estimated_bytes,
+			children: buckets,
+		}
+	}
+
+	/// Estimates the memory used by each part of the tree from the sizes of its collections, without going through them.
+	pub fn memory_usage(&self) -> MemoryUsage {
+		// Hash maps use a byte of control information per bucket.
+		let hash_map_bytes = |capacity: usize, entry_bytes: usize| capacity * (entry_bytes + 1);
+
+		MemoryUsage {
+			counts: self.counts.estimated_bytes(),
+			distinct_users: self.distinct_bytes,
+			users:
+				hash_map_bytes(self.users.capacity(), mem::size_of::<(User, UserLimit)>() + mem::size_of::<u16>())
+				+ hash_map_bytes(self.user_prefixes.capacity(), mem::size_of::<(User, AddressPrefix)>() + mem::size_of::<u16>()),
+			user_window: self.user_window.allocated_bytes(),
+			address_window: self.address_window.allocated_bytes() + self.trust_address_window.as_ref().map_or(0, TimeList::allocated_bytes),
+			velocity_window: self.recent_spam_window.as_ref().map_or(0, TimeList::allocated_bytes),
+			decay: self.decay.as_ref().map_or(0, DecayedWeights::estimated_bytes),
+			groups:
+				hash_map_bytes(self.asn_counts.capacity(), mem::size_of::<u32>() + mem::size_of::<SpamStats>())
+				+ hash_map_bytes(self.country_counts.capacity(), mem::size_of::<[u8; 2]>() + mem::size_of::<SpamStats>())
+				+ hash_map_bytes(self.network_counts.capacity(), mem::size_of::<u64>() + mem::size_of::<SpamStats>()),
+			empty_cache: estimated_btree_bytes(&self.empty_cache),
+		}
+	}
+
+	pub fn size(&self) -> TreeSize {
+		TreeSize {
+			prefixes: self.counts.len(),
+			users: self.users.len(),
+			user_window_entries: self.user_window.len(),
+			address_window_entries: self.address_window.len() + self.trust_address_window.as_ref().map_or(0, TimeList::len),
+			estimated_bytes: mem::size_of::<Self>() + self.memory_usage().parts().iter().map(|&(_, bytes)| bytes).sum::<usize>(),
+		}
+	}
+
+	pub fn settings(&self) -> &TreeSettings {
+		&self.settings
+	}
+
+	/// Changes the number of distinct users a prefix needs for queries to use it. Only has an effect if distinct users are tracked.
+	pub fn set_min_distinct_users(&mut self, minimum: Option<u32>) {
+		self.settings.min_distinct_users = minimum;
+	}
+
+	/// Changes the number of distinct users that have to report a prefix before queries count the reports. Only has an effect if the tree was made with a quarantine, because that’s what tracks the reporters.
+	pub fn set_spam_quarantine(&mut self, minimum: u32) {
+		if self.settings.spam_quarantine.is_some() {
+			self.settings.spam_quarantine = Some(minimum);
+		}
+	}
+
+	pub fn window_stats(&self) -> WindowStats {
+		let address_window = WindowSize::of(&self.address_window);
+		let trust_address_window = self.trust_address_window.as_ref().map_or(WindowSize::default(), WindowSize::of);
+
+		WindowStats {
+			user_window: WindowSize::of(&self.user_window),
+			address_window: WindowSize {
+				entries: address_window.entries + trust_address_window.entries,
+				bytes: address_window.bytes + trust_address_window.bytes,
+				max_entries: address_window.max_entries + trust_address_window.max_entries,
+			},
+			velocity_window: self.recent_spam_window.as_ref().map_or(WindowSize::default(), WindowSize::of),
+			clamped_times: self.clamped + self.user_window.clamped() + self.recent_spam_window.as_ref().map_or(0, TimeList::clamped),
+		}
+	}
+
+	pub fn overrides(&self) -> &Overrides {
+		&self.overrides
+	}
+
+	pub fn overrides_mut(&mut self) -> &mut Overrides {
+		&mut self.overrides
+	}
+
+	/// Gets the fixed result for an address with an override or in the allowlist or denylist, in that order.
+	fn query_lists(&self, address: &Address) -> Option<QueryResult> {
+		let (stats, prefix, verdict) =
+			if let Some((prefix, verdict)) = self.overrides.find(address) {
+				let stats =
+					match verdict {
+						Verdict::Trusted => SpamStats { trusted_users: u32::max_value(), ..SpamStats::EMPTY },
+						Verdict::Spam => SpamStats { spam_users: u32::max_value(), ..SpamStats::EMPTY },
+						Verdict::Neutral => SpamStats::EMPTY,
+					};
+
+				(stats, prefix, verdict)
+			} else if let Some(prefix) = self.settings.allowlist.find(address) {
+				(SpamStats { trusted_users: u32::max_value(), ..SpamStats::EMPTY }, prefix, Verdict::Trusted)
+			} else if let Some(prefix) = self.settings.denylist.find(address) {
+				(SpamStats { spam_users: u32::max_value(), ..SpamStats::EMPTY }, prefix, Verdict::Spam)
+			} else {
+				return None;
+			};
+
+		Some(QueryResult {
+			stats,
+			prefix_bits: prefix.bits(),
+			verdict: Some(verdict),
+		})
+	}
+
+	/// Queries the lists, then the counts for the longest prefix of an address with entries, then its autonomous system’s counts if they’re more specific.
+	pub fn query_stale(&self, address: &Address) -> QueryResult {
+		self.query_lists(address).unwrap_or_else(|| {
+			let result = self.query_counts(address);
+			self.query_asn(address, &result).unwrap_or(result)
+		})
+	}
+
+	/// Gets the counts for an address’s autonomous system, with the size of its network, if the network is longer than the prefix of a result from the counts, i.e. the prefix data is sparser than the AS’s.
+	fn query_asn(&self, address: &Address, result: &QueryResult) -> Option<QueryResult> {
+		// Distinct users aren’t tracked per AS, so there’s no telling whether its counts are safe to reveal, or come from enough reporters to trust.
+		if self.settings.min_distinct_users.is_some() || self.settings.spam_quarantine.is_some() {
+			return None;
+		}
+
+		let (asn, bits) = self.settings.asn(address)?;
+
+		if bits <= result.prefix_bits {
+			return None;
+		}
+
+		let stats = self.asn_counts.get(&asn)?;
+
+		Some(QueryResult {
+			stats: stats.clone(),
+			prefix_bits: bits,
+			verdict: Some(stats.verdict()),
+		})
+	}
+
+	/// Checks whether a prefix’s entries come from enough distinct users that its counts don’t reveal what any one of them reported.
+	fn has_enough_users(&self, counts: &PrefixCounts) -> bool {
+		match (self.settings.min_distinct_users, &counts.users) {
+			(Some(minimum), Some(users)) => users.count() >= minimum,
+			_ => true,
+		}
+	}
+
+	/// Checks whether a prefix’s reports other than trust come from too few distinct users to count yet.
+	fn is_quarantined(&self, counts: &PrefixCounts) -> bool {
+		match (self.settings.spam_quarantine, &counts.spam_reporters) {
+			(Some(minimum), Some(spam_reporters)) => spam_reporters.count() < minimum,
+			_ => false,
+		}
+	}
+
+	/// Finds the longest prefix of an address with entries, from enough distinct users if that’s required. Reports other than trust are left out while the prefix is quarantined.
+	fn query_counts(&self, address: &Address) -> QueryResult {
+		self.walk_counts(address).0
+	}
+
+	/// Finds the longest prefix of an address with entries like `query_counts`, along with the number of lookups in the counts it took, which is how deep the query went.
+	fn walk_counts(&self, address: &Address) -> (QueryResult, usize) {
+		let mut prefix = address.prefix(ADDRESS_BITS);
+		let minimum = self.settings.prefix_bits_minimum(address);
+		let mut lookups = 0;
+
+		loop {
+			lookups += 1;
+
+			let (key, value) =
+				match self.counts.last_at_or_before(&prefix) {
+					Some(pair) => pair,
+					None => break,
+				};
+
+			// IPv6 prefixes can be shorter than the IPv4 minimum and still contain IPv4 addresses, but don’t count for them.
+			if key.bits() <= prefix.bits() && key.bits() >= minimum && key.is_prefix_of(&address) && self.has_enough_users(value) {
+				let stats =
+					if self.is_quarantined(value) {
+						SpamStats { trusted_users: value.stats.trusted_users, automated_trusted_users: value.stats.automated_trusted_users, ..SpamStats::EMPTY }
+					} else {
+						value.stats.clone()
+					};
+
+				let result = QueryResult {
+					verdict: Some(stats.verdict()),
+					stats,
+					prefix_bits: key.bits(),
+				};
+
+				return (result, lookups);
+			}
+
+			// Nothing sorts between the key and the prefix, so the next prefix that can have entries for the address is the longest one containing both, or the one containing the key if that’s the key.
+			prefix =
+				match AddressPrefix::common_prefix(key, &prefix) {
+					common if common == *key =>
+						match key.supernet() {
+							Some(supernet) => supernet,
+							None => break,
+						},
+					common => common,
+				};
+
+			if prefix.bits() < minimum {
+				break;
+			}
+		}
+
+		(QueryResult::EMPTY, lookups)
+	}
+
+	/// Gets the size of the prefix the counts for an address come from and the number of lookups it took to find it, to tell which addresses make queries slow.
+	pub fn query_depth(&self, address: &Address) -> (u8, usize) {
+		let (result, lookups) = self.walk_counts(address);
+		(result.prefix_bits, lookups)
+	}
+
+	/// Finds the longest prefix of an address with entries like `query_counts`, remembering IPv6 /64s without any for a while if configured to, since most queries are for addresses nothing is known about.
+	fn query_counts_cached(&mut self, address: &Address) -> QueryResult {
+		// Addresses in a /64 without entries share all of their prefixes with entries, so they all get the same empty result.
+		let ttl =
+			match self.settings.empty_cache_ttl {
+				Some(ttl) if !address.is_ipv4() && self.settings.prefix_bits_minimum <= 64 => ttl,
+				_ => return self.query_counts(address),
+			};
+
+		let key = network_key(address);
+		let now = Instant::now();
+
+		match self.empty_cache.get(&key) {
+			Some(&expiry) if expiry > now => return QueryResult::EMPTY,
+			_ => {}
+		}
+
+		let result = self.query_counts(address);
+
+		if result.prefix_bits == 0 && !self.counts.contains_key(&address.prefix(64)) {
+			if self.empty_cache.len() >= EMPTY_CACHE_LIMIT {
+				self.empty_cache.clear();
+			}
+
+			self.empty_cache.insert(key, now + ttl);
+		}
+
+		result
+	}
+
+	/// Forgets the empty results for /64s that share a prefix with entries with an address.
+	fn invalidate_empty_cache(&mut self, address: &Address, levels: PrefixLevels) {
+		if self.empty_cache.is_empty() {
+			return;
+		}
+
+		let span = u64::max_value().checked_shr(u32::from(levels.minimum)).unwrap_or(0);
+		let first = network_key(address) & !span;
+		let mut invalidated = self.empty_cache.split_off(&first);
+
+		if let Some(after) = (first | span).checked_add(1) {
+			self.empty_cache.append(&mut invalidated.split_off(&after));
+		}
+	}
+
+	/// Gets the later of a time and the latest time the tree has been used at, and makes it the latest.
+	fn clock(&mut self, now: CoarseSystemTime) -> CoarseSystemTime {
+		self.latest = self.latest.max(now);
+		self.latest
+	}
+
+	/// Expires old entries, up to `EXPIRY_BUDGET` from each window, and returns whether that was all of them.
+	fn advance(&mut self, now: CoarseSystemTime) -> bool {
+		let now = self.clock(now);
+		let mut expired = [0; 4];
+
+		if let Some(recent_spam_window) = &mut self.recent_spam_window {
+			for ((address, counted), _) in recent_spam_window.trim(velocity_time(now)).take(EXPIRY_BUDGET) {
+				expired[0] += 1;
+				Self::remove_recent_spam(&mut self.counts, &address, counted);
+			}
+		}
+
+		for ((Operation(type_, address, user), counted), time) in self.user_window.trim(now).take(EXPIRY_BUDGET) {
+			expired[1] += 1;
+			Self::decrement(&mut self.users, (user, self.settings.user_limit(type_).0));
+
+			if self.settings.entries_per_user_prefix.is_some() {
+				Self::decrement(&mut self.user_prefixes, (user, self.settings.user_prefix(&address)));
+			}
+
+			if self.settings.distinct_users || self.settings.spam_quarantine.is_some() {
+				Self::remove_distinct(&mut self.counts, &address, counted, user, type_);
+			}
+
+			match &mut self.trust_address_window {
+				Some(trust_address_window) if type_.is_trust() => trust_address_window.push(AddressOperation(type_, address, counted), time),
+				_ => self.address_window.push(AddressOperation(type_, address, counted), time),
+			}
+		}
+
+		let trimmed =
+			self.address_window.trim(now).take(EXPIRY_BUDGET).map(|entry| (2, entry))
+				.chain(self.trust_address_window.iter_mut().flat_map(|window| window.trim(now).take(EXPIRY_BUDGET)).map(|entry| (3, entry)));
+
+		for (window, (AddressOperation(type_, address, counted), time)) in trimmed {
+			expired[window] += 1;
+			Self::remove_from_network(&mut self.network_counts, &self.settings, &address, type_);
+
+			// Before the counts, which can lose prefixes the weights still have.
+			if let Some(decay) = &mut self.decay {
+				let counts = &self.counts;
+				decay.remove(&address, counted.levels, type_, time, |prefix| counts.get(prefix).map_or(false, |prefix_counts| counted.includes(prefix_counts)));
+			}
+
+			self.distinct_bytes -= Self::unapply(&mut self.counts, &address, counted, type_);
+			Self::unapply_group(&mut self.asn_counts, self.settings.asn(&address).map(|(asn, _)| asn), type_);
+			Self::unapply_group(&mut self.country_counts, self.settings.country(&address), type_);
+
+			if let Some(threshold) = self.settings.split_threshold {
+				self.distinct_bytes -= Self::merge_sparse(&mut self.counts, &mut self.decay, &address, counted.levels, threshold);
+			}
+		}
+
+		expired.iter().all(|&count| count < EXPIRY_BUDGET)
+	}
+
+	/// Queries like `query_stale`, after expiring old entries, with empty results cached if configured.
+	pub fn query(&mut self, address: &Address, now: CoarseSystemTime) -> QueryResult {
+		self.advance(now);
+
+		match self.query_lists(address) {
+			Some(result) => result,
+			None => {
+				let result = self.query_counts_cached(address);
+				self.query_asn(address, &result).unwrap_or(result)
+			}
+		}
+	}
+
+	/// Expires some old entries, which otherwise only happens when the tree is next used, and returns whether that was all of them.
+	pub fn expire(&mut self, now: CoarseSystemTime) -> bool {
+		self.advance(now)
+	}
+
+	/// Gets the IPv6 /64s and IPv4 /24s with the most entries, most first, to see where reports are concentrated.
+	pub fn top_networks(&self, count: usize) -> Vec<(AddressPrefix, SpamStats)> {
+		let mut top = BinaryHeap::with_capacity(count + 1);
+
+		for (prefix, counts) in self.counts.iter() {
+			let network_bits = if prefix.first().is_ipv4() { IPV4_OFFSET_BITS + IPV4_NETWORK_BITS } else { NETWORK_BITS };
+
+			if prefix.bits() == network_bits {
+				top.push(Reverse((counts.stats.total(), prefix)));
+
+				if top.len() > count {
+					top.pop();
+				}
+			}
+		}
+
+		top.into_sorted_vec().into_iter()
+			.map(|Reverse((_, prefix))| (prefix.clone(), self.counts[prefix].stats.clone()))
+			.collect()
+	}
+
+	/// Makes an immutable snapshot of what queries need, after expiring old entries. The counts are shared rather than copied until the tree next changes.
+	pub fn snapshot(&mut self, now: CoarseSystemTime) -> Snapshot {
+		self.advance(now);
+
+		Snapshot(Self {
+			settings: self.settings.clone(),
+			overrides: self.overrides.clone(),
+			users: HashMap::new(),
+			user_prefixes: HashMap::new(),
+			counts: self.counts.clone(),
+			asn_counts: self.asn_counts.clone(),
+			country_counts: HashMap::new(),
+			network_counts: HashMap::new(),
+			additions: self.additions,
+			user_window: TimeList::new(self.settings.user_expiry),
+			address_window: TimeList::new(self.settings.address_expiry),
+			trust_address_window: None,
+			decay: None,
+			recent_spam_window: None,
+			empty_cache: BTreeMap::new(),
+			latest: self.latest,
+			clamped: self.clamped,
+			distinct_bytes: self.distinct_bytes,
+		})
+	}
+
+	/// Queries the counts for the longest prefix of an address with entries, along with when it was first and most recently reported.
+	pub fn query_seen(&mut self, address: &Address, now: CoarseSystemTime) -> SeenResult {
+		self.advance(now);
+
+		if let Some(QueryResult { stats, prefix_bits, .. }) = self.query_lists(address) {
+			return SeenResult {
+				stats,
+				prefix_bits,
+				seen: None,
+			};
+		}
+
+		let QueryResult { stats, prefix_bits, .. } = self.query_counts(address);
+
+		SeenResult {
+			seen: self.counts.get(&address.prefix(prefix_bits)).map(|counts| (counts.created, counts.updated)),
+			stats,
+			prefix_bits,
+		}
+	}
+
+	/// Queries the counts for the longest prefix of an address with entries, along with the number of distinct users behind them.
+	pub fn query_distinct(&mut self, address: &Address, now: CoarseSystemTime) -> DistinctResult {
+		self.advance(now);
+
+		if let Some(QueryResult { stats, prefix_bits, .. }) = self.query_lists(address) {
+			return DistinctResult {
+				stats,
+				prefix_bits,
+				users: None,
+			};
+		}
+
+		let QueryResult { stats, prefix_bits, .. } = self.query_counts(address);
+
+		DistinctResult {
+			users:
+				self.counts.get(&address.prefix(prefix_bits))
+					.and_then(|counts| counts.users.as_ref())
+					.map(DistinctUsers::count),
+			stats,
+			prefix_bits,
+		}
+	}
+
+	/// Queries the counts for the longest prefix of an address with entries, along with its number of recent spam entries.
+	pub fn query_velocity(&mut self, address: &Address, now: CoarseSystemTime) -> VelocityResult {
+		self.advance(now);
+
+		if let Some(QueryResult { stats, prefix_bits, .. }) = self.query_lists(address) {
+			return VelocityResult {
+				stats,
+				prefix_bits,
+				recent_spam: None,
+			};
+		}
+
+		let QueryResult { stats, prefix_bits, .. } = self.query_counts(address);
+
+		VelocityResult {
+			recent_spam:
+				match (&self.recent_spam_window, self.counts.get(&address.prefix(prefix_bits))) {
+					(Some(_), Some(counts)) => Some(if self.is_quarantined(counts) { 0 } else { counts.recent_spam }),
+					_ => None,
+				},
+			stats,
+			prefix_bits,
+		}
+	}
+
+	/// Finds the two-letter code of an address’s country, if there’s a country database and the address is in it.
+	pub fn country(&self, address: &Address) -> Option<[u8; 2]> {
+		self.settings.country(address)
+	}
+
+	/// Truncates an address to the size addresses are stored as, if they’re truncated, so full addresses are never logged.
+	pub fn truncate(&self, address: Address) -> Address {
+		self.settings.truncate(address)
+	}
+
+	/// Gets the counts for each country, ordered by country code, if there’s a country database.
+	pub fn country_counts(&self) -> Option<Vec<([u8; 2], SpamStats)>> {
+		self.settings.country_database.as_ref()?;
+
+		let mut country_counts: Vec<_> = self.country_counts.iter().map(|(&country, stats)| (country, stats.clone())).collect();
+		country_counts.sort_by_key(|&(country, _)| country);
+		Some(country_counts)
+	}
+
+	/// Queries the weights of entries for the longest prefix of an address with entries, which are just the counts unless weights decay.
+	pub fn query_weights(&mut self, address: &Address, now: CoarseSystemTime) -> WeightedResult {
+		self.advance(now);
+
+		if let Some(QueryResult { stats, prefix_bits, .. }) = self.query_lists(address) {
+			return WeightedResult {
+				weights: WeightStats::from_counts(&stats),
+				prefix_bits,
+			};
+		}
+
+		let QueryResult { stats, prefix_bits, .. } = self.query_counts(address);
+
+		let prefix = address.prefix(prefix_bits);
+
+		let weights =
+			match &self.decay {
+				Some(decay) if self.counts.get(&prefix).map_or(false, |counts| self.is_quarantined(counts)) => decay.get(&prefix, now).trusted_only(),
+				Some(decay) => decay.get(&prefix, now),
+				None => WeightStats::from_counts(&stats),
+			};
+
+		WeightedResult {
+			weights,
+			prefix_bits,
+		}
+	}
+
+	fn try_increment(&mut self, user: User, type_: OperationType, address: &Address) -> Option<()> {
+		// Limit the number of entries of any type stored for one user within one prefix.
+		let user_prefix =
+			match self.settings.entries_per_user_prefix {
+				Some(prefix_limit) => {
+					let key = (user, self.settings.user_prefix(address));
+
+					if self.user_prefixes.get(&key).map_or(0, |&count| count) >= prefix_limit {
+						return None;
+					}
+
+					Some(key)
+				}
+				None => None,
+			};
+
+		// Limit the number of entries stored for one user.
+		let (user_limit, limit) = self.settings.user_limit(type_);
+
+		match self.users.entry((user, user_limit)) {
+			hash_map::Entry::Occupied(entry) => {
+				let count = entry.into_mut();
+
+				if *count >= limit {
+					return None;
+				}
+
+				*count += 1;
+			}
+			hash_map::Entry::Vacant(_) if limit == 0 => return None,
+			hash_map::Entry::Vacant(entry) => {
+				entry.insert(1);
+			}
+		}
+
+		if let Some(key) = user_prefix {
+			*self.user_prefixes.entry(key).or_insert(0) += 1;
+		}
+
+		Some(())
+	}
+
+	/// Removes an entry from a user’s count, the reverse of `try_increment`.
+	fn decrement<K: Eq + Hash>(users: &mut HashMap<K, u16>, key: K) {
+		let entry = match users.entry(key) {
+			hash_map::Entry::Occupied(o) => o,
+			hash_map::Entry::Vacant(_) => panic!("User unexpectedly missing from map"),
+		};
+
+		if *entry.get() > 1 {
+			*entry.into_mut() -= 1;
+		} else {
+			entry.remove();
+		}
+	}
+
+	/// Updates the entries for each prefix of an address with a size in `levels`.
+	fn apply(counts: &mut CowMap<AddressPrefix, PrefixCounts>, address: &Address, levels: PrefixLevels, entry_update: impl Fn(cow_map::Entry<AddressPrefix, PrefixCounts>) -> ()) {
+		let mut prefix = address.prefix(ADDRESS_BITS);
+
+		for bits in levels.sizes().rev() {
+			prefix.shorten_to(bits);
+			entry_update(counts.entry(prefix.clone()));
+		}
+	}
+
+	/// Removes an entry from the counts of the group it belongs to, like its autonomous system or country, if it’s in one.
+	fn unapply_group<K: Eq + Hash>(group_counts: &mut HashMap<K, SpamStats>, key: Option<K>, type_: OperationType) {
+		let key =
+			match key {
+				Some(key) => key,
+				None => return,
+			};
+
+		if let hash_map::Entry::Occupied(mut entry) = group_counts.entry(key) {
+			let users = entry.get_mut().users_mut(type_);
+			*users = users.saturating_sub(1);
+
+			if *entry.get() == SpamStats::EMPTY {
+				entry.remove();
+			}
+		}
+	}
+
+	/// Removes an entry from its /64’s counts, if they’re kept.
+	fn remove_from_network(network_counts: &mut HashMap<u64, SpamStats>, settings: &TreeSettings, address: &Address, type_: OperationType) {
+		if let (Some(_), false) = (settings.network_cap, address.is_ipv4()) {
+			if let hash_map::Entry::Occupied(mut entry) = network_counts.entry(network_key(address)) {
+				let users = entry.get_mut().users_mut(type_);
+				*users = users.saturating_sub(1);
+
+				if *entry.get() == SpamStats::EMPTY {
+					entry.remove();
+				}
+			}
+		}
+	}
+
+	/// Updates the counts of each prefix an entry was counted for that’s still there.
+	fn unapply_counted(counts: &mut CowMap<AddressPrefix, PrefixCounts>, address: &Address, counted: Counted, update: impl Fn(cow_map::OccupiedEntry<AddressPrefix, PrefixCounts>)) {
+		Self::apply(counts, address, counted.levels, |entry| {
+			match entry {
+				cow_map::Entry::Occupied(entry) if counted.includes(entry.get()) => update(entry),
+				// Pruned or merged into a shorter prefix since, and maybe made again by later entries.
+				_ => {}
+			}
+		});
+	}
+
+	/// Removes a spam entry that’s no longer recent from each prefix it was counted for.
+	fn remove_recent_spam(counts: &mut CowMap<AddressPrefix, PrefixCounts>, address: &Address, counted: Counted) {
+		Self::unapply_counted(counts, address, counted, |mut entry| {
+			entry.get_mut().recent_spam -= 1;
+		});
+	}
+
+	/// Removes one of a user’s entries from the distinct users, and spam reporters if it isn’t trust, of each prefix it was counted for.
+	fn remove_distinct(counts: &mut CowMap<AddressPrefix, PrefixCounts>, address: &Address, counted: Counted, user: User, type_: OperationType) {
+		Self::unapply_counted(counts, address, counted, |mut entry| {
+			let counts = entry.get_mut();
+
+			if let Some(users) = &mut counts.users {
+				users.remove(user);
+			}
+
+			if let (Some(spam_reporters), false) = (&mut counts.spam_reporters, type_.is_trust()) {
+				spam_reporters.remove(user);
+			}
+		});
+	}
+
+	/// Removes an entry from the counts of each prefix it was counted for, removing prefixes left without any, and returns the memory freed from their distinct users.
+	fn unapply(counts: &mut CowMap<AddressPrefix, PrefixCounts>, address: &Address, counted: Counted, type_: OperationType) -> usize {
+		let freed = Cell::new(0);
+
+		Self::unapply_counted(counts, address, counted, |mut entry| {
+			let users = entry.get_mut().stats.users_mut(type_);
+
+			match users.checked_sub(1) {
+				Some(remaining) => *users = remaining,
+				None => panic!("Entry unexpectedly missing from counts"),
+			}
+
+			if entry.get().stats == SpamStats::EMPTY {
+				freed.set(freed.get() + entry.remove().distinct_bytes());
+			}
+		});
+
+		freed.get()
+	}
+
+	/// Finds the longest prefix size to count a new entry for an address for, when prefixes are only split off from ones with at least `threshold` entries.
+	fn split_depth(&self, address: &Address, levels: PrefixLevels, threshold: u32) -> u8 {
+		let mut depth = levels.minimum;
+
+		for bits in levels.sizes() {
+			depth = bits;
+
+			// Including the new entry.
+			let entries = self.counts.get(&address.prefix(bits)).map_or(0, |counts| counts.stats.total()) + 1;
+
+			if entries < threshold {
+				break;
+			}
+		}
+
+		depth
+	}
+
+	/// Removes the prefixes split off from the longest prefix of an address that has dropped below the split threshold, and returns the memory freed from their distinct users.
+	fn merge_sparse(counts: &mut CowMap<AddressPrefix, PrefixCounts>, decay: &mut Option<DecayedWeights>, address: &Address, levels: PrefixLevels, threshold: u32) -> usize {
+		let mut freed = 0;
+
+		for bits in levels.sizes() {
+			let prefix = address.prefix(bits);
+
+			if counts.get(&prefix).map_or(0, |counts| counts.stats.total()) < threshold {
+				// Prefixes sort right after the prefixes containing them.
+				let split: Vec<AddressPrefix> =
+					counts.iter_after(&prefix)
+						.map(|(key, _)| key)
+						.take_while(|key| prefix.contains(key))
+						.cloned()
+						.collect();
+
+				for key in split {
+					freed += counts.remove(&key).map_or(0, |removed| removed.distinct_bytes());
+
+					if let Some(decay) = decay {
+						decay.prune(&key);
+					}
+				}
+
+				break;
+			}
+		}
+
+		freed
+	}
+
+	/// Removes the prefixes least recently added to until nine tenths of `max_prefixes` remain. Adding to a prefix adds to all of its shorter prefixes, so those are never removed first, and addresses that have been pruned still get results from their aggregated shorter prefixes. Entries counted for a pruned prefix aren’t removed from it if it’s made again later, since it doesn’t have them anymore.
+	fn prune(&mut self, max_prefixes: usize) {
+		let target = max_prefixes - max_prefixes / 10;
+
+		let mut candidates: Vec<_> =
+			self.counts.iter()
+				.map(|(prefix, counts)| (counts.updated, Reverse(prefix.bits()), prefix.clone()))
+				.collect();
+
+		candidates.sort_unstable();
+
+		for (_, _, prefix) in candidates.into_iter().take(self.counts.len() - target) {
+			self.distinct_bytes -= self.counts.remove(&prefix).map_or(0, |removed| removed.distinct_bytes());
+
+			if let Some(decay) = &mut self.decay {
+				decay.prune(&prefix);
+			}
+		}
+	}
+
+	/// Records an operation, returning whether it was accepted. Operations from users that have reached their entry limit are ignored.
+	pub fn perform(&mut self, operation: Operation, time: CoarseSystemTime) -> bool {
+		let now = self.clock(time);
+		self.advance(now);
+
+		let Operation(type_, ref address, user) = operation;
+
+		// Allowlisted addresses can only be reported as trusted.
+		if !type_.is_trust() && self.settings.allowlist.find(address).is_some() {
+			return false;
+		}
+
+		if self.try_increment(user, type_, address).is_none() {
+			return false;
+		}
+
+		if now != time {
+			self.clamped += 1;
+		}
+
+		let counted = self.add(type_, address, Some(user), now);
+		self.user_window.push((operation, counted), now);
+		true
+	}
+
+	/// Adds an entry to the counts of every prefix of an address, without putting it in either window, and returns the prefixes it was counted for. The user is counted toward the prefixes’ distinct users if there is one.
+	fn add(&mut self, type_: OperationType, address: &Address, user: Option<User>, now: CoarseSystemTime) -> Counted {
+		let distinct_users = self.settings.distinct_users;
+		let spam_quarantine = self.settings.spam_quarantine.is_some();
+		let mut levels = self.settings.prefix_levels(address);
+
+		if let Some(threshold) = self.settings.split_threshold {
+			levels.maximum = self.split_depth(address, levels, threshold);
+		}
+
+		if let (Some(cap), false) = (self.settings.network_cap, address.is_ipv4()) {
+			let users = self.network_counts.entry(network_key(address)).or_insert(SpamStats::EMPTY).users_mut(type_);
+			*users += 1;
+
+			if *users > cap {
+				levels.minimum = levels.minimum.max(NETWORK_BITS);
+			}
+		}
+
+		self.invalidate_empty_cache(address, levels);
+
+		let recent = type_ == OperationType::Spam && self.recent_spam_window.is_some();
+		let distinct_grown = Cell::new(0);
+		let counted = Counted { levels, addition: self.additions };
+		self.additions += 1;
+
+		Self::apply(&mut self.counts, address, levels, |entry| {
+			let counts = entry.or_insert_with(|| PrefixCounts {
+				stats: SpamStats::EMPTY,
+				created: now,
+				updated: now,
+				users: if distinct_users { Some(DistinctUsers::new()) } else { None },
+				recent_spam: 0,
+				spam_reporters: if spam_quarantine { Some(DistinctUsers::new()) } else { None },
+				made_by: counted.addition,
+			});
+
+			*counts.stats.users_mut(type_) += 1;
+			counts.updated = now;
+
+			if recent {
+				counts.recent_spam += 1;
+			}
+
+			let distinct_bytes = counts.distinct_bytes();
+
+			if let (Some(users), Some(user)) = (&mut counts.users, user) {
+				users.add(user);
+			}
+
+			if let (Some(spam_reporters), Some(user), false) = (&mut counts.spam_reporters, user, type_.is_trust()) {
+				spam_reporters.add(user);
+			}
+
+			distinct_grown.set(distinct_grown.get() + counts.distinct_bytes() - distinct_bytes);
+		});
+
+		self.distinct_bytes += distinct_grown.get();
+
+		if let Some(decay) = &mut self.decay {
+			decay.add(address, levels, type_, now);
+		}
+
+		if let (true, Some(recent_spam_window)) = (recent, &mut self.recent_spam_window) {
+			recent_spam_window.push((address.clone(), counted), velocity_time(now));
+		}
+
+		if let Some((asn, _)) = self.settings.asn(address) {
+			*self.asn_counts.entry(asn).or_insert(SpamStats::EMPTY).users_mut(type_) += 1;
+		}
+
+		if let Some(country) = self.settings.country(address) {
+			*self.country_counts.entry(country).or_insert(SpamStats::EMPTY).users_mut(type_) += 1;
+		}
+
+		match self.settings.max_prefixes {
+			Some(max_prefixes) if self.counts.len() > max_prefixes => self.prune(max_prefixes),
+			_ => {}
+		}
+
+		counted
+	}
+
+	/// Removes reports that still count toward their users, returning whether there were any. Reports that only count toward their addresses anymore can’t be attributed to users, so they stay until they expire.
+	pub fn retract(&mut self, retraction: &Retraction, now: CoarseSystemTime) -> bool {
+		self.advance(now);
+
+		let removed: Vec<_> =
+			match retraction {
+				Retraction::Report(type_, address, user) => {
+					let found =
+						self.user_window.iter()
+							.enumerate()
+							.filter(|(_, ((Operation(t, a, u), _), _))| t == type_ && a == address && u == user)
+							.map(|(index, _)| index)
+							.last();
+
+					found.and_then(|index| self.user_window.remove(index)).into_iter().collect()
+				}
+				Retraction::User(user) => self.user_window.remove_where(|(Operation(_, _, u), _), _| u == user),
+			};
+
+		let retracted = !removed.is_empty();
+
+		for ((Operation(type_, address, user), counted), time) in removed {
+			self.remove(type_, &address, user, counted, time);
+		}
+
+		retracted
+	}
+
+	/// Removes an entry that has been taken out of the user window from its user’s count and its addresses.
+	fn remove(&mut self, type_: OperationType, address: &Address, user: User, counted: Counted, time: CoarseSystemTime) {
+		Self::remove_from_network(&mut self.network_counts, &self.settings, address, type_);
+		Self::decrement(&mut self.users, (user, self.settings.user_limit(type_).0));
+
+		if self.settings.entries_per_user_prefix.is_some() {
+			Self::decrement(&mut self.user_prefixes, (user, self.settings.user_prefix(address)));
+		}
+
+		if self.settings.distinct_users || self.settings.spam_quarantine.is_some() {
+			Self::remove_distinct(&mut self.counts, address, counted, user, type_);
+		}
+
+		if let (OperationType::Spam, Some(recent_spam_window)) = (type_, &mut self.recent_spam_window) {
+			let found = recent_spam_window.iter().position(|((_, recent), _)| recent.addition == counted.addition);
+
+			if found.and_then(|index| recent_spam_window.remove(index)).is_some() {
+				Self::remove_recent_spam(&mut self.counts, address, counted);
+			}
+		}
+
+		// Before the counts, which can lose prefixes the weights still have.
+		if let Some(decay) = &mut self.decay {
+			let counts = &self.counts;
+			decay.remove(address, counted.levels, type_, time, |prefix| counts.get(prefix).map_or(false, |prefix_counts| counted.includes(prefix_counts)));
+		}
+
+		self.distinct_bytes -= Self::unapply(&mut self.counts, address, counted, type_);
+		Self::unapply_group(&mut self.asn_counts, self.settings.asn(address).map(|(asn, _)| asn), type_);
+		Self::unapply_group(&mut self.country_counts, self.settings.country(address), type_);
+
+		if let Some(threshold) = self.settings.split_threshold {
+			self.distinct_bytes -= Self::merge_sparse(&mut self.counts, &mut self.decay, address, counted.levels, threshold);
+		}
+	}
+
+	/// Lists the entries still counting toward a user, in order of time.
+	pub fn user_entries(&mut self, user: User, now: CoarseSystemTime) -> Vec<Entry> {
+		self.advance(now);
+
+		self.user_window.iter()
+			.filter(|((Operation(_, _, u), _), _)| *u == user)
+			.map(|((Operation(type_, address, user), _), time)| Entry { time, type_: *type_, address: address.clone(), user: Some(*user) })
+			.collect()
+	}
+
+	/// Lists every entry, in order of time, then type, address, and user.
+	fn entries(&self) -> Vec<Entry> {
+		let user_entries =
+			self.user_window.iter()
+				.map(|((Operation(type_, address, user), _), time)| Entry { time, type_: *type_, address: address.clone(), user: Some(*user) });
+
+		let address_entries =
+			self.address_window.iter()
+				.chain(self.trust_address_window.iter().flat_map(TimeList::iter))
+				.map(|(AddressOperation(type_, address, _), time)| Entry { time, type_: *type_, address: address.clone(), user: None });
+
+		let mut result: Vec<Entry> = user_entries.chain(address_entries).collect();
+		result.sort();
+		result
+	}
+
+	/// Compares the entries of two trees as of a time, returning the fewest additions and removals that turn this tree’s entries into the other’s, in order of time.
+	pub fn diff(&mut self, other: &mut SpamTree, now: CoarseSystemTime) -> Vec<Change> {
+		self.advance(now);
+		other.advance(now);
+
+		let mut ours = self.entries().into_iter().peekable();
+		let mut theirs = other.entries().into_iter().peekable();
+		let mut result = Vec::new();
+
+		loop {
+			let change =
+				match (ours.peek(), theirs.peek()) {
+					(Some(a), Some(b)) if a == b => {
+						ours.next();
+						theirs.next();
+						continue;
+					}
+					(Some(a), Some(b)) if a < b => Change::Remove(ours.next().unwrap()),
+					(Some(_), None) => Change::Remove(ours.next().unwrap()),
+					(_, Some(_)) => Change::Add(theirs.next().unwrap()),
+					(None, None) => break,
+				};
+
+			result.push(change);
+		}
+
+		result
+	}
+
+	/// Combines another tree’s entries with this one’s, as if its operations had been performed here too, keeping this tree’s settings and overrides. Operations still counting toward their users’ limits are replayed in order, subject to the limits, except for ones older than the newest operation that doesn’t count anymore: those can’t be added to the user window in order, so they’re only counted for their addresses. Users with the same number in both trees are the same user.
+	pub fn merge(&mut self, mut other: SpamTree) {
+		let mut address_operations: Vec<_> =
+			self.address_window.drain()
+				.chain(other.address_window.drain())
+				.chain(self.trust_address_window.iter_mut().flat_map(TimeList::drain))
+				.chain(other.trust_address_window.iter_mut().flat_map(TimeList::drain))
+				.map(|(AddressOperation(type_, address, _), time)| (type_, address, time))
+				.collect();
+		let mut user_operations: Vec<_> = self.user_window.drain().chain(other.user_window.drain()).map(|((operation, _), time)| (operation, time)).collect();
+		address_operations.sort_by_key(|&(_, _, time)| time);
+		user_operations.sort_by_key(|&(_, time)| time);
+
+		let latest_address_time = address_operations.last().map(|&(_, _, time)| time);
+		let (early, user_operations): (Vec<_>, Vec<_>) =
+			user_operations.into_iter()
+				.partition(|&(_, time)| Some(time) < latest_address_time);
+
+		address_operations.extend(early.into_iter().map(|(Operation(type_, address, _), time)| (type_, address, time)));
+		address_operations.sort_by_key(|&(_, _, time)| time);
+
+		let mut merged = Self::new(self.settings.clone());
+		merged.overrides = mem::replace(&mut self.overrides, Overrides::default());
+
+		for (type_, address, time) in address_operations {
+			let counted = merged.add(type_, &address, None, time);
+
+			match &mut merged.trust_address_window {
+				Some(trust_address_window) if type_.is_trust() => trust_address_window.push(AddressOperation(type_, address, counted), time),
+				_ => merged.address_window.push(AddressOperation(type_, address, counted), time),
+			}
+		}
+
+		for (operation, time) in user_operations {
+			merged.perform(operation, time);
+		}
+
+		merged.latest = merged.latest.max(self.latest).max(other.latest);
+		merged.clamped += self.clamped + other.clamped;
+		*self = merged;
+	}
+}
+
+/// A copy of a tree’s counts, overrides, and lists at some point, which can be queried without changing it, e.g. from other threads while the tree keeps changing.
+#[derive(Debug)]
+pub struct Snapshot(SpamTree);
+
+impl Snapshot {
+	/// Queries the snapshot like `SpamTree::query`, without expiring entries that have gotten old since it was taken.
+	pub fn query(&self, address: &Address) -> QueryResult {
+		self.0.query_stale(address)
+	}
+
+	/// Gets the number of prefixes with entries.
+	pub fn prefix_count(&self) -> usize {
+		self.0.counts.len()
+	}
+
+	/// Iterates over the prefixes with entries and their counts, in order.
+	pub fn prefixes(&self) -> impl Iterator<Item = (&AddressPrefix, &SpamStats)> {
+		self.0.counts.iter().map(|(prefix, counts)| (prefix, &counts.stats))
+	}
+}
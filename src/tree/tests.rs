@@ -0,0 +1,518 @@
+use quickcheck::{Arbitrary, Gen};
+use rand::Rng;
+use rand::seq::SliceRandom;
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::time::Duration;
+
+use super::super::address::{ADDRESS_BITS, ADDRESS_BYTES, Address, AddressPrefix};
+use super::super::overrides::Verdict;
+use super::super::time_list::{CoarseDuration, CoarseSystemTime, Minutes, TimeList};
+use super::{AddressOperation, NETWORK_BITS, Operation, OperationType, QueryResult, Retraction, SpamStats, SpamTree, TreeOperation, TreeSettings, User};
+
+/// The number of different users in a history, few enough that they run into their limits.
+const USERS: u32 = 4;
+
+impl Arbitrary for OperationType {
+	fn arbitrary<G: Gen>(g: &mut G) -> Self {
+		*Self::ALL.choose(g).unwrap()
+	}
+}
+
+/// Operations at times that only move forward, from a few users on a few addresses, so that limits, retractions, and expiry all come into play. The addresses are variations on one, so they share prefixes of any size.
+#[derive(Clone, Debug)]
+pub struct History(pub Vec<(TreeOperation, CoarseSystemTime)>);
+
+impl Arbitrary for History {
+	fn arbitrary<G: Gen>(g: &mut G) -> Self {
+		let base: Address = Arbitrary::arbitrary(g);
+
+		let addresses: Vec<Address> =
+			(0..g.gen_range(1, 5))
+				.map(|_| {
+					let mut bytes = base.0;
+					let shared = g.gen_range(0, ADDRESS_BYTES + 1);
+					g.fill(&mut bytes[shared..]);
+					Address(bytes)
+				})
+				.collect();
+
+		// Around 2020, within a few weeks of each other, since windows can’t hold entries 2^16 hours apart.
+		let mut now = CoarseSystemTime::from_epoch_hours(g.gen_range(438000, 439000));
+		let size = g.size();
+		let mut result = Vec::new();
+
+		for _ in 0..g.gen_range(0, size) {
+			let type_ = Arbitrary::arbitrary(g);
+			let address = addresses.choose(g).unwrap().clone();
+			let user = User(g.gen_range(0, USERS));
+
+			let operation =
+				match g.gen_range(0, 8) {
+					0 => TreeOperation::Retract(Retraction::User(user)),
+					1 => TreeOperation::Retract(Retraction::Report(type_, address, user)),
+					_ => TreeOperation::Perform(Operation(type_, address, user)),
+				};
+
+			result.push((operation, now));
+
+			// Long enough gaps that entries sometimes stop counting toward their users.
+			now += CoarseDuration::new(g.gen_range(0, 200));
+		}
+
+		Self(result)
+	}
+}
+
+impl History {
+	/// Gets the time of the last operation, or any time if there are none.
+	pub fn end(&self) -> CoarseSystemTime {
+		self.0.last().map_or(CoarseSystemTime::from_epoch_hours(0), |&(_, time)| time)
+	}
+
+	/// Gets the distinct addresses in the history.
+	fn addresses(&self) -> Vec<Address> {
+		let mut result: Vec<Address> =
+			self.0.iter()
+				.filter_map(|(operation, _)| match operation {
+					TreeOperation::Perform(Operation(_, address, _)) | TreeOperation::Retract(Retraction::Report(_, address, _)) => Some(address.clone()),
+					TreeOperation::Retract(Retraction::User(_)) => None,
+				})
+				.collect();
+
+		result.sort();
+		result.dedup();
+		result
+	}
+
+	/// Makes every user in the history a different one from every user in histories that haven’t been through this.
+	fn with_other_users(self) -> Self {
+		let other = |User(user)| User(user + USERS);
+
+		Self(
+			self.0.into_iter()
+				.map(|(operation, time)| {
+					let operation =
+						match operation {
+							TreeOperation::Perform(Operation(type_, address, user)) => TreeOperation::Perform(Operation(type_, address, other(user))),
+							TreeOperation::Retract(Retraction::Report(type_, address, user)) => TreeOperation::Retract(Retraction::Report(type_, address, other(user))),
+							TreeOperation::Retract(Retraction::User(user)) => TreeOperation::Retract(Retraction::User(other(user))),
+						};
+
+					(operation, time)
+				})
+				.collect()
+		)
+	}
+
+	/// Applies every operation to a tree, returning the ones that changed it, which are the ones the daemon logs.
+	pub fn apply(&self, tree: &mut SpamTree) -> Vec<(TreeOperation, CoarseSystemTime)> {
+		self.0.iter()
+			.filter(|(operation, time)| {
+				match operation {
+					TreeOperation::Perform(operation) => tree.perform(operation.clone(), *time),
+					TreeOperation::Retract(retraction) => tree.retract(retraction, *time),
+				}
+			})
+			.cloned()
+			.collect()
+	}
+}
+
+/// Expires every entry in a tree, which takes until the longest expiry after the last one.
+fn expire_all(tree: &mut SpamTree, last: CoarseSystemTime) {
+	let mut now = last;
+	now += tree.settings.address_expiry.max(tree.settings.trust_address_expiry.unwrap_or(tree.settings.address_expiry));
+	now += CoarseDuration::new(1);
+
+	while !tree.expire(now) {}
+}
+
+/// Counts each prefix’s entries again from the windows, for the prefixes each entry was counted for.
+fn recount(tree: &SpamTree) -> BTreeMap<AddressPrefix, SpamStats> {
+	let entries =
+		tree.user_window.iter()
+			.map(|((Operation(type_, address, _), counted), _)| (type_, address, counted))
+			.chain(
+				tree.address_window.iter().chain(tree.trust_address_window.iter().flat_map(TimeList::iter))
+					.map(|(AddressOperation(type_, address, counted), _)| (type_, address, counted))
+			);
+
+	let mut result = BTreeMap::new();
+
+	for (&type_, address, &counted) in entries {
+		for bits in counted.levels.sizes() {
+			let prefix = address.prefix(bits);
+
+			if tree.counts.get(&prefix).map_or(false, |counts| counted.includes(counts)) {
+				*result.entry(prefix).or_insert(SpamStats::EMPTY).users_mut(type_) += 1;
+			}
+		}
+	}
+
+	result
+}
+
+/// Checks that every prefix counts exactly the entries in the windows that were counted for it.
+fn counts_match_windows(tree: &SpamTree) -> bool {
+	tree.counts.iter().map(|(prefix, counts)| (prefix.clone(), counts.stats.clone())).eq(recount(tree))
+}
+
+/// Checks that two query results are the same.
+fn same_result(a: &QueryResult, b: &QueryResult) -> bool {
+	a.stats == b.stats && a.prefix_bits == b.prefix_bits && a.verdict == b.verdict
+}
+
+/// Checks that two trees have the same entries and counts as of a time.
+pub fn same_entries(a: &mut SpamTree, b: &mut SpamTree, now: CoarseSystemTime) -> bool {
+	let (a_snapshot, b_snapshot) = (a.snapshot(now), b.snapshot(now));
+	a.diff(b, now).is_empty() && a_snapshot.prefixes().eq(b_snapshot.prefixes())
+}
+
+/// Checks that merging trees gives the same result in either order. Users with the same number in both trees are the same user, and which of their simultaneous operations count toward their limit would depend on the order, so the trees have different users.
+#[quickcheck]
+fn merge_is_commutative(a: History, b: History) -> bool {
+	let b = b.with_other_users();
+	let now = a.end().max(b.end());
+
+	let build = |history: &History| {
+		let mut tree = SpamTree::new(TreeSettings::DEFAULT);
+		history.apply(&mut tree);
+		tree
+	};
+
+	let mut a_then_b = build(&a);
+	a_then_b.merge(build(&b));
+
+	let mut b_then_a = build(&b);
+	b_then_a.merge(build(&a));
+
+	same_entries(&mut a_then_b, &mut b_then_a, now)
+}
+
+/// Checks that replaying only the operations that changed a tree, tombstones included, gives the same tree, and that reports from before a user was forgotten don’t come back.
+#[quickcheck]
+fn replay_does_not_resurrect_entries(history: History) -> bool {
+	let now = history.end();
+	let mut live = SpamTree::new(TreeSettings::DEFAULT);
+	let logged = History(history.apply(&mut live));
+
+	let mut replayed = SpamTree::new(TreeSettings::DEFAULT);
+	logged.apply(&mut replayed);
+
+	let mut forgotten = HashMap::new();
+
+	for (operation, time) in &logged.0 {
+		if let TreeOperation::Retract(Retraction::User(user)) = operation {
+			forgotten.insert(*user, *time);
+		}
+	}
+
+	let stayed_forgotten =
+		forgotten.iter().all(|(&user, &time)| {
+			replayed.user_entries(user, now).iter().all(|entry| entry.time >= time)
+		});
+
+	stayed_forgotten && same_entries(&mut live, &mut replayed, now)
+}
+
+/// Checks that no user has more entries counting toward a limit than it allows, whether their types share the combined limit or have their own.
+#[quickcheck]
+fn user_limits_cap_entries(history: History, limit: u8, trust_limit: Option<u8>, spam_limit: Option<u8>) -> bool {
+	let (limit, trust_limit, spam_limit) = (u16::from(limit % 8), trust_limit.map(|l| u16::from(l % 8)), spam_limit.map(|l| u16::from(l % 8)));
+	let now = history.end();
+	let mut tree = SpamTree::new(TreeSettings {
+		entries_per_user: limit,
+		trust_entries_per_user: trust_limit,
+		spam_entries_per_user: spam_limit,
+		..TreeSettings::DEFAULT
+	});
+	history.apply(&mut tree);
+
+	(0..USERS).all(|user| {
+		let entries = tree.user_entries(User(user), now);
+		let count = |trust: bool| entries.iter().filter(|entry| entry.type_.is_trust() == trust).count() as u16;
+		let (trust, spam) = (count(true), count(false));
+
+		match (trust_limit, spam_limit) {
+			(None, None) => trust + spam <= limit,
+			(Some(trust_limit), None) => trust <= trust_limit && spam <= limit,
+			(None, Some(spam_limit)) => trust <= limit && spam <= spam_limit,
+			(Some(trust_limit), Some(spam_limit)) => trust <= trust_limit && spam <= spam_limit,
+		}
+	})
+}
+
+/// Checks that one /64 contributes no more than the cap of each type to shorter prefixes, however many of its addresses are reported.
+#[quickcheck]
+fn network_cap_limits_shorter_prefixes(network: Address, hosts: Vec<(u64, OperationType)>, cap: u8) -> bool {
+	let cap = u32::from(cap % 8);
+	let mut tree = SpamTree::new(TreeSettings { network_cap: Some(cap), ..TreeSettings::DEFAULT });
+	let now = CoarseSystemTime::from_epoch_hours(0);
+
+	// A global unicast network, so it isn’t an IPv4-mapped one, which isn’t capped.
+	let mut bytes = network.0;
+	bytes[0] = 0x20;
+
+	for (user, (host, type_)) in hosts.into_iter().enumerate() {
+		bytes[ADDRESS_BYTES - 8..].copy_from_slice(&host.to_be_bytes());
+		tree.perform(Operation(type_, Address(bytes), User(user as u32)), now);
+	}
+
+	tree.counts.iter()
+		.filter(|(prefix, _)| prefix.bits() < NETWORK_BITS)
+		.all(|(_, counts)| OperationType::ALL.iter().all(|&type_| counts.stats.users(type_) <= cap))
+}
+
+/// Checks that the memory of distinct users, which is kept as prefixes change instead of summed when asked for, matches the prefixes left.
+#[quickcheck]
+fn distinct_bytes_match_prefixes(history: History, quarantine: Option<u8>) -> bool {
+	let mut tree = SpamTree::new(TreeSettings {
+		distinct_users: true,
+		spam_quarantine: quarantine.map(|q| u32::from(q % 4)),
+		..TreeSettings::DEFAULT
+	});
+	history.apply(&mut tree);
+	tree.snapshot(history.end());
+
+	tree.distinct_bytes == tree.counts.iter().map(|(_, counts)| counts.distinct_bytes()).sum::<usize>()
+}
+
+/// Checks that when prefixes are only split off where entries are dense, expiring and retracting entries only removes them from the prefixes they were counted for, and expiring all of them leaves nothing behind.
+#[quickcheck]
+fn split_entries_expire_completely(history: History, threshold: u8, decay: bool) -> bool {
+	let mut tree = SpamTree::new(TreeSettings {
+		split_threshold: Some(1 + u32::from(threshold % 4)),
+		decay_half_life: if decay { Some(CoarseDuration::new(24)) } else { None },
+		..TreeSettings::DEFAULT
+	});
+	history.apply(&mut tree);
+	let counted = counts_match_windows(&tree);
+
+	expire_all(&mut tree, history.end());
+	counted && tree.counts.len() == 0 && tree.decay.as_ref().map_or(true, |decay| decay.estimated_bytes() == 0)
+}
+
+/// Checks that pruning keeps the number of prefixes under the limit without the counts drifting: each prefix counts exactly the entries counted for it since it was last made, so no more than without pruning, and expiring every entry leaves nothing behind.
+#[quickcheck]
+fn pruned_counts_do_not_drift(history: History, max_prefixes: u8) -> bool {
+	let max_prefixes = 10 + usize::from(max_prefixes % 64);
+	let mut pruned = SpamTree::new(TreeSettings { max_prefixes: Some(max_prefixes), ..TreeSettings::DEFAULT });
+	let mut unpruned = SpamTree::new(TreeSettings::DEFAULT);
+	history.apply(&mut pruned);
+	history.apply(&mut unpruned);
+
+	let bounded =
+		pruned.counts.iter().all(|(prefix, counts)| {
+			OperationType::ALL.iter().all(|&type_| counts.stats.users(type_) <= unpruned.counts[prefix].stats.users(type_))
+		});
+
+	let counted = pruned.counts.len() <= max_prefixes && bounded && counts_match_windows(&pruned);
+	expire_all(&mut pruned, history.end());
+	counted && pruned.counts.len() == 0
+}
+
+/// Checks that with any mix of the settings that change which prefixes entries are counted for and what’s kept about them, each prefix counts exactly the entries counted for it, and expiring every entry leaves nothing behind.
+#[quickcheck]
+fn entries_expire_completely(history: History, split_threshold: Option<u8>, max_prefixes: Option<u8>, network_cap: Option<u8>, quarantine: Option<u8>, per_prefix: Option<u8>, flags: u8) -> bool {
+	let flag = |bit: u8| flags & (1 << bit) != 0;
+
+	let mut tree = SpamTree::new(TreeSettings {
+		split_threshold: split_threshold.map(|threshold| 1 + u32::from(threshold % 4)),
+		max_prefixes: max_prefixes.map(|max| 10 + usize::from(max % 64)),
+		network_cap: network_cap.map(|cap| u32::from(cap % 4)),
+		spam_quarantine: quarantine.map(|minimum| u32::from(minimum % 4)),
+		entries_per_user_prefix: per_prefix.map(|limit| u16::from(limit % 4)),
+		decay_half_life: if flag(0) { Some(CoarseDuration::new(24)) } else { None },
+		velocity_window: if flag(1) { Some(CoarseDuration::<Minutes>::new(60)) } else { None },
+		distinct_users: flag(2),
+		allocation_boundaries_only: flag(3),
+		trust_address_expiry: if flag(4) { Some(CoarseDuration::new(24 * 7)) } else { None },
+		truncate_bits: if flag(5) { Some(64) } else { None },
+		..TreeSettings::DEFAULT
+	});
+	history.apply(&mut tree);
+	let counted = counts_match_windows(&tree);
+
+	expire_all(&mut tree, history.end());
+
+	counted
+		&& tree.counts.len() == 0
+		&& tree.distinct_bytes == 0
+		&& tree.network_counts.is_empty()
+		&& tree.users.is_empty()
+		&& tree.user_prefixes.is_empty()
+		&& tree.decay.as_ref().map_or(true, |decay| decay.estimated_bytes() == 0)
+}
+
+/// Checks that each prefix’s distinct users are the users with entries still counting toward them that were counted for it, and its spam reporters the ones with entries other than trust.
+#[quickcheck]
+fn distinct_users_match_user_window(history: History, quarantine: Option<u8>) -> bool {
+	let mut tree = SpamTree::new(TreeSettings {
+		distinct_users: true,
+		spam_quarantine: quarantine.map(|minimum| u32::from(minimum % 4)),
+		..TreeSettings::DEFAULT
+	});
+	history.apply(&mut tree);
+
+	tree.counts.iter().all(|(prefix, counts)| {
+		let reporters = |trust: bool| {
+			tree.user_window.iter()
+				.filter(|((Operation(type_, address, _), counted), _)| {
+					(trust || !type_.is_trust()) && counted.levels.includes(prefix.bits()) && prefix.is_prefix_of(address) && counted.includes(counts)
+				})
+				.map(|((Operation(_, _, user), _), _)| *user)
+				.collect::<HashSet<User>>()
+				.len() as u32
+		};
+
+		counts.users.as_ref().map_or(false, |users| users.count() == reporters(true))
+			&& counts.spam_reporters.as_ref().map_or(true, |spam_reporters| spam_reporters.count() == reporters(false))
+	})
+}
+
+/// Checks that queries only get counts from prefixes with enough distinct users, and leave out reports other than trust from prefixes with too few spam reporters.
+#[quickcheck]
+fn queries_respect_distinct_minimums(history: History, min_distinct: u8, quarantine: u8) -> bool {
+	let (min_distinct, quarantine) = (u32::from(min_distinct % 4), u32::from(quarantine % 4));
+	let now = history.end();
+	let mut tree = SpamTree::new(TreeSettings {
+		distinct_users: true,
+		min_distinct_users: Some(min_distinct),
+		spam_quarantine: Some(quarantine),
+		..TreeSettings::DEFAULT
+	});
+	history.apply(&mut tree);
+
+	history.addresses().iter().all(|address| {
+		let result = tree.query(address, now);
+
+		match tree.counts.get(&address.prefix(result.prefix_bits)) {
+			Some(counts) => {
+				let spam_reporters = counts.spam_reporters.as_ref().unwrap().count();
+				let hidden = OperationType::ALL.iter().all(|&type_| type_.is_trust() || result.stats.users(type_) == 0);
+				counts.users.as_ref().unwrap().count() >= min_distinct && (spam_reporters >= quarantine || hidden)
+			}
+			None => result.prefix_bits == 0,
+		}
+	})
+}
+
+/// Checks that caching empty results doesn’t change any result, as entries are added and expire between queries.
+#[quickcheck]
+fn empty_cache_does_not_change_results(history: History, others: Vec<Address>) -> bool {
+	let mut cached = SpamTree::new(TreeSettings { empty_cache_ttl: Some(Duration::from_secs(3600)), ..TreeSettings::DEFAULT });
+	let mut uncached = SpamTree::new(TreeSettings::DEFAULT);
+	let mut addresses = history.addresses();
+	addresses.extend(others);
+
+	history.0.iter().all(|(operation, time)| {
+		History(vec![(operation.clone(), *time)]).apply(&mut cached);
+		History(vec![(operation.clone(), *time)]).apply(&mut uncached);
+		addresses.iter().all(|address| same_result(&cached.query(address, *time), &uncached.query(address, *time)))
+	})
+}
+
+/// Checks that no user has more entries counting toward their limit in one user prefix than it allows.
+#[quickcheck]
+fn user_prefix_limits_cap_entries(history: History, limit: u8) -> bool {
+	let limit = usize::from(limit % 4);
+	let now = history.end();
+	let mut tree = SpamTree::new(TreeSettings { entries_per_user_prefix: Some(limit as u16), ..TreeSettings::DEFAULT });
+	history.apply(&mut tree);
+
+	(0..USERS).all(|user| {
+		let mut per_prefix = HashMap::new();
+
+		for entry in tree.user_entries(User(user), now) {
+			*per_prefix.entry(tree.settings.user_prefix(&entry.address)).or_insert(0) += 1;
+		}
+
+		per_prefix.values().all(|&count| count <= limit)
+	})
+}
+
+/// Checks that entries’ decayed weights are never more than the number of them, and never negative.
+#[quickcheck]
+fn decayed_weights_stay_within_counts(history: History, half_life: u8) -> bool {
+	let now = history.end();
+	let mut tree = SpamTree::new(TreeSettings { decay_half_life: Some(CoarseDuration::new(1 + u16::from(half_life))), ..TreeSettings::DEFAULT });
+	history.apply(&mut tree);
+
+	history.addresses().iter().all(|address| {
+		let counts = tree.query(address, now).stats;
+		let weights = tree.query_weights(address, now).weights;
+
+		OperationType::ALL.iter().zip(weights.0.iter())
+			.all(|(&type_, weight)| (0.0..=f64::from(counts.users(type_)) + 1e-9).contains(weight))
+	})
+}
+
+/// Checks that an override decides the result for every address within its prefix, and removing it gives the results from the counts again.
+#[quickcheck]
+fn overrides_take_precedence(history: History, bits: u8, verdict: u8) -> bool {
+	let now = history.end();
+	let verdict = Verdict::from_code(1 + verdict % 3).unwrap();
+	let mut tree = SpamTree::new(TreeSettings::DEFAULT);
+	let mut plain = SpamTree::new(TreeSettings::DEFAULT);
+	history.apply(&mut tree);
+	history.apply(&mut plain);
+
+	let addresses = history.addresses();
+	let prefix = match addresses.first() {
+		Some(address) => address.prefix(bits % (ADDRESS_BITS + 1)),
+		None => return true,
+	};
+
+	tree.overrides_mut().set(prefix.clone(), Some(verdict));
+
+	let overridden =
+		addresses.iter().all(|address| {
+			let result = tree.query(address, now);
+
+			if prefix.is_prefix_of(address) {
+				result.verdict == Some(verdict) && result.prefix_bits == prefix.bits()
+			} else {
+				same_result(&result, &plain.query(address, now))
+			}
+		});
+
+	tree.overrides_mut().set(prefix, None);
+	overridden && addresses.iter().all(|address| same_result(&tree.query(address, now), &plain.query(address, now)))
+}
+
+/// Checks that operations from before the latest time the tree has been used at count as happening at that time, so windows stay in order and entries still expire completely.
+#[quickcheck]
+fn earlier_times_are_clamped(history: History, swaps: Vec<(u8, u8)>) -> bool {
+	let mut times: Vec<CoarseSystemTime> = history.0.iter().map(|&(_, time)| time).collect();
+
+	if !times.is_empty() {
+		for (a, b) in swaps {
+			let len = times.len();
+			times.swap(usize::from(a) % len, usize::from(b) % len);
+		}
+	}
+
+	let mut tree = SpamTree::new(TreeSettings::DEFAULT);
+	let mut latest = None;
+	let mut clamped = 0;
+
+	for ((operation, _), &time) in history.0.iter().zip(&times) {
+		let accepted = History(vec![(operation.clone(), time)]).apply(&mut tree).len() == 1;
+
+		if let (TreeOperation::Perform(_), true, Some(latest)) = (operation, accepted, latest) {
+			if time < latest {
+				clamped += 1;
+			}
+		}
+
+		latest = latest.max(Some(time));
+	}
+
+	let window_times: Vec<CoarseSystemTime> = tree.user_window.iter().map(|(_, time)| time).collect();
+	let ordered = window_times.windows(2).all(|pair| pair[0] <= pair[1]);
+	let counted = tree.clamped == clamped && ordered && counts_match_windows(&tree);
+
+	expire_all(&mut tree, latest.unwrap_or(history.end()));
+	counted && tree.counts.len() == 0
+}
@@ -0,0 +1,43 @@
+/// A setting that can be changed while the daemon is running, over the admin socket, taking effect with the next request.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Tunable {
+	IdleTimeout,
+	SpamPrior,
+	TrustedPrior,
+	MinDistinctUsers,
+	SpamQuarantine,
+	SlowRequestThreshold,
+	SlowRequestSample,
+	DegradedAfter,
+}
+
+impl Tunable {
+	pub const ALL: [Self; 8] = [
+		Self::IdleTimeout,
+		Self::SpamPrior,
+		Self::TrustedPrior,
+		Self::MinDistinctUsers,
+		Self::SpamQuarantine,
+		Self::SlowRequestThreshold,
+		Self::SlowRequestSample,
+		Self::DegradedAfter,
+	];
+
+	/// Gets the name of the tunable, which is the name of its command-line option.
+	pub fn name(self) -> &'static str {
+		match self {
+			Self::IdleTimeout => "idle-timeout",
+			Self::SpamPrior => "spam-prior",
+			Self::TrustedPrior => "trusted-prior",
+			Self::MinDistinctUsers => "min-distinct-users",
+			Self::SpamQuarantine => "spam-quarantine",
+			Self::SlowRequestThreshold => "slow-request-threshold",
+			Self::SlowRequestSample => "slow-request-sample",
+			Self::DegradedAfter => "degraded-after",
+		}
+	}
+
+	pub fn from_name(name: &str) -> Option<Self> {
+		Self::ALL.iter().copied().find(|tunable| tunable.name() == name)
+	}
+}
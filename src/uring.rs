@@ -0,0 +1,159 @@
+use io_uring::{IoUring, opcode, types};
+use std::fs::File;
+use std::io::{self, ErrorKind, Seek, SeekFrom, Write};
+use std::mem;
+use std::os::unix::fs::FileExt;
+use std::os::unix::io::AsRawFd;
+use tracing::{error, warn};
+
+/// How much to buffer before submitting a write, the same as `BufWriter`’s default.
+const BUFFER_CAPACITY: usize = 8 * 1024;
+
+/// A buffered file writer that submits writes through io_uring instead of making `write` calls, so a full buffer doesn’t block the event loop. Only one write is in flight at a time, so writes reach the file in order; data written while one is in flight is buffered until it completes.
+pub struct UringWriter {
+	file: File,
+	ring: IoUring,
+
+	/// The offset in the file where the data in flight starts.
+	offset: u64,
+
+	buffer: Vec<u8>,
+
+	/// Data submitted to the kernel, and how much of it has been written so far. It can’t be touched until the write completes.
+	in_flight: Option<(Vec<u8>, usize)>,
+}
+
+impl UringWriter {
+	/// Creates a writer that writes from the file’s current position onwards.
+	pub fn new(mut file: File) -> io::Result<Self> {
+		let offset = file.seek(SeekFrom::Current(0))?;
+
+		Ok(Self {
+			file,
+			ring: IoUring::new(4)?,
+			offset,
+			buffer: Vec::with_capacity(BUFFER_CAPACITY),
+			in_flight: None,
+		})
+	}
+
+	/// Submits a write of the rest of the data in flight.
+	fn submit_in_flight(&mut self) -> io::Result<()> {
+		let (data, written) = self.in_flight.as_ref().unwrap();
+		let rest = &data[*written..];
+
+		let entry =
+			opcode::Write::new(types::Fd(self.file.as_raw_fd()), rest.as_ptr(), rest.len() as u32)
+				.offset((self.offset + *written as u64) as libc::off_t)
+				.build();
+
+		// Safety: the data stays in `in_flight`, unmodified, until the write completes.
+		unsafe {
+			self.ring.submission().push(&entry)
+				.map_err(|_| io::Error::new(ErrorKind::Other, "io_uring submission queue is full"))?;
+		}
+
+		self.ring.submit()?;
+		Ok(())
+	}
+
+	/// Moves the buffer in flight and submits it. There can’t already be a write in flight.
+	fn submit_buffer(&mut self) -> io::Result<()> {
+		debug_assert!(self.in_flight.is_none());
+		let data = mem::replace(&mut self.buffer, Vec::with_capacity(BUFFER_CAPACITY));
+		self.in_flight = Some((data, 0));
+		self.submit_in_flight()
+	}
+
+	/// Handles the completion of the write in flight if there is one, waiting for it if `wait` is set. Short writes are continued, interrupted ones are resubmitted, and failed ones are retried with a blocking write; if that fails too, the unwritten data goes back in the buffer to be written by the next flush.
+	fn complete(&mut self, wait: bool) -> io::Result<()> {
+		while self.in_flight.is_some() {
+			if wait {
+				self.ring.submit_and_wait(1)?;
+			}
+
+			let result =
+				match self.ring.completion().next() {
+					Some(entry) => entry.result(),
+					None => return Ok(()),
+				};
+
+			if result == -libc::EINTR || result == -libc::EAGAIN {
+				self.submit_in_flight()?;
+				continue;
+			}
+
+			if result <= 0 {
+				let err =
+					if result == 0 {
+						io::Error::new(ErrorKind::WriteZero, "io_uring write made no progress")
+					} else {
+						io::Error::from_raw_os_error(-result)
+					};
+
+				warn!(error = %err, "io_uring write failed; retrying with a blocking write");
+
+				let (data, written) = self.in_flight.take().unwrap();
+				let rest = &data[written..];
+				self.offset += written as u64;
+
+				if let Err(err) = self.file.write_all_at(rest, self.offset) {
+					self.buffer.splice(0..0, rest.iter().copied());
+					return Err(err);
+				}
+
+				self.offset += rest.len() as u64;
+				continue;
+			}
+
+			let (data, written) = self.in_flight.as_mut().unwrap();
+			*written += result as usize;
+
+			if *written == data.len() {
+				self.offset += data.len() as u64;
+				self.in_flight = None;
+			} else {
+				self.submit_in_flight()?;
+			}
+		}
+
+		Ok(())
+	}
+}
+
+impl Write for UringWriter {
+	fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+		self.buffer.extend_from_slice(data);
+
+		if self.buffer.len() >= BUFFER_CAPACITY {
+			self.complete(false)?;
+
+			if self.in_flight.is_none() {
+				self.submit_buffer()?;
+			}
+		}
+
+		Ok(data.len())
+	}
+
+	fn flush(&mut self) -> io::Result<()> {
+		loop {
+			self.complete(true)?;
+
+			if self.buffer.is_empty() {
+				return Ok(());
+			}
+
+			self.submit_buffer()?;
+		}
+	}
+}
+
+impl Drop for UringWriter {
+	fn drop(&mut self) {
+		// Also waits for the write in flight, which has to finish before its data is freed.
+		if let Err(err) = self.flush() {
+			error!(error = %err, "failed to flush operation log");
+		}
+	}
+}